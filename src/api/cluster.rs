@@ -0,0 +1,307 @@
+use crate::api::dto::{AddMemberDto, Consistency};
+use crate::api::schedule::{self, GetScheduleQueryDto, ListSchedulesQueryDto};
+use crate::app_context::ApiContext;
+use crate::cluster::{Cluster, ClusterError, ScheduleData};
+use crate::config;
+use crate::scheduler::schedule_actor::TriggerNow;
+use crate::scheduler::supervisor::RunForwardedTrigger;
+use actix_web::error::{
+    ErrorBadGateway, ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound,
+    ErrorUnauthorized, InternalError,
+};
+use actix_web::http::header::{AUTHORIZATION, RETRY_AFTER};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse, Responder};
+use async_raft::raft::{AppendEntriesRequest, InstallSnapshotRequest, VoteRequest};
+use std::sync::Arc;
+
+/// Membership management, served on the public API.
+pub(crate) fn admin_endpoints() -> actix_web::Scope {
+    web::scope("/cluster")
+        .service(status)
+        .service(add_member)
+        .service(remove_member)
+        .service(step_down)
+}
+
+/// Endpoints called by other members, served on the cluster listener only.
+pub(crate) fn endpoints() -> actix_web::Scope {
+    web::scope("/cluster")
+        .service(node)
+        .service(rejoin)
+        .service(read_index)
+        .service(read_schedules)
+        .service(read_schedule)
+        .service(write)
+        .service(trigger)
+        .service(append_entries)
+        .service(vote)
+        .service(install_snapshot)
+}
+
+/// Header of `503` answers carrying the id of the leader, when one is known.
+pub(crate) const LEADER_HEADER: &str = "x-cluster-leader";
+/// Header of `503` answers carrying the cluster listener address of the leader.
+pub(crate) const LEADER_ADDRESS_HEADER: &str = "x-cluster-leader-address";
+
+/// Maps errors of cluster writes, reads and RPCs to responses. Errors of a cluster unable to
+/// answer for now are `503`, with a `Retry-After` of the election timeout and the leader as
+/// known to this node.
+pub(crate) fn write_error(cluster: &Cluster) -> impl Fn(ClusterError) -> actix_web::Error + '_ {
+    move |e| match e {
+        ClusterError::NoLeader
+        | ClusterError::NotLeader(_)
+        | ClusterError::Lagging(_)
+        | ClusterError::Unavailable(_) => {
+            let (_, election_timeout_max) = config::cluster::election_timeout();
+            let mut response = HttpResponse::ServiceUnavailable();
+            response.insert_header((RETRY_AFTER, election_timeout_max.div_ceil(1000).to_string()));
+            let leader = match &e {
+                ClusterError::NotLeader(Some(leader)) => Some(*leader),
+                _ => cluster.leader(),
+            };
+            if let Some(leader) = leader {
+                response.insert_header((LEADER_HEADER, leader.to_string()));
+                if let Some(address) = cluster.address(leader) {
+                    response.insert_header((LEADER_ADDRESS_HEADER, address));
+                }
+            }
+            let response = response.body(e.to_string());
+            InternalError::from_response(e, response).into()
+        }
+        ClusterError::Forward(_) => ErrorBadGateway(e),
+        ClusterError::Membership(_) => ErrorConflict(e),
+        ClusterError::UnknownNode(_) | ClusterError::Raft(_) => ErrorInternalServerError(e),
+    }
+}
+
+/// Returns the cluster of this node if the request comes from an authenticated member.
+fn member<'a>(ctx: &'a ApiContext, req: &HttpRequest) -> actix_web::Result<&'a Arc<Cluster>> {
+    let cluster = clustered(ctx)?;
+    let authorization = req.headers().get(AUTHORIZATION).map(|v| v.as_bytes());
+    if !cluster.authorized(authorization) {
+        return Err(ErrorUnauthorized("Invalid cluster secret"));
+    }
+    Ok(cluster)
+}
+
+/// Returns the cluster of this node if the request carries the cluster secret. Cluster
+/// administration is served on the public API, so it is refused without a configured secret.
+fn admin<'a>(ctx: &'a ApiContext, req: &HttpRequest) -> actix_web::Result<&'a Arc<Cluster>> {
+    if !clustered(ctx)?.secured() {
+        return Err(ErrorForbidden(
+            "Cluster administration requires SCHEDULERS_CLUSTER_SECRET",
+        ));
+    }
+    member(ctx, req)
+}
+
+fn clustered(ctx: &ApiContext) -> actix_web::Result<&Arc<Cluster>> {
+    ctx.cluster
+        .as_ref()
+        .ok_or_else(|| ErrorNotFound("This node is not clustered"))
+}
+
+/// Raft state of this node and replication state of the other members.
+#[get("/status")]
+pub async fn status(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(admin(&ctx, &http)?.status().await))
+}
+
+/// Adds a node to the cluster, answers once it caught up with the log and votes. Leader only.
+#[post("/members")]
+pub async fn add_member(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<AddMemberDto>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = admin(&ctx, &http)?;
+    let req = req.into_inner();
+    cluster
+        .add_member(req.node_id, req.address.trim_end_matches('/').to_string())
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(web::Json(cluster.status().await))
+}
+
+/// Removes a member from the cluster. Leader only.
+#[delete("/members/{id}")]
+pub async fn remove_member(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    id: web::Path<u64>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = admin(&ctx, &http)?;
+    cluster
+        .remove_member(id.into_inner())
+        .await
+        .map_err(|e| match e {
+            ClusterError::UnknownNode(_) => ErrorNotFound(e),
+            e => write_error(cluster)(e),
+        })?;
+    Ok(web::Json(cluster.status().await))
+}
+
+/// Hands leadership over to another member, which this node rejoins as a follower. Leader
+/// only.
+#[post("/leader/step-down")]
+pub async fn step_down(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let cluster = admin(&ctx, &http)?;
+    cluster.step_down().await.map_err(write_error(cluster))?;
+    Ok(web::Json(cluster.status().await))
+}
+
+/// Adds back a leader that stepped down, answers once it votes again. Leader only.
+#[post("/members")]
+pub async fn rejoin(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<AddMemberDto>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = admin(&ctx, &http)?;
+    let req = req.into_inner();
+    cluster
+        .add_member(req.node_id, req.address)
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Raft state of this node, queried by the leader for the cluster status.
+#[get("/node")]
+pub async fn node(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(member(&ctx, &http)?.node_status()))
+}
+
+/// Confirms this node leads the cluster, for a linearizable read on another member. Answers
+/// the applied index the member waits for.
+#[get("/read-index")]
+pub async fn read_index(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let index = cluster.read_index().await.map_err(write_error(cluster))?;
+    Ok(web::Json(index))
+}
+
+/// Reads with `leader` consistency sent by other members, answered on the leader only.
+#[get("/read/schedules")]
+pub async fn read_schedules(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    query: web::Query<ListSchedulesQueryDto>,
+) -> actix_web::Result<HttpResponse> {
+    leading(&ctx, &http)?;
+    let local = schedule::list_schedules(&ctx, &query);
+    schedule::read(&ctx, &http, Consistency::Stale, local).await
+}
+
+#[get("/read/schedules/{id}")]
+pub async fn read_schedule(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Path<GetScheduleQueryDto>,
+) -> actix_web::Result<HttpResponse> {
+    leading(&ctx, &http)?;
+    let local = schedule::find_schedule(&ctx, &req.id);
+    schedule::read(&ctx, &http, Consistency::Stale, local).await
+}
+
+/// Rejects reads forwarded to a member that doesn't lead the cluster anymore, rather than
+/// forwarding them again.
+fn leading(ctx: &ApiContext, http: &HttpRequest) -> actix_web::Result<()> {
+    let cluster = member(ctx, http)?;
+    match cluster.read_leader().map_err(write_error(cluster))? {
+        None => Ok(()),
+        leader => Err(write_error(cluster)(ClusterError::NotLeader(leader))),
+    }
+}
+
+/// Proposes a write forwarded by another member, answers on the leader only.
+#[post("/write")]
+pub async fn write(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<ScheduleData>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let response = cluster
+        .write_forwarded(req.into_inner())
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(web::Json(response))
+}
+
+/// Runs a manual trigger handed over by another member, answers `503` if this node doesn't
+/// own the shard of the schedule.
+#[post("/trigger/{id}")]
+pub async fn trigger(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    id: web::Path<String>,
+    req: web::Json<TriggerNow>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let id = id.into_inner();
+    let ran = ctx
+        .supervisor
+        .send(RunForwardedTrigger(id.clone(), req.into_inner()))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !ran {
+        let e = ClusterError::Unavailable(format!("node {} doesn't own {}", cluster.id(), id));
+        return Err(write_error(cluster)(e));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/append-entries")]
+pub async fn append_entries(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<AppendEntriesRequest<ScheduleData>>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let response = cluster
+        .append_entries(req.into_inner())
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(web::Json(response))
+}
+
+#[post("/vote")]
+pub async fn vote(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<VoteRequest>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let response = cluster
+        .vote(req.into_inner())
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(web::Json(response))
+}
+
+#[post("/install-snapshot")]
+pub async fn install_snapshot(
+    ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
+    req: web::Json<InstallSnapshotRequest>,
+) -> actix_web::Result<impl Responder> {
+    let cluster = member(&ctx, &http)?;
+    let response = cluster
+        .install_snapshot(req.into_inner())
+        .await
+        .map_err(write_error(cluster))?;
+    Ok(web::Json(response))
+}
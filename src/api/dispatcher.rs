@@ -0,0 +1,18 @@
+use crate::app_context::ApiContext;
+use actix_web::{get, web, Responder};
+use std::sync::Arc;
+
+pub(crate) fn endpoints() -> actix_web::Scope {
+    web::scope("/api/dispatcher").service(limits).service(queue)
+}
+
+#[get("/limits")]
+pub async fn limits(ctx: web::Data<Arc<ApiContext>>) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(ctx.dispatcher.limiter_status()))
+}
+
+/// Dispatch workers and queue wait times per priority class.
+#[get("/queue")]
+pub async fn queue(ctx: web::Data<Arc<ApiContext>>) -> actix_web::Result<impl Responder> {
+    Ok(web::Json(ctx.dispatcher.queue_status()))
+}
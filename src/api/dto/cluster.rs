@@ -0,0 +1,61 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterNodeDto {
+    pub node_id: u64,
+    /// Raft role of the node, e.g. `Leader` or `Follower`
+    pub state: String,
+    pub term: u64,
+    /// Current leader, if known
+    pub leader: Option<u64>,
+    /// Index of the last entry appended to the log
+    pub last_log_index: u64,
+    /// Index of the last entry applied to the schedules
+    pub last_applied: u64,
+    /// Shards whose schedules the node runs
+    pub owned_shards: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterStatusDto {
+    #[serde(flatten)]
+    pub node: ClusterNodeDto,
+    /// Other members, and nodes being added
+    pub members: Vec<ClusterMemberDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterMemberDto {
+    pub node_id: u64,
+    /// Address of its cluster listener, if known
+    pub address: Option<String>,
+    /// `false` while the node catches up with the log, before it's promoted
+    pub voter: bool,
+    /// Whether the node answered, the fields below are unset otherwise
+    pub reachable: bool,
+    pub state: Option<String>,
+    pub last_log_index: Option<u64>,
+    pub last_applied: Option<u64>,
+    pub owned_shards: Option<usize>,
+    /// Log entries the node is behind this one
+    pub lag: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AddMemberDto {
+    pub node_id: u64,
+    /// Address of the cluster listener of the node, e.g. `http://schedule-rs-3.schedule-rs:8081`
+    pub address: String,
+}
+
+/// Consistency of reads of replicated schedules in cluster mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consistency {
+    /// Reflects every write committed before the read, confirmed with the leader
+    Linearizable,
+    /// Served by the leader, without confirming it still leads the cluster
+    Leader,
+    /// Served by the node receiving the read, possibly behind the leader
+    Stale,
+}
@@ -0,0 +1,45 @@
+use crate::db::schema::Priority;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LimiterStatusDto {
+    /// Limiter group, e.g. `host:api.partner.com`
+    pub group: String,
+    /// Requests allowed per `per_seconds`
+    pub rate: u32,
+    /// Rate period in seconds
+    pub per_seconds: u64,
+    /// Maximum number of concurrent requests, if capped
+    pub concurrency: Option<usize>,
+    /// Due requests waiting for the limiter
+    pub queued: usize,
+    /// Requests currently in flight
+    pub in_flight: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DispatchQueueDto {
+    /// Maximum number of requests in flight
+    pub workers: usize,
+    /// State of each priority class, highest first
+    pub classes: Vec<DispatchQueueStatusDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DispatchQueueStatusDto {
+    pub priority: Priority,
+    /// Workers only this class and higher classes may use
+    pub reserved: usize,
+    /// Due requests waiting for a worker
+    pub queued: usize,
+    /// Requests currently in flight
+    pub in_flight: usize,
+    /// Requests dispatched since start
+    pub dispatched: u64,
+    /// Average time requests waited for a worker
+    pub wait_ms_avg: u64,
+    /// Longest time a request waited for a worker
+    pub wait_ms_max: u64,
+    /// Time the oldest queued request has been waiting
+    pub oldest_wait_ms: u64,
+}
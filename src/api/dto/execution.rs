@@ -0,0 +1,68 @@
+use crate::db::schema::{
+    ExecutionDocument, MatchesDocument, OutboundRequestDocument, ResponseDocument, ScheduleId,
+    UpstreamDocument,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionDto {
+    /// Unique identifier for execution
+    pub id: u64,
+    /// Executed schedule
+    pub schedule_id: ScheduleId,
+    /// Run number of the schedule
+    pub run: u64,
+    /// Attempt number within the run
+    pub attempt: u32,
+    /// Whether the run was triggered manually
+    pub manual: bool,
+    /// Instant the run was scheduled for
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    /// Started at
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Finished at
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    /// Response status code, if upstream responded
+    pub status_code: Option<u16>,
+    /// Response latency in milliseconds, if upstream responded
+    pub latency_ms: Option<u64>,
+    /// Whether the execution met the success criteria
+    pub succeeded: bool,
+    /// Error that prevented the request from completing, or failed success criteria
+    pub error: Option<String>,
+    /// Values matched by the success criteria
+    pub matches: Option<MatchesDocument>,
+    /// Captured upstream response, the body is available at
+    /// `/api/schedules/{schedule_id}/executions/{id}/body`
+    pub response: Option<ResponseDocument>,
+    /// Whether the request was only built and not sent
+    pub dry_run: bool,
+    /// Request as it would be sent, for dry runs
+    pub request: Option<OutboundRequestDocument>,
+    /// Execution that triggered this one, for follow-ups
+    pub upstream: Option<UpstreamDocument>,
+}
+
+impl From<ExecutionDocument> for ExecutionDto {
+    fn from(document: ExecutionDocument) -> Self {
+        Self {
+            id: document.id,
+            schedule_id: document.schedule_id,
+            run: document.run,
+            attempt: document.attempt,
+            manual: document.manual,
+            scheduled_at: document.scheduled_at,
+            started_at: document.started_at,
+            finished_at: document.finished_at,
+            status_code: document.status_code,
+            latency_ms: document.latency_ms,
+            succeeded: document.succeeded,
+            error: document.error,
+            matches: document.matches,
+            response: document.response,
+            dry_run: document.dry_run,
+            request: document.request,
+            upstream: document.upstream,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealthDto {
+    /// `ok` if the node is ready to serve, `unavailable` otherwise
+    pub status: String,
+    pub db: DbHealthDto,
+    pub scheduler: SchedulerHealthDto,
+    pub dispatcher: DispatcherHealthDto,
+    /// Raft state, if the node is clustered
+    pub cluster: Option<ClusterHealthDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DbHealthDto {
+    /// Database answers reads
+    pub open: bool,
+    /// Database was recovered from a previous run rather than created
+    pub recovered: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SchedulerHealthDto {
+    /// Actors of stored schedules and workflows were started, `false` if the supervisor
+    /// didn't answer in time
+    pub restored: bool,
+    pub schedule_actors: usize,
+    pub workflow_actors: usize,
+    /// Lag of the latest tick, from its scheduled instant to its first attempt
+    pub lag_ms_last: u64,
+    /// Average lag of ticks since start
+    pub lag_ms_avg: u64,
+    /// Runs recorded in the trigger ledger whose outcome isn't stored yet
+    pub triggers_in_flight: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DispatcherHealthDto {
+    /// Maximum number of requests in flight
+    pub workers: usize,
+    /// Requests currently in flight
+    pub in_flight: usize,
+    /// Due requests waiting for a dispatch worker
+    pub queued: usize,
+    /// Due requests waiting for a rate limiter
+    pub limiter_queued: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterHealthDto {
+    pub node_id: u64,
+    /// Raft role of the node, e.g. `Leader` or `Follower`
+    pub state: String,
+    pub term: u64,
+    /// Current leader, if known
+    pub leader: Option<u64>,
+    /// Index of the last entry applied to the schedules
+    pub last_applied: u64,
+}
@@ -1,17 +1,43 @@
+mod cluster;
+mod dispatcher;
+mod execution;
+mod health;
 mod schedule;
+mod workflow;
 
+pub use cluster::*;
+pub use dispatcher::*;
+pub use execution::*;
+pub use health::*;
 pub use schedule::*;
+pub use workflow::*;
 
+use crate::db::schema::ScheduleId;
 use serde::{Deserialize, Serialize};
 
+/// Run of a schedule in the trigger ledger, recorded before its request is sent.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TriggerDto {
-    /// Trigger id
+    /// Trigger id, sent as the `Idempotency-Key` header of the run's requests
     pub id: String,
     /// Schedule id
-    pub schedule_id: u64,
+    pub schedule_id: ScheduleId,
+    /// Run of the schedule, manual triggers share the run of the latest tick
+    pub run: u64,
     /// Schedule at
     pub at: chrono::DateTime<chrono::Utc>,
-    /// Node id
-    pub node_id: u64,
+    /// Node id, unset on standalone nodes
+    pub node_id: Option<u64>,
+    /// Manual trigger or follow-up, out of the schedule's ticks
+    pub manual: bool,
+    pub status: TriggerStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerStatus {
+    /// Request may have been sent, its outcome isn't recorded yet
+    InFlight,
+    /// Run finished, retries included
+    Completed,
 }
@@ -1,5 +1,6 @@
 use crate::db::schema::{
-    CallbackDocument, RequestHeaders, ScheduleDocument, ScheduleStatus, Tags,
+    CallbackDocument, CaptureDocument, FollowUpDocument, Priority, RequestBody, RequestHeaders,
+    ScheduleDocument, ScheduleId, ScheduleStatus, SuccessDocument, Tags,
 };
 use actix::Message;
 use serde::{Deserialize, Serialize};
@@ -19,13 +20,57 @@ impl From<CallbackDocument> for CallbackDto {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SuccessDto {
+    pub status: Option<Vec<String>>,
+    pub json_path: Option<String>,
+    pub body_regex: Option<String>,
+    pub max_latency_ms: Option<u64>,
+}
+
+impl From<SuccessDocument> for SuccessDto {
+    fn from(document: SuccessDocument) -> Self {
+        Self {
+            status: document.status,
+            json_path: document.json_path,
+            body_regex: document.body_regex,
+            max_latency_ms: document.max_latency_ms,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureDto {
+    pub body: Option<bool>,
+    pub headers: Option<Vec<String>>,
+    pub max_body_bytes: Option<usize>,
+}
+
+impl From<CaptureDocument> for CaptureDto {
+    fn from(document: CaptureDocument) -> Self {
+        Self {
+            body: document.body,
+            headers: document.headers,
+            max_body_bytes: document.max_body_bytes,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RequestDto {
     pub url: String,
     pub method: String,
     pub headers: Option<RequestHeaders>,
-    pub body: Option<String>,
+    pub body: Option<RequestBody>,
     pub retry: Option<Vec<u32>>,
+    pub rate_limit_key: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub max_redirects: Option<usize>,
+    pub ca_bundle: Option<String>,
+    pub client_identity: Option<String>,
+    pub insecure_skip_verify: Option<bool>,
+    pub template: Option<bool>,
 }
 
 impl From<crate::db::schema::RequestDocument> for RequestDto {
@@ -36,10 +81,49 @@ impl From<crate::db::schema::RequestDocument> for RequestDto {
             headers: document.headers,
             body: document.body,
             retry: document.retry,
+            rate_limit_key: document.rate_limit_key,
+            connect_timeout_ms: document.connect_timeout_ms,
+            timeout_ms: document.timeout_ms,
+            max_redirects: document.max_redirects,
+            ca_bundle: document.ca_bundle,
+            client_identity: document.client_identity,
+            insecure_skip_verify: document.insecure_skip_verify,
+            template: document.template,
         }
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FollowUpDto {
+    Schedule(ScheduleId),
+    Request { request: Box<RequestDto> },
+}
+
+impl From<FollowUpDocument> for FollowUpDto {
+    fn from(document: FollowUpDocument) -> Self {
+        match document {
+            FollowUpDocument::Schedule(id) => Self::Schedule(id),
+            FollowUpDocument::Request { request } => Self::Request {
+                request: Box::new((*request).into()),
+            },
+        }
+    }
+}
+
+/// Handling of a schedule created with the `dedup_key` of a pending schedule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnDuplicate {
+    /// Keep the pending schedule and return it
+    #[default]
+    Return,
+    /// Replace the pending schedule with the new one, debouncing the action
+    Replace,
+    /// Reject the new schedule
+    Reject,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ScheduleDto {
     /// Unique identifier for schedule
@@ -54,6 +138,26 @@ pub struct ScheduleDto {
     pub schedule_at: Option<String>,
     /// Callback to be executed after request is executed
     pub callback: Option<CallbackDto>,
+    /// Criteria deciding whether an execution succeeded
+    pub success: Option<SuccessDto>,
+    /// What to keep from upstream responses
+    pub capture: Option<CaptureDto>,
+    /// Record the request that would be sent instead of sending it
+    pub dry_run: Option<bool>,
+    /// Schedule ids or inline requests fired after a run succeeds
+    pub on_success: Option<Vec<FollowUpDto>>,
+    /// Schedule ids or inline requests fired after a run fails
+    pub on_failure: Option<Vec<FollowUpDto>>,
+    /// How long the schedule is kept once a one time run succeeded
+    pub retention: Option<String>,
+    /// Instant the schedule is removed, once it completed successfully
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Key identifying duplicates of the schedule while it is pending
+    pub dedup_key: Option<String>,
+    /// How long after creation duplicates are detected
+    pub dedup_window: Option<String>,
+    /// Dispatch priority class
+    pub priority: Option<Priority>,
     /// Created at
     pub created_at: chrono::DateTime<chrono::Utc>,
     /// Updated at
@@ -76,6 +180,20 @@ impl From<ScheduleDocument> for ScheduleDto {
             schedule: document.schedule,
             schedule_at: document.schedule_at,
             callback: document.callback.map(|callback| callback.into()),
+            success: document.success.map(|success| success.into()),
+            capture: document.capture.map(|capture| capture.into()),
+            dry_run: document.dry_run,
+            on_success: document
+                .on_success
+                .map(|f| f.into_iter().map(|f| f.into()).collect()),
+            on_failure: document
+                .on_failure
+                .map(|f| f.into_iter().map(|f| f.into()).collect()),
+            retention: document.retention,
+            expires_at: document.expires_at,
+            dedup_key: document.dedup_key,
+            dedup_window: document.dedup_window,
+            priority: document.priority,
             created_at: document.created_at,
             updated_at: document.updated_at,
             status: document.status,
@@ -96,8 +214,31 @@ pub struct CreateScheduleDto {
     pub schedule: Option<String>,
     /// Schedule in ISO 8601 format
     pub schedule_at: Option<String>,
+    /// Delay from now, e.g. `15m` or `2h30m`, resolved to `schedule_at` by the server
+    pub schedule_in: Option<String>,
+    /// Key identifying duplicates of the schedule while it is pending
+    pub dedup_key: Option<String>,
+    /// How long after creation duplicates are detected, e.g. `10m`,
+    /// defaults to `SCHEDULERS_DEDUP_WINDOW`
+    pub dedup_window: Option<String>,
+    /// What to do if a pending schedule with the same `dedup_key` exists, defaults to `return`
+    pub on_duplicate: Option<OnDuplicate>,
+    /// Dispatch priority class, `critical`, `high`, `normal` or `low`, defaults to `normal`
+    pub priority: Option<Priority>,
     /// Callback to be executed after request is executed
     pub callback: Option<CallbackDto>,
+    /// Criteria deciding whether an execution succeeded
+    pub success: Option<SuccessDto>,
+    /// What to keep from upstream responses
+    pub capture: Option<CaptureDto>,
+    /// Record the request that would be sent instead of sending it
+    pub dry_run: Option<bool>,
+    /// Schedule ids or inline requests fired after a run succeeds
+    pub on_success: Option<Vec<FollowUpDto>>,
+    /// Schedule ids or inline requests fired after a run fails
+    pub on_failure: Option<Vec<FollowUpDto>>,
+    /// How long the schedule is kept once a one time run succeeded, e.g. `1h`
+    pub retention: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Message)]
@@ -115,4 +256,26 @@ pub struct UpdateScheduleDto {
     pub schedule_at: Option<String>,
     /// Callback to be executed after request is executed
     pub callback: Option<CallbackDto>,
+    /// Criteria deciding whether an execution succeeded
+    pub success: Option<SuccessDto>,
+    /// What to keep from upstream responses
+    pub capture: Option<CaptureDto>,
+    /// Record the request that would be sent instead of sending it
+    pub dry_run: Option<bool>,
+    /// Schedule ids or inline requests fired after a run succeeds
+    pub on_success: Option<Vec<FollowUpDto>>,
+    /// Schedule ids or inline requests fired after a run fails
+    pub on_failure: Option<Vec<FollowUpDto>>,
+    /// How long the schedule is kept once a one time run succeeded, e.g. `1h`
+    pub retention: Option<String>,
+    /// Dispatch priority class
+    pub priority: Option<Priority>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TriggerScheduleDto {
+    /// Headers added to, or replacing, the schedule's headers for this run
+    pub headers: Option<RequestHeaders>,
+    /// Body replacing the schedule's body for this run
+    pub body: Option<RequestBody>,
 }
@@ -0,0 +1,114 @@
+use crate::api::dto::{RequestDto, SuccessDto};
+use crate::db::schema::{
+    ScheduleStatus, StepRunDocument, Tags, WorkflowDocument, WorkflowId, WorkflowRunDocument,
+    WorkflowRunStatus, WorkflowStepDocument,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowStepDto {
+    /// Name of the step, unique within the workflow
+    pub name: String,
+    /// Request sent by the step
+    pub request: RequestDto,
+    /// Steps that must succeed before this one runs
+    pub depends_on: Option<Vec<String>>,
+    /// Criteria deciding whether the step succeeded
+    pub success: Option<SuccessDto>,
+    /// Deadline of the step including retries, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+impl From<WorkflowStepDocument> for WorkflowStepDto {
+    fn from(document: WorkflowStepDocument) -> Self {
+        Self {
+            name: document.name,
+            request: document.request.into(),
+            depends_on: document.depends_on,
+            success: document.success.map(|success| success.into()),
+            timeout_ms: document.timeout_ms,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowDto {
+    /// Unique identifier for workflow
+    pub id: WorkflowId,
+    /// Optional tags to group workflows
+    pub tags: Option<Tags>,
+    /// Schedule in cron format
+    pub schedule: Option<String>,
+    /// Schedule in ISO 8601 format
+    pub schedule_at: Option<String>,
+    /// Steps of the workflow
+    pub steps: Vec<WorkflowStepDto>,
+    /// Created at
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Updated at
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// status, same lifecycle as schedules
+    pub status: ScheduleStatus,
+}
+
+impl From<WorkflowDocument> for WorkflowDto {
+    fn from(document: WorkflowDocument) -> Self {
+        Self {
+            id: document.id,
+            tags: document.tags,
+            schedule: document.schedule,
+            schedule_at: document.schedule_at,
+            steps: document.steps.into_iter().map(|s| s.into()).collect(),
+            created_at: document.created_at,
+            updated_at: document.updated_at,
+            status: document.status,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateWorkflowDto {
+    /// Unique identifier for workflow
+    pub id: WorkflowId,
+    /// Optional tags to group workflows
+    pub tags: Option<Tags>,
+    /// Schedule in cron format
+    pub schedule: Option<String>,
+    /// Schedule in ISO 8601 format
+    pub schedule_at: Option<String>,
+    /// Steps of the workflow
+    pub steps: Vec<WorkflowStepDto>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowRunDto {
+    /// Unique identifier for run
+    pub id: u64,
+    pub workflow_id: WorkflowId,
+    /// Run number of the workflow
+    pub run: u64,
+    /// Instant the run was scheduled for
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    /// Started at
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Finished at, once the run is no longer running
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: WorkflowRunStatus,
+    /// State of each step
+    pub steps: Vec<StepRunDocument>,
+}
+
+impl From<WorkflowRunDocument> for WorkflowRunDto {
+    fn from(document: WorkflowRunDocument) -> Self {
+        Self {
+            id: document.id,
+            workflow_id: document.workflow_id,
+            run: document.run,
+            scheduled_at: document.scheduled_at,
+            started_at: document.started_at,
+            finished_at: document.finished_at,
+            status: document.status,
+            steps: document.steps,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::api::dto::{
+    ClusterHealthDto, DbHealthDto, DispatcherHealthDto, HealthDto, SchedulerHealthDto,
+};
+use crate::app_context::ApiContext;
+use crate::metrics::metrics;
+use crate::scheduler::supervisor::GetStatus;
+use actix_web::{get, web, HttpResponse, Responder};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long the supervisor may take to answer before the node is considered not ready
+const SUPERVISOR_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub(crate) fn endpoints() -> actix_web::Scope {
+    web::scope("/health")
+        .service(liveness)
+        .service(readiness)
+        .service(details)
+}
+
+/// Answers as long as the HTTP server is responsive.
+#[get("/live")]
+pub async fn liveness() -> impl Responder {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Ready once the database answers, the supervisor restored stored schedules and, when
+/// clustered, a leader is known.
+#[get("/ready")]
+pub async fn readiness(ctx: web::Data<Arc<ApiContext>>) -> impl Responder {
+    let health = check(&ctx).await;
+    let body = json!({ "status": health.status });
+    if health.status == "ok" {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// State of the database, the scheduler and the dispatcher, 503 if the node is not ready.
+#[get("")]
+pub async fn details(ctx: web::Data<Arc<ApiContext>>) -> impl Responder {
+    let health = check(&ctx).await;
+    if health.status == "ok" {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+async fn check(ctx: &ApiContext) -> HealthDto {
+    let db = DbHealthDto {
+        open: ctx.db.contains_key("health").is_ok(),
+        recovered: ctx.db.was_recovered(),
+    };
+    // a supervisor that doesn't answer in time is stuck, e.g. restoring or flooded
+    let status = tokio::time::timeout(SUPERVISOR_TIMEOUT, ctx.supervisor.send(GetStatus))
+        .await
+        .ok()
+        .and_then(|res| res.ok());
+    let lag = &metrics().lag;
+    let scheduler = SchedulerHealthDto {
+        restored: status.as_ref().map(|s| s.restored).unwrap_or(false),
+        schedule_actors: status.as_ref().map(|s| s.schedule_actors).unwrap_or(0),
+        workflow_actors: status.as_ref().map(|s| s.workflow_actors).unwrap_or(0),
+        lag_ms_last: (metrics().lag_last.get() * 1000.0) as u64,
+        lag_ms_avg: (lag.get_sample_sum() * 1000.0 / lag.get_sample_count().max(1) as f64) as u64,
+        triggers_in_flight: ctx.triggers.count_in_flight(),
+    };
+    let queue = ctx.dispatcher.queue_status();
+    let dispatcher = DispatcherHealthDto {
+        workers: queue.workers,
+        in_flight: queue.classes.iter().map(|c| c.in_flight).sum(),
+        queued: queue.classes.iter().map(|c| c.queued).sum(),
+        limiter_queued: ctx
+            .dispatcher
+            .limiter_status()
+            .iter()
+            .map(|l| l.queued)
+            .sum(),
+    };
+    let cluster = ctx.cluster.as_ref().map(|cluster| {
+        let metrics = cluster.metrics();
+        ClusterHealthDto {
+            node_id: cluster.id(),
+            state: format!("{:?}", metrics.state),
+            term: metrics.current_term,
+            leader: metrics.current_leader,
+            last_applied: metrics.last_applied,
+        }
+    });
+    // clustered nodes can't take writes without a leader
+    let ready = db.open
+        && scheduler.restored
+        && cluster.as_ref().map(|c| c.leader.is_some()).unwrap_or(true);
+    HealthDto {
+        status: if ready { "ok" } else { "unavailable" }.to_string(),
+        db,
+        scheduler,
+        dispatcher,
+        cluster,
+    }
+}
@@ -0,0 +1,31 @@
+use crate::app_context::ApiContext;
+use crate::metrics::metrics;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{get, web, HttpResponse};
+use std::sync::Arc;
+
+/// Metrics in the Prometheus text format. Gauges of queued state and the database size are
+/// refreshed on each scrape.
+#[get("/metrics")]
+pub async fn scrape(ctx: web::Data<Arc<ApiContext>>) -> actix_web::Result<HttpResponse> {
+    let m = metrics();
+    for class in ctx.dispatcher.queue_status().classes {
+        let priority = class.priority.as_str();
+        m.dispatch_queued
+            .with_label_values(&[priority])
+            .set(class.queued as i64);
+        m.dispatch_in_flight
+            .with_label_values(&[priority])
+            .set(class.in_flight as i64);
+    }
+    let db = ctx.db.clone();
+    let size = tokio::task::spawn_blocking(move || db.size_on_disk())
+        .await
+        .map_err(ErrorInternalServerError)?
+        .map_err(ErrorInternalServerError)?;
+    m.db_size.set(size as i64);
+    let body = m.encode().map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
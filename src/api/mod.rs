@@ -1,2 +1,7 @@
+pub(crate) mod cluster;
+pub(crate) mod dispatcher;
 pub(crate) mod dto;
+pub(crate) mod health;
+pub(crate) mod metrics;
 pub(crate) mod schedule;
+pub(crate) mod workflow;
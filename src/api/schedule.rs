@@ -1,8 +1,24 @@
-use crate::api::dto::{CreateScheduleDto, ScheduleDto};
+use crate::api::cluster::write_error;
+use crate::api::dto::{
+    Consistency, CreateScheduleDto, ExecutionDto, OnDuplicate, ScheduleDto, TriggerScheduleDto,
+    UpdateScheduleDto,
+};
 use crate::app_context::ApiContext;
-use actix_web::error::ErrorNotFound;
-use actix_web::{get, post, web, Responder};
-use serde::Deserialize;
+use crate::cluster::{ScheduleData, ScheduleEventResponse, APPLIED_INDEX_HEADER};
+use crate::db::schema::ExecutionDocument;
+use crate::db::Created;
+use crate::scheduler::dispatcher::encode_body;
+use crate::scheduler::schedule_actor::TriggerNow;
+use crate::scheduler::supervisor::{RestartSchedule, StartSchedule, StopSchedule, TriggerSchedule};
+use crate::scheduler::ticker::parse_duration;
+use crate::scheduler::{assertion, chain};
+use actix_web::error::{
+    ErrorBadGateway, ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound,
+};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::sync::Arc;
 
 pub(crate) fn endpoints() -> actix_web::Scope {
@@ -10,23 +26,35 @@ pub(crate) fn endpoints() -> actix_web::Scope {
         .service(index)
         .service(get_schedule)
         .service(create_schedule)
+        .service(update_schedule)
+        .service(delete_schedule)
+        .service(trigger_schedule)
+        .service(list_executions)
+        .service(get_execution_body)
 }
 
 #[derive(Clone, Deserialize)]
 pub struct ListSchedulesQueryDto {
     pub page: Option<usize>,
     pub after: Option<usize>,
+    /// Defaults to `stale`, listings are served by the node receiving them
+    pub consistency: Option<Consistency>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ReadQueryDto {
+    /// Defaults to `linearizable`, a schedule reflects the writes acknowledged before the read
+    pub consistency: Option<Consistency>,
 }
 
 #[get("/schedules")]
 pub async fn index(
     ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
     query: web::Query<ListSchedulesQueryDto>,
-) -> actix_web::Result<impl Responder> {
-    let page = query.page.unwrap_or(50);
-    let after = query.after.unwrap_or(0);
-    let response: Vec<ScheduleDto> = ctx.schedules.list(page, after).await?;
-    Ok(web::Json(response))
+) -> actix_web::Result<HttpResponse> {
+    let consistency = query.consistency.unwrap_or(Consistency::Stale);
+    read(&ctx, &http, consistency, list_schedules(&ctx, &query)).await
 }
 
 #[derive(Clone, Deserialize)]
@@ -37,13 +65,80 @@ pub struct GetScheduleQueryDto {
 #[get("/schedules/{id}")]
 pub async fn get_schedule(
     ctx: web::Data<Arc<ApiContext>>,
+    http: HttpRequest,
     req: web::Path<GetScheduleQueryDto>,
-) -> actix_web::Result<impl Responder> {
-    let response = ctx.schedules.get::<ScheduleDto>(req.id.clone()).await?;
-    match response {
-        Some(schedule) => Ok(web::Json(schedule)),
-        None => Err(ErrorNotFound("Schedule not found")),
-    }
+    query: web::Query<ReadQueryDto>,
+) -> actix_web::Result<HttpResponse> {
+    let consistency = query.consistency.unwrap_or(Consistency::Linearizable);
+    read(&ctx, &http, consistency, find_schedule(&ctx, &req.id)).await
+}
+
+pub(crate) async fn list_schedules(
+    ctx: &ApiContext,
+    query: &ListSchedulesQueryDto,
+) -> actix_web::Result<Vec<ScheduleDto>> {
+    let page = query.page.unwrap_or(50);
+    let after = query.after.unwrap_or(0);
+    Ok(ctx.schedules.list(page, after).await?)
+}
+
+pub(crate) async fn find_schedule(ctx: &ApiContext, id: &str) -> actix_web::Result<ScheduleDto> {
+    ctx.schedules
+        .get::<ScheduleDto>(id.to_string())
+        .await?
+        .ok_or_else(|| ErrorNotFound("Schedule not found"))
+}
+
+/// Serves a read of replicated schedules with the requested consistency, the index of the last
+/// log entry applied by the serving node in the `x-applied-index` header. Reads with `leader`
+/// consistency are sent to the leader, at the same path under `/cluster/read` of its cluster
+/// listener. Standalone nodes serve every read locally.
+pub(crate) async fn read<T: Serialize>(
+    ctx: &ApiContext,
+    http: &HttpRequest,
+    consistency: Consistency,
+    local: impl Future<Output = actix_web::Result<T>>,
+) -> actix_web::Result<HttpResponse> {
+    let cluster = match &ctx.cluster {
+        Some(cluster) => cluster,
+        None => return Ok(HttpResponse::Ok().json(local.await?)),
+    };
+    let applied = match consistency {
+        Consistency::Stale => cluster.applied_index(),
+        Consistency::Linearizable => cluster
+            .linearizable_read()
+            .await
+            .map_err(write_error(cluster))?,
+        Consistency::Leader => match cluster.read_leader().map_err(write_error(cluster))? {
+            None => cluster.applied_index(),
+            Some(leader) => {
+                let path = http
+                    .uri()
+                    .path_and_query()
+                    .map(|p| p.as_str())
+                    .unwrap_or_else(|| http.path());
+                let path = path.strip_prefix("/api").unwrap_or(path);
+                let forwarded = cluster
+                    .forward_read(leader, path)
+                    .await
+                    .map_err(write_error(cluster))?;
+                let status = StatusCode::from_u16(forwarded.status)
+                    .map_err(|e| ErrorBadGateway(e.to_string()))?;
+                let mut builder = HttpResponse::build(status);
+                if let Some(content_type) = forwarded.content_type {
+                    builder.content_type(content_type);
+                }
+                if let Some(applied) = forwarded.applied_index {
+                    builder.insert_header((APPLIED_INDEX_HEADER, applied.to_string()));
+                }
+                return Ok(builder.body(forwarded.body));
+            }
+        },
+    };
+    let response = local.await?;
+    Ok(HttpResponse::Ok()
+        .insert_header((APPLIED_INDEX_HEADER, applied.to_string()))
+        .json(response))
 }
 
 #[post("/schedules")]
@@ -51,6 +146,309 @@ pub async fn create_schedule(
     ctx: web::Data<Arc<ApiContext>>,
     req: web::Json<CreateScheduleDto>,
 ) -> actix_web::Result<impl Responder> {
-    let response = ctx.schedules.create_schedule(req.into_inner()).await?;
+    let mut req = req.into_inner();
+    if let Some(schedule_in) = req.schedule_in.take() {
+        if req.schedule.is_some() || req.schedule_at.is_some() {
+            return Err(ErrorBadRequest(
+                "schedule_in can't be combined with schedule or schedule_at",
+            ));
+        }
+        let delay = parse_duration(&schedule_in).map_err(ErrorBadRequest)?;
+        // resolved against the server clock, clients don't need synchronized clocks
+        let at = chrono::Utc::now()
+            .checked_add_signed(delay)
+            .ok_or_else(|| ErrorBadRequest("schedule_in is too far in the future"))?;
+        req.schedule_at = Some(at.to_rfc3339());
+    }
+    if let Some(retention) = &req.retention {
+        parse_duration(retention).map_err(ErrorBadRequest)?;
+    }
+    if let Some(window) = &req.dedup_window {
+        parse_duration(window).map_err(ErrorBadRequest)?;
+    }
+    let on_duplicate = req.on_duplicate.unwrap_or_default();
+    ctx.dispatcher
+        .check_request(&req.request)
+        .map_err(ErrorBadRequest)?;
+    if let Some(callback) = &req.callback {
+        ctx.dispatcher
+            .check_callback(callback)
+            .map_err(ErrorBadRequest)?;
+    }
+    if let Some(success) = &req.success {
+        assertion::check(success).map_err(ErrorBadRequest)?;
+    }
+    // cluster members check referenced schedules when they apply the write
+    match &ctx.cluster {
+        Some(_) => chain::check_requests(
+            req.on_success.as_ref(),
+            req.on_failure.as_ref(),
+            &ctx.dispatcher,
+        ),
+        None => {
+            chain::check(
+                &req.id,
+                req.on_success.as_ref(),
+                req.on_failure.as_ref(),
+                &ctx.schedules,
+                &ctx.dispatcher,
+            )
+            .await
+        }
+    }
+    .map_err(ErrorBadRequest)?;
+    let created = match &ctx.cluster {
+        // every member applies the write, the owner of the shard of the schedule starts its actor
+        Some(cluster) => match cluster
+            .write(ScheduleData::Create(req, chrono::Utc::now()))
+            .await
+            .map_err(write_error(cluster))?
+        {
+            ScheduleEventResponse::Created { schedule, replaced } => {
+                Created::New { schedule, replaced }
+            }
+            ScheduleEventResponse::Duplicate(existing) => Created::Duplicate(existing),
+            ScheduleEventResponse::Rejected(e) => return Err(ErrorBadRequest(e)),
+            other => return Err(unexpected("create", other)),
+        },
+        None => {
+            let created = ctx.schedules.create_schedule(req).await?;
+            if let Created::New { schedule, replaced } = &created {
+                if let Some(replaced) = replaced {
+                    ctx.supervisor.do_send(StopSchedule(replaced.clone()));
+                }
+                ctx.supervisor.do_send(StartSchedule(schedule.id.clone()));
+            }
+            created
+        }
+    };
+    match created {
+        Created::New { schedule, .. } => Ok(HttpResponse::Ok().json(schedule)),
+        Created::Duplicate(_) if on_duplicate == OnDuplicate::Reject => Err(ErrorConflict(
+            "A pending schedule with the same dedup_key exists",
+        )),
+        Created::Duplicate(existing) => Ok(HttpResponse::Ok()
+            .insert_header(("x-deduplicated", "true"))
+            .json(existing)),
+    }
+}
+
+/// Replaces the fields of the definition of a schedule that are set, keeping its runs, see
+/// [crate::db::ScheduleRepository::update_schedule]. Its actor restarts with the new definition.
+#[put("/schedules/{id}")]
+pub async fn update_schedule(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetScheduleQueryDto>,
+    params: web::Json<serde_json::Map<String, serde_json::Value>>,
+) -> actix_web::Result<impl Responder> {
+    // the id is taken from the path, the body may leave it out
+    let mut params = params.into_inner();
+    match params.get("id") {
+        Some(id) if id.as_str() != Some(req.id.as_str()) => {
+            return Err(ErrorBadRequest("id doesn't match the schedule updated"))
+        }
+        Some(_) => {}
+        None => {
+            params.insert("id".to_string(), serde_json::json!(req.id));
+        }
+    }
+    let params: UpdateScheduleDto =
+        serde_json::from_value(serde_json::Value::Object(params)).map_err(ErrorBadRequest)?;
+    if let Some(retention) = &params.retention {
+        parse_duration(retention).map_err(ErrorBadRequest)?;
+    }
+    ctx.dispatcher
+        .check_request(&params.request)
+        .map_err(ErrorBadRequest)?;
+    if let Some(callback) = &params.callback {
+        ctx.dispatcher
+            .check_callback(callback)
+            .map_err(ErrorBadRequest)?;
+    }
+    if let Some(success) = &params.success {
+        assertion::check(success).map_err(ErrorBadRequest)?;
+    }
+    match &ctx.cluster {
+        Some(_) => chain::check_requests(
+            params.on_success.as_ref(),
+            params.on_failure.as_ref(),
+            &ctx.dispatcher,
+        ),
+        None => {
+            chain::check(
+                &params.id,
+                params.on_success.as_ref(),
+                params.on_failure.as_ref(),
+                &ctx.schedules,
+                &ctx.dispatcher,
+            )
+            .await
+        }
+    }
+    .map_err(ErrorBadRequest)?;
+    let updated = match &ctx.cluster {
+        // the owner of the shard of the schedule restarts its actor once the write is applied
+        Some(cluster) => match cluster
+            .write(ScheduleData::Update(params, chrono::Utc::now()))
+            .await
+            .map_err(write_error(cluster))?
+        {
+            ScheduleEventResponse::Updated(schedule) => Some(schedule),
+            ScheduleEventResponse::NotFound(_) => None,
+            ScheduleEventResponse::Rejected(e) => return Err(ErrorBadRequest(e)),
+            other => return Err(unexpected("update", other)),
+        },
+        None => {
+            let updated = ctx
+                .schedules
+                .update_schedule(params, chrono::Utc::now())
+                .await?;
+            if let Some(schedule) = &updated {
+                ctx.supervisor.do_send(RestartSchedule(schedule.id.clone()));
+            }
+            updated
+        }
+    };
+    updated
+        .map(web::Json)
+        .ok_or_else(|| ErrorNotFound("Schedule not found"))
+}
+
+/// Removes a schedule with its executions, stopping its actor.
+#[delete("/schedules/{id}")]
+pub async fn delete_schedule(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetScheduleQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    let deleted = match &ctx.cluster {
+        Some(cluster) => match cluster
+            .write(ScheduleData::Delete(req.id.clone(), chrono::Utc::now()))
+            .await
+            .map_err(write_error(cluster))?
+        {
+            ScheduleEventResponse::Deleted(_) => true,
+            ScheduleEventResponse::NotFound(_) => false,
+            other => return Err(unexpected("delete", other)),
+        },
+        None => {
+            let deleted = ctx.schedules.delete(req.id.clone()).await?;
+            if deleted {
+                ctx.executions.delete(req.id.clone()).await?;
+                ctx.triggers.delete(req.id.clone()).await?;
+                ctx.supervisor.do_send(StopSchedule(req.id.clone()));
+            }
+            deleted
+        }
+    };
+    if deleted {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(ErrorNotFound("Schedule not found"))
+    }
+}
+
+/// Error of a committed write whose result doesn't match the write, e.g. from a member
+/// running another version.
+pub(crate) fn unexpected(write: &str, response: ScheduleEventResponse) -> actix_web::Error {
+    ErrorInternalServerError(format!("Unexpected result of {}: {:?}", write, response))
+}
+
+/// Runs the schedule now, without affecting its cadence. Result is recorded as a manual execution.
+#[post("/schedules/{id}/trigger")]
+pub async fn trigger_schedule(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetScheduleQueryDto>,
+    payload: web::Bytes,
+) -> actix_web::Result<impl Responder> {
+    let schedule = ctx
+        .schedules
+        .get::<ScheduleDto>(req.id.clone())
+        .await?
+        .ok_or_else(|| ErrorNotFound("Schedule not found"))?;
+    // overrides are optional, an empty body triggers the schedule as is
+    let overrides: TriggerScheduleDto = if payload.is_empty() {
+        TriggerScheduleDto::default()
+    } else {
+        serde_json::from_slice(&payload).map_err(ErrorBadRequest)?
+    };
+    if let Some(body) = &overrides.body {
+        encode_body(body).map_err(ErrorBadRequest)?;
+    }
+    // on cluster members, the member owning the shard of the schedule runs it
+    let triggered = ctx
+        .supervisor
+        .send(TriggerSchedule(
+            schedule.id.clone(),
+            TriggerNow {
+                headers: overrides.headers,
+                body: overrides.body,
+                upstream: None,
+            },
+        ))
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if let Err(e) = triggered {
+        return Err(match &ctx.cluster {
+            Some(cluster) => write_error(cluster)(e),
+            None => ErrorInternalServerError(e),
+        });
+    }
+    Ok(HttpResponse::Accepted().json(schedule))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ListExecutionsQueryDto {
+    pub page: Option<usize>,
+    pub after: Option<usize>,
+}
+
+/// Executions of a schedule, newest first. Executions are stored by the node that ran them, on
+/// cluster members only those this node ran are listed.
+#[get("/schedules/{id}/executions")]
+pub async fn list_executions(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetScheduleQueryDto>,
+    query: web::Query<ListExecutionsQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    let page = query.page.unwrap_or(50);
+    let after = query.after.unwrap_or(0);
+    let response: Vec<ExecutionDto> = ctx.executions.list(req.id.clone(), page, after).await?;
     Ok(web::Json(response))
 }
+
+#[derive(Clone, Deserialize)]
+pub struct GetExecutionQueryDto {
+    pub id: String,
+    pub execution_id: u64,
+}
+
+/// Raw captured response body, with the captured `Content-Type`. Like executions, only found on
+/// the node that ran it.
+#[get("/schedules/{id}/executions/{execution_id}/body")]
+pub async fn get_execution_body(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetExecutionQueryDto>,
+) -> actix_web::Result<HttpResponse> {
+    let execution: ExecutionDocument = ctx
+        .executions
+        .get(req.id.clone(), req.execution_id)
+        .await?
+        .ok_or_else(|| ErrorNotFound("Execution not found"))?;
+    let body = ctx
+        .executions
+        .get_body(req.id.clone(), req.execution_id)
+        .await?
+        .ok_or_else(|| ErrorNotFound("Response body was not captured"))?;
+    let response = execution.response;
+    let mut builder = HttpResponse::Ok();
+    if let Some(content_type) = response
+        .as_ref()
+        .and_then(|r| r.headers.get("content-type"))
+    {
+        builder.content_type(content_type.as_str());
+    }
+    if response.map(|r| r.body_truncated).unwrap_or(false) {
+        builder.insert_header(("x-body-truncated", "true"));
+    }
+    Ok(builder.body(body))
+}
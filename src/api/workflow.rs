@@ -0,0 +1,147 @@
+use crate::api::cluster::write_error;
+use crate::api::dto::{CreateWorkflowDto, WorkflowDto, WorkflowRunDto};
+use crate::api::schedule::unexpected;
+use crate::app_context::ApiContext;
+use crate::cluster::{ScheduleData, ScheduleEventResponse};
+use crate::db::schema::{WorkflowRunDocument, WorkflowRunStatus};
+use crate::scheduler::supervisor::{CancelWorkflowRun, StartWorkflow};
+use crate::scheduler::workflow::{self, cancel_run};
+use actix_web::error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound};
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub(crate) fn endpoints() -> actix_web::Scope {
+    web::scope("/api/workflows")
+        .service(index)
+        .service(get_workflow)
+        .service(create_workflow)
+        .service(list_runs)
+        .service(get_run)
+        .service(cancel_workflow_run)
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ListQueryDto {
+    pub page: Option<usize>,
+    pub after: Option<usize>,
+}
+
+#[get("")]
+pub async fn index(
+    ctx: web::Data<Arc<ApiContext>>,
+    query: web::Query<ListQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    let page = query.page.unwrap_or(50);
+    let after = query.after.unwrap_or(0);
+    let response: Vec<WorkflowDto> = ctx.workflows.list(page, after).await?;
+    Ok(web::Json(response))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GetWorkflowQueryDto {
+    pub id: String,
+}
+
+#[get("/{id}")]
+pub async fn get_workflow(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetWorkflowQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    match ctx.workflows.get::<WorkflowDto>(req.id.clone()).await? {
+        Some(workflow) => Ok(web::Json(workflow)),
+        None => Err(ErrorNotFound("Workflow not found")),
+    }
+}
+
+#[post("")]
+pub async fn create_workflow(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Json<CreateWorkflowDto>,
+) -> actix_web::Result<impl Responder> {
+    workflow::check(&req, &ctx.dispatcher).map_err(ErrorBadRequest)?;
+    let response = match &ctx.cluster {
+        // the owner of the shard of the workflow starts it once the write is applied
+        Some(cluster) => match cluster
+            .write(ScheduleData::CreateWorkflow(
+                req.into_inner(),
+                chrono::Utc::now(),
+            ))
+            .await
+            .map_err(write_error(cluster))?
+        {
+            ScheduleEventResponse::WorkflowCreated(workflow) => workflow,
+            other => return Err(unexpected("create workflow", other)),
+        },
+        None => {
+            let response = ctx.workflows.create(req.into_inner()).await?;
+            ctx.supervisor.do_send(StartWorkflow(response.id.clone()));
+            response
+        }
+    };
+    Ok(web::Json(response))
+}
+
+#[get("/{id}/runs")]
+pub async fn list_runs(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetWorkflowQueryDto>,
+    query: web::Query<ListQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    let page = query.page.unwrap_or(50);
+    let after = query.after.unwrap_or(0);
+    let response: Vec<WorkflowRunDto> =
+        ctx.workflows.list_runs(req.id.clone(), page, after).await?;
+    Ok(web::Json(response))
+}
+
+#[derive(Clone, Deserialize)]
+pub struct GetRunQueryDto {
+    pub id: String,
+    pub run_id: u64,
+}
+
+#[get("/{id}/runs/{run_id}")]
+pub async fn get_run(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetRunQueryDto>,
+) -> actix_web::Result<impl Responder> {
+    match ctx
+        .workflows
+        .get_run::<WorkflowRunDto>(req.id.clone(), req.run_id)
+        .await?
+    {
+        Some(run) => Ok(web::Json(run)),
+        None => Err(ErrorNotFound("Run not found")),
+    }
+}
+
+/// Cancels a running workflow run, in flight steps are abandoned and pending steps never start.
+#[post("/{id}/runs/{run_id}/cancel")]
+pub async fn cancel_workflow_run(
+    ctx: web::Data<Arc<ApiContext>>,
+    req: web::Path<GetRunQueryDto>,
+) -> actix_web::Result<HttpResponse> {
+    let mut run: WorkflowRunDocument = ctx
+        .workflows
+        .get_run(req.id.clone(), req.run_id)
+        .await?
+        .ok_or_else(|| ErrorNotFound("Run not found"))?;
+    if run.status != WorkflowRunStatus::Running {
+        return Err(ErrorConflict("Run is not running"));
+    }
+    let cancelled = ctx
+        .supervisor
+        .send(CancelWorkflowRun {
+            workflow_id: req.id.clone(),
+            run_id: req.run_id,
+        })
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !cancelled {
+        // no actor owns the run, e.g. it was interrupted by a restart
+        cancel_run(&mut run);
+        ctx.workflows.save_run(run).await?;
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
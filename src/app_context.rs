@@ -1,14 +1,26 @@
+use crate::cluster::Cluster;
+use crate::config;
 use crate::config::db::SledConfigExt;
-use crate::db::ScheduleRepository;
-use sled::{Db, Tree};
+use crate::db::{ExecutionRepository, ScheduleRepository, TriggerRepository, WorkflowRepository};
+use crate::metrics::metrics;
+use crate::scheduler::dispatcher::Dispatcher;
+use crate::scheduler::supervisor::ScheduleSupervisor;
+use actix::{Actor, Addr};
+use sled::Db;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{event, span, Level};
 
 pub struct ApiContext {
-    #[allow(dead_code)]
     pub db: Db,
-    pub schedules: ScheduleRepository,
-    #[allow(dead_code)]
-    pub triggers: Tree,
+    pub schedules: Arc<ScheduleRepository>,
+    pub executions: Arc<ExecutionRepository>,
+    pub workflows: Arc<WorkflowRepository>,
+    pub triggers: Arc<TriggerRepository>,
+    pub dispatcher: Arc<Dispatcher>,
+    pub supervisor: Addr<ScheduleSupervisor>,
+    /// Raft cluster this node is a member of, if clustered
+    pub cluster: Option<Arc<Cluster>>,
 }
 
 impl ApiContext {
@@ -21,13 +33,56 @@ impl ApiContext {
         } else {
             event!(Level::INFO, "Database created");
         }
-        let schedules = ScheduleRepository::new(&db);
-        let triggers = db.open_tree("triggers").unwrap();
+        if let Some(every) = config::db::flush_every_ms() {
+            let db = db.clone();
+            actix::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_millis(every));
+                loop {
+                    interval.tick().await;
+                    let started = Instant::now();
+                    if let Err(e) = db.flush_async().await {
+                        event!(Level::ERROR, error = %e, "Flushing database failed");
+                        continue;
+                    }
+                    metrics().db_flush.observe(started.elapsed().as_secs_f64());
+                }
+            });
+        }
+        let schedules = Arc::new(ScheduleRepository::new(&db));
+        let executions = Arc::new(ExecutionRepository::new(&db));
+        let workflows = Arc::new(WorkflowRepository::new(&db));
+        let triggers = Arc::new(TriggerRepository::new(&db));
+        let dispatcher = Arc::new(Dispatcher::from_env());
+        let supervisor = ScheduleSupervisor::new(
+            schedules.clone(),
+            executions.clone(),
+            workflows.clone(),
+            triggers.clone(),
+            dispatcher.clone(),
+        )
+        .start();
+        let cluster = config::cluster::node_id().map(|id| {
+            event!(Level::INFO, node_id = id, "Joining cluster");
+            Cluster::start(
+                id,
+                config::cluster::peers(),
+                db.clone(),
+                schedules.clone(),
+                executions.clone(),
+                triggers.clone(),
+                supervisor.clone(),
+            )
+        });
 
         Self {
             db,
             schedules,
+            executions,
+            workflows,
             triggers,
+            dispatcher,
+            supervisor,
+            cluster,
         }
     }
 
@@ -44,7 +99,7 @@ impl ApiContext {
 impl Drop for ApiContext {
     fn drop(&mut self) {
         self.shutdown().unwrap();
-        // Ensure all spans have been shipped to Jaeger.
+        // Ensure all spans have been shipped to the exporter.
         opentelemetry::global::shutdown_tracer_provider();
     }
 }
@@ -1,3 +1,683 @@
+use crate::api::dto::{AddMemberDto, ClusterMemberDto, ClusterNodeDto, ClusterStatusDto};
+use crate::config;
+use crate::db::schema::{RunDocument, ScheduleId, WorkflowId};
+use crate::db::{ExecutionRepository, ScheduleRepository, TriggerRepository};
+use crate::scheduler::schedule_actor::TriggerNow;
+use crate::scheduler::supervisor::{
+    JoinCluster, RestartSchedule, ScheduleSupervisor, SetOwnership, StartSchedule, StartWorkflow,
+    StopSchedule,
+};
+use actix::Addr;
+use async_raft::error::ClientReadError;
+use async_raft::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, ClientWriteRequest, InstallSnapshotRequest,
+    InstallSnapshotResponse, VoteRequest, VoteResponse,
+};
+use async_raft::{
+    ChangeConfigError, ClientWriteError, Config, InitializeError, NodeId, Raft, RaftError,
+    RaftMetrics, SnapshotPolicy, State,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{mpsc, watch};
+
 mod msg;
-mod store;
+mod network;
+mod shards;
 mod snapshot;
+mod store;
+
+pub use msg::{
+    ScheduleData, ScheduleEventResponse, TickClaim, WorkflowRunClaim, WorkflowRunFinished,
+};
+use network::ClusterNetwork;
+pub use network::{ForwardedRead, APPLIED_INDEX_HEADER};
+pub use shards::Ownership;
+use store::SchedulerRaftStorage;
+
+pub type SchedulerRaft =
+    Raft<ScheduleData, ScheduleEventResponse, ClusterNetwork, SchedulerRaftStorage>;
+
+#[derive(Debug, Error)]
+pub enum ClusterError {
+    #[error("no cluster leader is known")]
+    NoLeader,
+    #[error("this node is not the cluster leader")]
+    NotLeader(Option<NodeId>),
+    #[error("node {0} is not a cluster member")]
+    UnknownNode(NodeId),
+    /// No quorum committed the write in time, it may still be committed later
+    #[error("the cluster can't take writes: {0}")]
+    Unavailable(String),
+    #[error("forwarding to the leader failed: {0}")]
+    Forward(String),
+    #[error("this node didn't apply log index {0} in time")]
+    Lagging(u64),
+    #[error("membership change failed: {0}")]
+    Membership(ChangeConfigError),
+    #[error(transparent)]
+    Raft(#[from] RaftError),
+}
+
+impl From<ChangeConfigError> for ClusterError {
+    fn from(e: ChangeConfigError) -> Self {
+        match e {
+            ChangeConfigError::NodeNotLeader(leader) => ClusterError::NotLeader(leader),
+            ChangeConfigError::RaftError(e) => ClusterError::Raft(e),
+            e => ClusterError::Membership(e),
+        }
+    }
+}
+
+/// Member of the Raft cluster replicating schedules. Writes are proposed through the leader.
+/// Schedules are spread over shards the leader assigns to live members, each member running
+/// the schedules of its shards.
+pub struct Cluster {
+    id: NodeId,
+    raft: SchedulerRaft,
+    network: Arc<ClusterNetwork>,
+    storage: Arc<SchedulerRaftStorage>,
+    /// Shards owned by this node
+    ownership: watch::Receiver<Ownership>,
+    /// Nodes being added, catching up with the log before they're promoted
+    learners: Mutex<HashSet<NodeId>>,
+}
+
+impl Cluster {
+    pub fn start(
+        id: NodeId,
+        peers: HashMap<NodeId, String>,
+        db: sled::Db,
+        schedules: Arc<ScheduleRepository>,
+        executions: Arc<ExecutionRepository>,
+        triggers: Arc<TriggerRepository>,
+        supervisor: Addr<ScheduleSupervisor>,
+    ) -> Arc<Self> {
+        // nodes missing from the initial members join once added through the API
+        let founding = peers.contains_key(&id);
+        let (election_timeout_min, election_timeout_max) = config::cluster::election_timeout();
+        let config = Config::build("schedule-rs".to_string())
+            .heartbeat_interval(config::cluster::heartbeat_interval())
+            .election_timeout_min(election_timeout_min)
+            .election_timeout_max(election_timeout_max)
+            .snapshot_policy(SnapshotPolicy::LogsSinceLast(
+                config::cluster::snapshot_logs(),
+            ))
+            .validate()
+            .expect("Invalid Raft config");
+        let secret = config::cluster::secret();
+        if secret.is_none() {
+            log::warn!(
+                "SCHEDULERS_CLUSTER_SECRET not set, cluster requests are not authenticated and \
+                 cluster administration is disabled"
+            );
+        }
+        let members: HashSet<NodeId> = peers.keys().copied().collect();
+        let network = Arc::new(ClusterNetwork::new(peers, secret));
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let storage = Arc::new(SchedulerRaftStorage::new(
+            id,
+            db,
+            network.clone(),
+            schedules,
+            executions,
+            triggers,
+            events_tx,
+        ));
+        let ownership = storage.ownership();
+        let raft = Raft::new(id, Arc::new(config), network.clone(), storage.clone());
+        let cluster = Arc::new(Self {
+            id,
+            raft,
+            network,
+            storage,
+            ownership: ownership.clone(),
+            learners: Mutex::new(HashSet::new()),
+        });
+        // actors claim their ticks through the cluster, so it's known before they start
+        supervisor.do_send(JoinCluster(cluster.clone()));
+
+        if founding {
+            let c = cluster.clone();
+            actix::spawn(async move {
+                // every member proposes the same configuration, only a pristine cluster takes it
+                match c.raft.initialize(members).await {
+                    Ok(()) => log::info!("Cluster initialized"),
+                    Err(InitializeError::NotAllowed) => {
+                        log::debug!("Cluster already initialized")
+                    }
+                    Err(e) => log::error!("error initializing cluster: {}", e),
+                }
+            });
+        } else {
+            log::info!(
+                "Node {} is not in SCHEDULERS_CLUSTER_PEERS, waiting to be added to the cluster",
+                id
+            );
+        }
+        actix::spawn(follow_ownership(ownership, supervisor.clone()));
+        actix::spawn(run_events(events, supervisor));
+        actix::spawn(assign_shards(cluster.clone()));
+        cluster
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn metrics(&self) -> RaftMetrics {
+        self.raft.metrics().borrow().clone()
+    }
+
+    /// Raft state of this node.
+    pub fn node_status(&self) -> ClusterNodeDto {
+        let metrics = self.metrics();
+        ClusterNodeDto {
+            node_id: self.id,
+            state: format!("{:?}", metrics.state),
+            term: metrics.current_term,
+            leader: metrics.current_leader,
+            last_log_index: metrics.last_log_index,
+            last_applied: metrics.last_applied,
+            owned_shards: self.ownership.borrow().owned.len(),
+        }
+    }
+
+    /// Raft state of this node and of the other members, queried concurrently. Lag is
+    /// measured against the log of this node, so it's meaningful on the leader.
+    pub async fn status(&self) -> ClusterStatusDto {
+        let node = self.node_status();
+        let voters = self.metrics().membership_config.all_nodes();
+        let learners = self.learners.lock().unwrap().clone();
+        let mut queries = vec![];
+        for id in voters.iter().chain(learners.difference(&voters)).copied() {
+            if id == self.id {
+                continue;
+            }
+            let network = self.network.clone();
+            queries.push((
+                id,
+                voters.contains(&id),
+                tokio::spawn(async move { network.node_status(id).await }),
+            ));
+        }
+        let mut members = vec![];
+        for (id, voter, query) in queries {
+            let status = match query.await {
+                Ok(Ok(status)) => Some(status),
+                Ok(Err(e)) => {
+                    log::debug!("node {} didn't answer: {}", id, e);
+                    None
+                }
+                Err(_) => None,
+            };
+            members.push(ClusterMemberDto {
+                node_id: id,
+                address: self.network.address(id),
+                voter,
+                reachable: status.is_some(),
+                state: status.as_ref().map(|s| s.state.clone()),
+                last_log_index: status.as_ref().map(|s| s.last_log_index),
+                last_applied: status.as_ref().map(|s| s.last_applied),
+                owned_shards: status.as_ref().map(|s| s.owned_shards),
+                lag: status
+                    .as_ref()
+                    .map(|s| node.last_log_index.saturating_sub(s.last_log_index)),
+            });
+        }
+        members.sort_by_key(|m| m.node_id);
+        ClusterStatusDto { node, members }
+    }
+
+    /// Adds a node to the cluster, on the leader only. The address of the node is replicated
+    /// first, then the node catches up with the log as a non voter before it's promoted.
+    /// Returns once the node is a voting member.
+    pub async fn add_member(&self, id: NodeId, address: String) -> Result<(), ClusterError> {
+        self.ensure_leader()?;
+        self.write_forwarded(ScheduleData::SetPeer(id, address))
+            .await?;
+        self.learners.lock().unwrap().insert(id);
+        let added = self.promote(id).await;
+        self.learners.lock().unwrap().remove(&id);
+        added
+    }
+
+    async fn promote(&self, id: NodeId) -> Result<(), ClusterError> {
+        log::info!("Adding node {} to the cluster", id);
+        match self.raft.add_non_voter(id).await {
+            Ok(()) | Err(ChangeConfigError::Noop) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut members = self.metrics().membership_config.all_nodes();
+        if !members.insert(id) {
+            return Ok(());
+        }
+        self.raft.change_membership(members).await?;
+        log::info!("Node {} joined the cluster", id);
+        Ok(())
+    }
+
+    /// Removes a member from the cluster, on the leader only. The removed node should be
+    /// stopped afterwards, it doesn't take part in elections anymore.
+    pub async fn remove_member(&self, id: NodeId) -> Result<(), ClusterError> {
+        self.ensure_leader()?;
+        let mut members = self.metrics().membership_config.all_nodes();
+        if !members.remove(&id) {
+            return Err(ClusterError::UnknownNode(id));
+        }
+        log::info!("Removing node {} from the cluster", id);
+        self.raft.change_membership(members).await?;
+        Ok(())
+    }
+
+    /// Hands leadership over to another voter, on the leader only. async-raft can't transfer
+    /// leadership, so this is a membership change rather than a handoff: the leader removes
+    /// itself from the cluster, which makes it step down once the change is committed and lets
+    /// the other voters elect a leader among them. It then asks the new leader to add it back.
+    /// Until it votes again the cluster has one voter less, e.g. two voters out of three can't
+    /// lose another one. If it isn't added back within `SCHEDULERS_CLUSTER_JOIN_TIMEOUT_MS`, it
+    /// stays out of the cluster until added through the leader. Returns the new leader.
+    pub async fn step_down(&self) -> Result<NodeId, ClusterError> {
+        self.ensure_leader()?;
+        let mut members = self.metrics().membership_config.all_nodes();
+        members.remove(&self.id);
+        if members.is_empty() {
+            return Err(ClusterError::Membership(
+                ChangeConfigError::InoperableConfig,
+            ));
+        }
+        let address = self
+            .network
+            .address(self.id)
+            .ok_or(ClusterError::UnknownNode(self.id))?;
+        log::info!("Stepping down, removing node {} from the cluster", self.id);
+        self.raft.change_membership(members.clone()).await?;
+        let leader = self.elected(&members).await?;
+        log::info!("Node {} leads the cluster, rejoining", leader);
+        let member = AddMemberDto {
+            node_id: self.id,
+            address,
+        };
+        if let Err(e) = self.network.rejoin(leader, &member).await {
+            log::error!(
+                "Node {} couldn't rejoin the cluster, add it back through the leader: {}",
+                self.id,
+                e
+            );
+            return Err(e);
+        }
+        log::info!("Node {} rejoined the cluster", self.id);
+        Ok(leader)
+    }
+
+    /// Waits for the given members to elect a leader, asking them as this node doesn't hear
+    /// from the leader while it isn't a member.
+    async fn elected(&self, members: &HashSet<NodeId>) -> Result<NodeId, ClusterError> {
+        let (_, election_timeout_max) = config::cluster::election_timeout();
+        let deadline = Instant::now() + Duration::from_millis(election_timeout_max * 10);
+        while Instant::now() < deadline {
+            for id in members {
+                if let Ok(status) = self.network.node_status(*id).await {
+                    if let Some(leader) = status.leader.filter(|l| members.contains(l)) {
+                        return Ok(leader);
+                    }
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        Err(ClusterError::NoLeader)
+    }
+
+    /// Whether this node currently leads the cluster.
+    pub fn is_leader(&self) -> bool {
+        self.metrics().state == State::Leader
+    }
+
+    fn ensure_leader(&self) -> Result<(), ClusterError> {
+        let metrics = self.metrics();
+        if metrics.state == State::Leader {
+            Ok(())
+        } else {
+            Err(ClusterError::NotLeader(metrics.current_leader))
+        }
+    }
+
+    /// Records that this node fires the given run of a schedule, if it owns the shard of the
+    /// schedule and the run wasn't claimed before. Two members never both get
+    /// [ScheduleEventResponse::TickClaimed] for the same run.
+    pub async fn claim_tick(
+        &self,
+        schedule_id: ScheduleId,
+        run: u64,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        let token = match self.ownership.borrow().token(&schedule_id) {
+            Some(token) => token,
+            None => return Ok(ScheduleEventResponse::TickFenced(schedule_id)),
+        };
+        let claim = TickClaim {
+            schedule_id,
+            run,
+            at,
+            node_id: self.id,
+            token,
+        };
+        self.write(ScheduleData::ClaimTick(claim, chrono::Utc::now()))
+            .await
+    }
+
+    /// Records that this node starts the given run of a workflow, under the same rules as
+    /// [Cluster::claim_tick].
+    pub async fn claim_workflow_run(
+        &self,
+        workflow_id: WorkflowId,
+        run: u64,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        let token = match self.ownership.borrow().token(&workflow_id) {
+            Some(token) => token,
+            None => return Ok(ScheduleEventResponse::TickFenced(workflow_id)),
+        };
+        let claim = WorkflowRunClaim {
+            workflow_id,
+            run,
+            at,
+            node_id: self.id,
+            token,
+        };
+        self.write(ScheduleData::ClaimWorkflowRun(claim, chrono::Utc::now()))
+            .await
+    }
+
+    /// Stores the outcome of a workflow run, unless a later run was claimed meanwhile.
+    pub async fn finish_workflow_run(
+        &self,
+        finished: WorkflowRunFinished,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        self.write(ScheduleData::FinishWorkflowRun(
+            finished,
+            chrono::Utc::now(),
+        ))
+        .await
+    }
+
+    /// Hands a manual trigger to the member owning the shard of the schedule, which runs it
+    /// like its ticks. Fails with [ClusterError::Unavailable] while the shard has no other
+    /// owner, e.g. while it is handed over to this node.
+    pub async fn trigger(&self, id: ScheduleId, trigger: TriggerNow) -> Result<(), ClusterError> {
+        let owner = self
+            .storage
+            .owner_of(&id)
+            .map_err(|e| ClusterError::Raft(RaftError::RaftStorage(e)))?;
+        match owner {
+            Some(owner) if owner != self.id => self.network.trigger(owner, &id, &trigger).await,
+            _ => Err(ClusterError::Unavailable(format!(
+                "no other member owns the shard of {}",
+                id
+            ))),
+        }
+    }
+
+    /// Stores the outcome of a run on its schedule, see [ScheduleRepository::record_run].
+    pub async fn record_run(
+        &self,
+        run: RunDocument,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        self.write(ScheduleData::RecordRun(run, chrono::Utc::now()))
+            .await
+    }
+
+    /// Whether a request from another member is authenticated, see [ClusterNetwork::authorized].
+    pub fn authorized(&self, authorization: Option<&[u8]>) -> bool {
+        self.network.authorized(authorization)
+    }
+
+    /// Whether members share a secret, which cluster administration requires.
+    pub fn secured(&self) -> bool {
+        self.network.secured()
+    }
+
+    pub async fn append_entries(
+        &self,
+        rpc: AppendEntriesRequest<ScheduleData>,
+    ) -> Result<AppendEntriesResponse, ClusterError> {
+        Ok(self.raft.append_entries(rpc).await?)
+    }
+
+    pub async fn vote(&self, rpc: VoteRequest) -> Result<VoteResponse, ClusterError> {
+        Ok(self.raft.vote(rpc).await?)
+    }
+
+    pub async fn install_snapshot(
+        &self,
+        rpc: InstallSnapshotRequest,
+    ) -> Result<InstallSnapshotResponse, ClusterError> {
+        Ok(self.raft.install_snapshot(rpc).await?)
+    }
+
+    /// Index of the last log entry applied on this node, the state reads are served from.
+    pub fn applied_index(&self) -> u64 {
+        self.metrics().last_applied
+    }
+
+    /// Waits until this node applied every write committed before the call, so a read served
+    /// afterwards is linearizable. The leader confirms it still leads the cluster and answers
+    /// its applied index, which followers wait for. Returns the applied index of this node.
+    pub async fn linearizable_read(&self) -> Result<u64, ClusterError> {
+        let timeout = config::cluster::read_timeout();
+        let index = match tokio::time::timeout(timeout, self.raft.client_read()).await {
+            // no majority confirmed the leadership
+            Err(_) => return Err(ClusterError::NoLeader),
+            Ok(Ok(())) => return Ok(self.applied_index()),
+            Ok(Err(ClientReadError::ForwardToLeader(Some(leader)))) if leader != self.id => {
+                tokio::time::timeout(timeout, self.network.read_index(leader))
+                    .await
+                    .map_err(|_| ClusterError::Forward("read index timed out".to_string()))??
+            }
+            Ok(Err(ClientReadError::ForwardToLeader(_))) => return Err(ClusterError::NoLeader),
+            Ok(Err(ClientReadError::RaftError(e))) => return Err(e.into()),
+        };
+        let mut metrics = self.raft.metrics();
+        let applied = async {
+            loop {
+                let applied = metrics.borrow_and_update().last_applied;
+                if applied >= index {
+                    return Ok(applied);
+                }
+                if metrics.changed().await.is_err() {
+                    return Err(ClusterError::Raft(RaftError::ShuttingDown));
+                }
+            }
+        };
+        tokio::time::timeout(timeout, applied)
+            .await
+            .map_err(|_| ClusterError::Lagging(index))?
+    }
+
+    /// Confirms this node leads the cluster and answers its applied index, for a linearizable
+    /// read on another member. It is not forwarded again.
+    pub async fn read_index(&self) -> Result<u64, ClusterError> {
+        match self.raft.client_read().await {
+            Ok(()) => Ok(self.applied_index()),
+            Err(ClientReadError::ForwardToLeader(leader)) => Err(ClusterError::NotLeader(leader)),
+            Err(ClientReadError::RaftError(e)) => Err(e.into()),
+        }
+    }
+
+    /// Member reads with `leader` consistency go to, `None` if this node leads the cluster.
+    pub fn read_leader(&self) -> Result<Option<NodeId>, ClusterError> {
+        let metrics = self.metrics();
+        match metrics.current_leader {
+            Some(_) if metrics.state == State::Leader => Ok(None),
+            Some(leader) if leader != self.id => Ok(Some(leader)),
+            _ => Err(ClusterError::NoLeader),
+        }
+    }
+
+    /// Serves a read on the leader, see [ClusterNetwork::forward_read].
+    pub async fn forward_read(
+        &self,
+        leader: NodeId,
+        path: &str,
+    ) -> Result<ForwardedRead, ClusterError> {
+        self.network.forward_read(leader, path).await
+    }
+
+    /// Proposes a write, through the leader if this node is a follower. Returns once the entry
+    /// is committed and applied, or with [ClusterError::Unavailable] once
+    /// `SCHEDULERS_CLUSTER_WRITE_TIMEOUT_MS` elapsed.
+    pub async fn write(&self, data: ScheduleData) -> Result<ScheduleEventResponse, ClusterError> {
+        committed(async {
+            match self.raft.client_write(ClientWriteRequest::new(data)).await {
+                Ok(response) => Ok(response.data),
+                Err(ClientWriteError::ForwardToLeader(data, Some(leader))) if leader != self.id => {
+                    self.network.forward_write(leader, &data).await
+                }
+                Err(ClientWriteError::ForwardToLeader(_, _)) => Err(ClusterError::NoLeader),
+                Err(ClientWriteError::RaftError(e)) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Proposes a write forwarded by another member. It is not forwarded again, so writes
+    /// don't bounce between members during elections.
+    pub async fn write_forwarded(
+        &self,
+        data: ScheduleData,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        committed(async {
+            match self.raft.client_write(ClientWriteRequest::new(data)).await {
+                Ok(response) => Ok(response.data),
+                Err(ClientWriteError::ForwardToLeader(_, leader)) => {
+                    Err(ClusterError::NotLeader(leader))
+                }
+                Err(ClientWriteError::RaftError(e)) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    /// Leader as known to this node.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.metrics().current_leader
+    }
+
+    /// Cluster listener address of a member.
+    pub fn address(&self, id: NodeId) -> Option<String> {
+        self.network.address(id)
+    }
+}
+
+/// Bounds the time a write waits for a quorum. A leader cut off from the majority keeps
+/// waiting for acknowledgements, the entry may still be committed once the quorum is back.
+async fn committed(
+    write: impl std::future::Future<Output = Result<ScheduleEventResponse, ClusterError>>,
+) -> Result<ScheduleEventResponse, ClusterError> {
+    let timeout = config::cluster::write_timeout();
+    tokio::time::timeout(timeout, write).await.map_err(|_| {
+        ClusterError::Unavailable(format!("write wasn't committed within {:?}", timeout))
+    })?
+}
+
+/// Starts and stops actors of schedules and workflows changed by writes applied on this node.
+/// The supervisor only starts actors of those in shards this node owns.
+async fn run_events(
+    mut events: mpsc::UnboundedReceiver<ScheduleEventResponse>,
+    supervisor: Addr<ScheduleSupervisor>,
+) {
+    while let Some(event) = events.recv().await {
+        match event {
+            ScheduleEventResponse::Created { schedule, replaced } => {
+                if let Some(replaced) = replaced {
+                    supervisor.do_send(StopSchedule(replaced));
+                }
+                supervisor.do_send(StartSchedule(schedule.id));
+            }
+            ScheduleEventResponse::Updated(schedule) => {
+                supervisor.do_send(RestartSchedule(schedule.id));
+            }
+            ScheduleEventResponse::Deleted(id) => {
+                supervisor.do_send(StopSchedule(id));
+            }
+            ScheduleEventResponse::Expired(ids) => {
+                for id in ids {
+                    supervisor.do_send(StopSchedule(id));
+                }
+            }
+            ScheduleEventResponse::WorkflowCreated(workflow) => {
+                supervisor.do_send(StartWorkflow(workflow.id));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Hands the shards this node owns to the supervisor, as they are assigned.
+async fn follow_ownership(
+    mut ownership: watch::Receiver<Ownership>,
+    supervisor: Addr<ScheduleSupervisor>,
+) {
+    loop {
+        let owned = ownership.borrow_and_update().clone();
+        log::info!("Owning {} of {} shards", owned.owned.len(), owned.shards);
+        supervisor.do_send(SetOwnership(owned));
+        if ownership.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Assigns shards to live members while this node leads the cluster. Members that didn't
+/// answer the leader within `SCHEDULERS_CLUSTER_OWNER_TIMEOUT_MS` lose their shards, which
+/// go back to them once they answer again.
+async fn assign_shards(cluster: Arc<Cluster>) {
+    let timeout = config::cluster::owner_timeout();
+    let shards = config::cluster::shards();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut leading_since = None;
+    loop {
+        interval.tick().await;
+        let metrics = cluster.metrics();
+        if metrics.state != State::Leader {
+            leading_since = None;
+            continue;
+        }
+        // a new leader gives members time to answer before taking their shards
+        let since = *leading_since.get_or_insert_with(Instant::now);
+        let live: HashSet<NodeId> = metrics
+            .membership_config
+            .all_nodes()
+            .into_iter()
+            .filter(|id| {
+                *id == cluster.id
+                    || since.elapsed() < timeout
+                    || cluster
+                        .network
+                        .last_contact(*id)
+                        .map(|t| t.elapsed() < timeout)
+                        .unwrap_or(false)
+            })
+            .collect();
+        let current = match cluster.storage.shard_owners() {
+            Ok(current) => current,
+            Err(e) => {
+                log::error!("error reading shard owners: {}", e);
+                continue;
+            }
+        };
+        let changes = shards::rebalance(shards, &current, &live);
+        if changes.is_empty() {
+            continue;
+        }
+        log::info!("Assigning {} shards to {:?}", changes.len(), live);
+        if let Err(e) = cluster
+            .write_forwarded(ScheduleData::AssignShards(changes))
+            .await
+        {
+            log::warn!("error assigning shards: {}", e);
+        }
+    }
+}
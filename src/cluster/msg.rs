@@ -1,41 +1,107 @@
-use crate::api::dto::{CreateScheduleDto, ScheduleDto, UpdateScheduleDto};
-use crate::schema::{RequestHeaders, ScheduleId};
-use async_raft::{AppData, AppDataResponse};
+use crate::api::dto::{
+    CreateScheduleDto, CreateWorkflowDto, ScheduleDto, UpdateScheduleDto, WorkflowDto,
+};
+use crate::cluster::shards::ShardOwner;
+use crate::db::schema::{RunDocument, ScheduleDocument, ScheduleId, ScheduleStatus, WorkflowId};
+use async_raft::{AppData, AppDataResponse, NodeId};
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Request {
-    url: String,
-    method: String,
-    headers: Option<RequestHeaders>,
-    body: Vec<u8>,
-}
-
-impl Debug for Request {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Request")
-            .field("url", &self.url)
-            .field("method", &self.method)
-            .finish()
-    }
-}
 
+/// Writes replicated through Raft. Each carries the instant it was proposed, so every node
+/// applies it the same way.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ScheduleData {
     Create(CreateScheduleDto, DateTime<chrono::Utc>),
     Update(UpdateScheduleDto, DateTime<chrono::Utc>),
     Delete(ScheduleId, DateTime<chrono::Utc>),
+    /// Address of a member, replicated so every member can reach members added at runtime
+    SetPeer(NodeId, String),
+    /// New owners of shards with their fencing tokens, decided by the leader
+    AssignShards(Vec<(u32, ShardOwner)>),
+    /// Run of a schedule about to be fired by the owner of its shard
+    ClaimTick(TickClaim, DateTime<chrono::Utc>),
+    /// Outcome of a run, stored if the definition it ran is still current
+    RecordRun(RunDocument, DateTime<chrono::Utc>),
+    /// Inline follow-up of a finished run, run by the owner of its shard like any schedule
+    CreateFollowUp(Box<ScheduleDocument>),
+    /// Removes the listed expiry entries with their schedules if still expired by the instant,
+    /// listed by the leader
+    Expire(
+        DateTime<chrono::Utc>,
+        Vec<(DateTime<chrono::Utc>, ScheduleId)>,
+    ),
+    CreateWorkflow(CreateWorkflowDto, DateTime<chrono::Utc>),
+    /// Run of a workflow about to be started by the owner of its shard
+    ClaimWorkflowRun(WorkflowRunClaim, DateTime<chrono::Utc>),
+    /// Outcome of a workflow run, stored if no later run was claimed
+    FinishWorkflowRun(WorkflowRunFinished, DateTime<chrono::Utc>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TickClaim {
+    pub schedule_id: ScheduleId,
+    pub run: u64,
+    /// Instant the run is scheduled at
+    pub at: DateTime<chrono::Utc>,
+    pub node_id: NodeId,
+    /// Fencing token of the shard of the schedule held by the node
+    pub token: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowRunClaim {
+    pub workflow_id: WorkflowId,
+    pub run: u64,
+    /// Instant the run is scheduled at
+    pub at: DateTime<chrono::Utc>,
+    pub node_id: NodeId,
+    /// Fencing token of the shard of the workflow held by the node
+    pub token: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowRunFinished {
+    pub workflow_id: WorkflowId,
+    pub run: u64,
+    /// Instant the run was scheduled at
+    pub at: DateTime<chrono::Utc>,
+    /// Status of the workflow after the run
+    pub status: ScheduleStatus,
 }
 
 impl AppData for ScheduleData {}
 
+/// Result of applying a `ScheduleData` entry to the state machine.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ScheduleEventResponse {
-    Created(ScheduleDto),
+    /// Schedule was stored, replacing the pending schedule with the same dedup key, if any
+    Created {
+        schedule: ScheduleDto,
+        replaced: Option<ScheduleId>,
+    },
+    /// Schedule was not stored, a pending schedule with the same dedup key exists, or a
+    /// follow-up with the same id
+    Duplicate(ScheduleDto),
     Updated(ScheduleDto),
     Deleted(ScheduleId),
+    /// Schedule to update or delete doesn't exist
+    NotFound(ScheduleId),
+    /// Schedule was not stored, its follow-ups reference missing schedules or form a cycle
+    Rejected(String),
+    PeerSet(NodeId),
+    ShardsAssigned,
+    /// Run of a schedule or workflow was recorded, the claiming node fires it
+    TickClaimed(ScheduleId),
+    /// Claiming node doesn't own the shard of the schedule or workflow anymore
+    TickFenced(ScheduleId),
+    /// Run was claimed before, by this node or a former owner
+    TickAlreadyClaimed(ScheduleId),
+    RunRecorded(ScheduleId),
+    /// Schedule was deleted or replaced since the run started, or a later run was recorded
+    RunDiscarded(ScheduleId),
+    /// Schedules removed once they expired
+    Expired(Vec<ScheduleId>),
+    WorkflowCreated(WorkflowDto),
 }
 
 impl AppDataResponse for ScheduleEventResponse {}
@@ -0,0 +1,309 @@
+use super::msg::{ScheduleData, ScheduleEventResponse};
+use super::ClusterError;
+use crate::api::dto::{AddMemberDto, ClusterNodeDto};
+use crate::config;
+use crate::scheduler::schedule_actor::TriggerNow;
+use async_raft::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    VoteRequest, VoteResponse,
+};
+use async_raft::{NodeId, RaftNetwork};
+use async_trait::async_trait;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Calls other cluster members on their cluster listener.
+pub struct ClusterNetwork {
+    /// Address of each member, by node id
+    peers: RwLock<HashMap<NodeId, String>>,
+    /// Bearer token authenticating members to each other
+    secret: Option<String>,
+    rpc_timeout: Duration,
+    snapshot_timeout: Duration,
+    read_timeout: Duration,
+    join_timeout: Duration,
+    client: reqwest::Client,
+    /// Instant each member last answered an append entries request, heartbeats included
+    contacts: Mutex<HashMap<NodeId, Instant>>,
+}
+
+impl ClusterNetwork {
+    pub fn new(peers: HashMap<NodeId, String>, secret: Option<String>) -> Self {
+        Self {
+            peers: RwLock::new(peers),
+            secret,
+            rpc_timeout: config::cluster::rpc_timeout(),
+            snapshot_timeout: config::cluster::snapshot_timeout(),
+            read_timeout: config::cluster::read_timeout(),
+            join_timeout: config::cluster::join_timeout(),
+            client: reqwest::Client::new(),
+            contacts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn address(&self, id: NodeId) -> Option<String> {
+        self.peers.read().unwrap().get(&id).cloned()
+    }
+
+    pub fn set_address(&self, id: NodeId, address: String) {
+        self.peers.write().unwrap().insert(id, address);
+    }
+
+    /// Instant the given member last answered this node, only tracked while leading.
+    pub fn last_contact(&self, id: NodeId) -> Option<Instant> {
+        self.contacts.lock().unwrap().get(&id).copied()
+    }
+
+    /// Whether members share a secret.
+    pub fn secured(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Whether a request from another member carries the shared secret, as the value of its
+    /// `authorization` header.
+    pub fn authorized(&self, authorization: Option<&[u8]>) -> bool {
+        match &self.secret {
+            None => true,
+            Some(secret) => authorization
+                .and_then(|value| value.strip_prefix(b"Bearer "))
+                .map(|token| constant_time_eq(token, secret.as_bytes()))
+                .unwrap_or(false),
+        }
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        target: NodeId,
+        path: &str,
+    ) -> Option<reqwest::RequestBuilder> {
+        let address = self.address(target)?;
+        let request = self
+            .client
+            .request(method, format!("{}/cluster{}", address, path));
+        Some(match &self.secret {
+            Some(secret) => request.bearer_auth(secret),
+            None => request,
+        })
+    }
+
+    /// Sends a Raft RPC to another member.
+    async fn rpc<Req, Res>(
+        &self,
+        target: NodeId,
+        path: &str,
+        rpc: &Req,
+        timeout: Duration,
+    ) -> anyhow::Result<Res>
+    where
+        Req: Serialize,
+        Res: DeserializeOwned,
+    {
+        let request = self
+            .request(Method::POST, target, path)
+            .ok_or_else(|| anyhow::anyhow!("node {} is not a cluster member", target))?;
+        Self::send(target, request.json(rpc).timeout(timeout)).await
+    }
+
+    async fn send<Res: DeserializeOwned>(
+        target: NodeId,
+        request: reqwest::RequestBuilder,
+    ) -> anyhow::Result<Res> {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!(
+                "node {} answered {}: {}",
+                target,
+                status,
+                body
+            ));
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Asks another member for its Raft state.
+    pub async fn node_status(&self, target: NodeId) -> anyhow::Result<ClusterNodeDto> {
+        let request = self
+            .request(Method::GET, target, "/node")
+            .ok_or_else(|| anyhow::anyhow!("node {} has no known address", target))?;
+        Self::send(target, request.timeout(self.rpc_timeout)).await
+    }
+
+    /// Asks the leader to add a member back to the cluster, answers once it is a voting member.
+    pub async fn rejoin(&self, leader: NodeId, member: &AddMemberDto) -> Result<(), ClusterError> {
+        let request = self
+            .request(Method::POST, leader, "/members")
+            .ok_or(ClusterError::UnknownNode(leader))?;
+        let response = request
+            .json(member)
+            .timeout(self.join_timeout)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(ClusterError::Forward(format!("{}: {}", status, body)));
+        }
+        Ok(())
+    }
+
+    /// Hands a manual trigger of a schedule to the member owning its shard, answers once the
+    /// owner accepted it.
+    pub async fn trigger(
+        &self,
+        owner: NodeId,
+        id: &str,
+        trigger: &TriggerNow,
+    ) -> Result<(), ClusterError> {
+        let request = self
+            .request(Method::POST, owner, &format!("/trigger/{}", id))
+            .ok_or(ClusterError::UnknownNode(owner))?;
+        let response = request
+            .json(trigger)
+            .timeout(self.rpc_timeout)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            // the shard changed hands since
+            if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                return Err(ClusterError::Unavailable(body));
+            }
+            return Err(ClusterError::Forward(format!("{}: {}", status, body)));
+        }
+        Ok(())
+    }
+
+    /// Proposes a write through the leader, returns the result of applying it.
+    pub async fn forward_write(
+        &self,
+        leader: NodeId,
+        data: &ScheduleData,
+    ) -> Result<ScheduleEventResponse, ClusterError> {
+        let request = self
+            .request(Method::POST, leader, "/write")
+            .ok_or(ClusterError::UnknownNode(leader))?;
+        let response = request
+            .json(data)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            // the leader lost its quorum or its leadership
+            if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                return Err(ClusterError::Unavailable(body));
+            }
+            return Err(ClusterError::Forward(format!("{}: {}", status, body)));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))
+    }
+
+    /// Asks the leader for the index reads have to wait for, once it confirmed it still
+    /// leads the cluster.
+    pub async fn read_index(&self, leader: NodeId) -> Result<u64, ClusterError> {
+        let request = self
+            .request(Method::GET, leader, "/read-index")
+            .ok_or(ClusterError::UnknownNode(leader))?;
+        Self::send(leader, request.timeout(self.read_timeout))
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))
+    }
+
+    /// Sends a read to the leader, `path` being relative to `/cluster/read`. The response is
+    /// passed on as is, errors included.
+    pub async fn forward_read(
+        &self,
+        leader: NodeId,
+        path: &str,
+    ) -> Result<ForwardedRead, ClusterError> {
+        let request = self
+            .request(Method::GET, leader, &format!("/read{}", path))
+            .ok_or(ClusterError::UnknownNode(leader))?;
+        let response = request
+            .timeout(self.read_timeout)
+            .send()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let content_type = header("content-type");
+        let applied_index = header(APPLIED_INDEX_HEADER).and_then(|v| v.parse().ok());
+        let status = response.status().as_u16();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| ClusterError::Forward(e.to_string()))?
+            .to_vec();
+        Ok(ForwardedRead {
+            status,
+            content_type,
+            applied_index,
+            body,
+        })
+    }
+}
+
+/// Header of reads in cluster mode, the index of the last log entry applied by the node that
+/// served them.
+pub const APPLIED_INDEX_HEADER: &str = "x-applied-index";
+
+/// Response of the leader to a forwarded read.
+pub struct ForwardedRead {
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub applied_index: Option<u64>,
+    pub body: Vec<u8>,
+}
+
+/// Compares without bailing out at the first difference, so the time taken doesn't tell how
+/// much of a guessed secret is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[async_trait]
+impl RaftNetwork<ScheduleData> for ClusterNetwork {
+    async fn append_entries(
+        &self,
+        target: NodeId,
+        rpc: AppendEntriesRequest<ScheduleData>,
+    ) -> anyhow::Result<AppendEntriesResponse> {
+        let response = self
+            .rpc(target, "/append-entries", &rpc, self.rpc_timeout)
+            .await?;
+        self.contacts.lock().unwrap().insert(target, Instant::now());
+        Ok(response)
+    }
+
+    async fn install_snapshot(
+        &self,
+        target: NodeId,
+        rpc: InstallSnapshotRequest,
+    ) -> anyhow::Result<InstallSnapshotResponse> {
+        self.rpc(target, "/install-snapshot", &rpc, self.snapshot_timeout)
+            .await
+    }
+
+    async fn vote(&self, target: NodeId, rpc: VoteRequest) -> anyhow::Result<VoteResponse> {
+        self.rpc(target, "/vote", &rpc, self.rpc_timeout).await
+    }
+}
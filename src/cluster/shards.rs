@@ -0,0 +1,129 @@
+use async_raft::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Shard a schedule belongs to. The hash is stable across builds, unlike the std hasher.
+pub fn shard_of(id: &str, shards: u32) -> u32 {
+    crc32fast::hash(id.as_bytes()) % shards
+}
+
+/// Member running the schedules of a shard, recorded through the log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardOwner {
+    pub node_id: NodeId,
+    /// Fencing token, incremented every time the shard changes hands. Ticks are only claimed
+    /// with the token of the current owner, so a former owner can't fire them.
+    pub token: u64,
+}
+
+/// Shards owned by a node, with their fencing tokens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ownership {
+    pub shards: u32,
+    pub owned: HashMap<u32, u64>,
+}
+
+impl Ownership {
+    /// Fencing token of the shard of the given schedule, if this node owns it.
+    pub fn token(&self, id: &str) -> Option<u64> {
+        if self.shards == 0 {
+            return None;
+        }
+        self.owned.get(&shard_of(id, self.shards)).copied()
+    }
+
+    pub fn owns(&self, id: &str) -> bool {
+        self.token(id).is_some()
+    }
+}
+
+/// Owner of each shard among live members, by rendezvous hashing: a shard goes to the member
+/// with the highest hash of the pair, so members joining or leaving only move their share.
+/// Returns the shards whose owner changes, with the next fencing token of each.
+pub fn rebalance(
+    shards: u32,
+    current: &HashMap<u32, ShardOwner>,
+    live: &HashSet<NodeId>,
+) -> Vec<(u32, ShardOwner)> {
+    let mut changes = vec![];
+    for shard in 0..shards {
+        let owner = live
+            .iter()
+            .copied()
+            .max_by_key(|node| (mix(*node ^ ((shard as u64) << 32)), *node));
+        if let Some(owner) = owner {
+            let previous = current.get(&shard);
+            if previous.map(|o| o.node_id) != Some(owner) {
+                let token = previous.map(|o| o.token).unwrap_or(0) + 1;
+                changes.push((
+                    shard,
+                    ShardOwner {
+                        node_id: owner,
+                        token,
+                    },
+                ));
+            }
+        }
+    }
+    changes
+}
+
+/// SplitMix64 finalizer, spreads close inputs over the whole range.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(current: &mut HashMap<u32, ShardOwner>, changes: Vec<(u32, ShardOwner)>) {
+        current.extend(changes);
+    }
+
+    #[test]
+    fn assigns_every_shard_to_a_live_member() {
+        let mut current = HashMap::new();
+        let live = HashSet::from([0, 1, 2]);
+        let changes = rebalance(64, &current, &live);
+        apply(&mut current, changes);
+        assert_eq!(current.len(), 64);
+        for node in &live {
+            assert!(current.values().any(|o| o.node_id == *node));
+        }
+        // a stable membership moves nothing
+        assert!(rebalance(64, &current, &live).is_empty());
+    }
+
+    #[test]
+    fn moves_only_the_shards_of_a_member_leaving() {
+        let mut current = HashMap::new();
+        let changes = rebalance(64, &current, &HashSet::from([0, 1, 2]));
+        apply(&mut current, changes);
+        let before = current.clone();
+        let changes = rebalance(64, &current, &HashSet::from([0, 1]));
+        assert!(!changes.is_empty());
+        for (shard, owner) in &changes {
+            assert_eq!(before[shard].node_id, 2);
+            assert_ne!(owner.node_id, 2);
+            assert_eq!(owner.token, before[shard].token + 1);
+        }
+        apply(&mut current, changes);
+        assert!(current.values().all(|o| o.node_id != 2));
+    }
+
+    #[test]
+    fn maps_ids_to_owned_shards() {
+        let shards = 8;
+        let shard = shard_of("a", shards);
+        let ownership = Ownership {
+            shards,
+            owned: HashMap::from([(shard, 3)]),
+        };
+        assert_eq!(ownership.token("a"), Some(3));
+        assert_eq!(shard_of("a", shards), shard);
+        assert!(!Ownership::default().owns("a"));
+    }
+}
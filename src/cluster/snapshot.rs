@@ -1,44 +1,436 @@
-use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+use async_raft::raft::MembershipConfig;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error as IoError, Read, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::io::{Error as IoError, SeekFrom};
 use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+/// Leading bytes of every snapshot file
+const MAGIC: &[u8; 8] = b"SRSNAP\0\0";
+/// Version of the snapshot file layout
+const VERSION: u32 = 1;
+/// Extension of complete snapshot files, files being received end with `.part`
+const EXTENSION: &str = "snap";
+
+// Records of the compressed payload
+const END: u8 = 0;
+const TREE: u8 = 1;
+const ENTRY: u8 = 2;
+
+/// Describes the state machine a snapshot holds, stored in the snapshot file header.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotMeta {
+    pub id: String,
+    /// Index of the last log entry applied to the state machine
+    pub index: u64,
+    pub term: u64,
+    pub membership: MembershipConfig,
+}
+
+/// Snapshot file of the state machine. Snapshots of this node are read from it, and snapshots
+/// of the leader are streamed into it chunk by chunk.
+///
+/// A snapshot file is laid out as:
+/// - the magic bytes and the layout version as a big endian `u32`
+/// - the length of the [SnapshotMeta] as a big endian `u32`, followed by its JSON
+/// - gzip compressed records: the name of each tree followed by its entries
+/// - the CRC32 of all previous bytes as a big endian `u32`
+pub struct ClusterSnapshot {
+    file: tokio::fs::File,
+    path: PathBuf,
+}
+
+impl ClusterSnapshot {
+    /// Opens a snapshot file for reading.
+    pub async fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = tokio::fs::File::open(&path).await?;
+        Ok(Self { file, path })
+    }
+
+    /// Creates a file receiving a snapshot.
+    pub async fn create(path: PathBuf) -> std::io::Result<Self> {
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Self { file, path })
+    }
+
+    /// Syncs the received snapshot to disk, returns its path.
+    pub async fn finish(self) -> std::io::Result<PathBuf> {
+        self.file.sync_all().await?;
+        Ok(self.path)
+    }
+}
+
+/// Snapshot files of a node, of which the latest is kept.
+pub struct SnapshotDir {
+    path: PathBuf,
+}
+
+impl SnapshotDir {
+    pub fn new(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Path of the complete snapshot with the given id.
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.path.join(format!("{}.{}", id, EXTENSION))
+    }
+
+    /// Path of a file receiving a snapshot.
+    pub fn part_path(&self) -> PathBuf {
+        let nanos = chrono::Utc::now().timestamp_nanos();
+        self.path.join(format!("{}.part", nanos))
+    }
+
+    /// Returns the valid snapshot with the highest index, removes files left over from
+    /// interrupted snapshots.
+    pub fn latest(&self) -> std::io::Result<Option<SnapshotMeta>> {
+        let mut latest: Option<SnapshotMeta> = None;
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().map(|e| e == "part").unwrap_or(false) {
+                std::fs::remove_file(&path)?;
+                continue;
+            }
+            if path.extension().map(|e| e != EXTENSION).unwrap_or(true) {
+                continue;
+            }
+            match verify(&path) {
+                Ok(meta)
+                    if latest
+                        .as_ref()
+                        .map(|l| l.index < meta.index)
+                        .unwrap_or(true) =>
+                {
+                    latest = Some(meta)
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Ignoring snapshot {}: {}", path.display(), e),
+            }
+        }
+        Ok(latest)
+    }
+
+    /// Removes every snapshot but the given one.
+    pub fn retain(&self, id: &str) -> std::io::Result<()> {
+        let keep = self.path(id);
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path != keep && path.extension().map(|e| e == EXTENSION).unwrap_or(false) {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Feeds every written byte to a CRC32.
+struct ChecksumWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn invalid(message: impl Into<String>) -> IoError {
+    IoError::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+fn write_chunk(out: &mut impl Write, chunk: &[u8]) -> std::io::Result<()> {
+    out.write_all(&(chunk.len() as u32).to_be_bytes())?;
+    out.write_all(chunk)
+}
+
+fn read_u32(input: &mut impl Read) -> std::io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_chunk(input: &mut impl Read) -> std::io::Result<Vec<u8>> {
+    let mut chunk = vec![0; read_u32(input)? as usize];
+    input.read_exact(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// Writes the given trees into a new snapshot file, synced to disk before returning.
+pub fn write(
+    db: &sled::Db,
+    trees: &[&str],
+    meta: &SnapshotMeta,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let part = path.with_extension("part");
+    let mut out = ChecksumWriter {
+        inner: BufWriter::new(File::create(&part)?),
+        hasher: crc32fast::Hasher::new(),
+    };
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_be_bytes())?;
+    write_chunk(&mut out, &serde_json::to_vec(meta)?)?;
+    let mut payload = GzEncoder::new(out, Compression::default());
+    for name in trees {
+        payload.write_all(&[TREE])?;
+        write_chunk(&mut payload, name.as_bytes())?;
+        for entry in db.open_tree(name)?.iter() {
+            let (key, value) = entry?;
+            payload.write_all(&[ENTRY])?;
+            write_chunk(&mut payload, &key)?;
+            write_chunk(&mut payload, &value)?;
+        }
+    }
+    payload.write_all(&[END])?;
+    let out = payload.finish()?;
+    let checksum = out.hasher.finalize();
+    let mut file = out.inner;
+    file.write_all(&checksum.to_be_bytes())?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(part, path)?;
+    Ok(())
+}
+
+/// Checks the checksum and layout version of a snapshot file, returns its metadata.
+pub fn verify(path: &Path) -> std::io::Result<SnapshotMeta> {
+    let mut input = BufReader::new(File::open(path)?);
+    let len = input.get_ref().metadata()?.len();
+    if len < (MAGIC.len() + 4 + 4 + 4) as u64 {
+        return Err(invalid("snapshot is truncated"));
+    }
+    let mut hasher = crc32fast::Hasher::new();
+    let mut remaining = len - 4;
+    let mut buf = vec![0; 64 * 1024];
+    while remaining > 0 {
+        let want = remaining.min(buf.len() as u64) as usize;
+        let n = input.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(invalid("snapshot is truncated"));
+        }
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    if read_u32(&mut input)? != hasher.finalize() {
+        return Err(invalid("snapshot checksum mismatch"));
+    }
+    read_header(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_header(input: &mut impl Read) -> std::io::Result<SnapshotMeta> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a snapshot"));
+    }
+    let version = read_u32(input)?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported snapshot version {}", version)));
+    }
+    Ok(serde_json::from_slice(&read_chunk(input)?)?)
+}
 
-pub struct ClusterSnapshot {}
+/// Replaces the trees held by a snapshot, checked with [verify] beforehand, with its content.
+/// Trees the snapshot doesn't hold are left as they are.
+pub fn restore(db: &sled::Db, path: &Path) -> anyhow::Result<()> {
+    let mut input = BufReader::new(File::open(path)?);
+    read_header(&mut input)?;
+    let mut payload = GzDecoder::new(input);
+    let mut tree: Option<sled::Tree> = None;
+    let mut batch = sled::Batch::default();
+    let mut batched = 0;
+    loop {
+        let mut record = [0; 1];
+        payload.read_exact(&mut record)?;
+        match record[0] {
+            ENTRY => {
+                let key = read_chunk(&mut payload)?;
+                let value = read_chunk(&mut payload)?;
+                batch.insert(key, value);
+                batched += 1;
+                if batched == 1024 {
+                    let tree = tree
+                        .as_ref()
+                        .ok_or_else(|| invalid("entry outside a tree"))?;
+                    tree.apply_batch(std::mem::take(&mut batch))?;
+                    batched = 0;
+                }
+            }
+            TREE | END => {
+                if let Some(tree) = tree.take() {
+                    tree.apply_batch(std::mem::take(&mut batch))?;
+                    batched = 0;
+                }
+                if record[0] == END {
+                    break;
+                }
+                let name = read_chunk(&mut payload)?;
+                let next = db.open_tree(name)?;
+                next.clear()?;
+                tree = Some(next);
+            }
+            other => return Err(invalid(format!("unknown snapshot record {}", other)).into()),
+        }
+    }
+    db.flush()?;
+    Ok(())
+}
 
 impl AsyncRead for ClusterSnapshot {
     fn poll_read(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        todo!()
+        Pin::new(&mut self.file).poll_read(cx, buf)
     }
 }
 
 impl AsyncWrite for ClusterSnapshot {
     fn poll_write(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
-    ) -> Poll<anyhow::Result<usize, IoError>> {
-        todo!()
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
     }
 
-    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<anyhow::Result<(), IoError>> {
-        todo!()
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.file).poll_flush(cx)
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<anyhow::Result<(), IoError>> {
-        todo!()
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
     }
 }
 
 impl AsyncSeek for ClusterSnapshot {
-    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
-        todo!()
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        Pin::new(&mut self.file).start_seek(position)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.file).poll_complete(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Snapshot directory removed once the test is done.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("schedule-rs-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn meta(id: &str, index: u64) -> SnapshotMeta {
+        SnapshotMeta {
+            id: id.to_string(),
+            index,
+            term: 1,
+            membership: MembershipConfig::new_initial(0),
+        }
+    }
+
+    fn db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
     }
 
-    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
-        todo!()
+    #[test]
+    fn restores_written_trees() {
+        let tmp = TempDir::new("snapshot-round-trip");
+        let dir = SnapshotDir::new(&tmp.0).unwrap();
+        let source = db();
+        source.open_tree("a").unwrap().insert("k1", "v1").unwrap();
+        source.open_tree("a").unwrap().insert("k2", "v2").unwrap();
+        source.open_tree("b").unwrap().insert("k3", "v3").unwrap();
+        let path = dir.path("s1");
+        write(&source, &["a", "b"], &meta("s1", 7), &path).unwrap();
+
+        let verified = verify(&path).unwrap();
+        assert_eq!(verified.id, "s1");
+        assert_eq!(verified.index, 7);
+
+        let target = db();
+        target.open_tree("a").unwrap().insert("stale", "v").unwrap();
+        target.open_tree("c").unwrap().insert("kept", "v").unwrap();
+        restore(&target, &path).unwrap();
+        let a = target.open_tree("a").unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(a.get("k2").unwrap().unwrap(), "v2");
+        assert_eq!(
+            target.open_tree("b").unwrap().get("k3").unwrap().unwrap(),
+            "v3"
+        );
+        // trees out of the snapshot are left as they are
+        assert!(target.open_tree("c").unwrap().contains_key("kept").unwrap());
+    }
+
+    #[test]
+    fn rejects_corrupted_files() {
+        let tmp = TempDir::new("snapshot-corrupted");
+        let dir = SnapshotDir::new(&tmp.0).unwrap();
+        let source = db();
+        source.open_tree("a").unwrap().insert("k", "v").unwrap();
+        let path = dir.path("s1");
+        write(&source, &["a"], &meta("s1", 3), &path).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let error = verify(&path).unwrap_err();
+        assert_eq!(error.to_string(), "snapshot checksum mismatch");
+
+        std::fs::write(&path, &bytes[..10]).unwrap();
+        assert_eq!(
+            verify(&path).unwrap_err().to_string(),
+            "snapshot is truncated"
+        );
+    }
+
+    #[test]
+    fn picks_the_latest_valid_snapshot() {
+        let tmp = TempDir::new("snapshot-latest");
+        let dir = SnapshotDir::new(&tmp.0).unwrap();
+        let source = db();
+        write(&source, &[], &meta("s1", 3), &dir.path("s1")).unwrap();
+        write(&source, &[], &meta("s2", 5), &dir.path("s2")).unwrap();
+        std::fs::write(dir.path("s3"), b"not a snapshot at all").unwrap();
+        std::fs::write(dir.part_path(), b"interrupted").unwrap();
+
+        assert_eq!(dir.latest().unwrap().unwrap().id, "s2");
+        // interrupted transfers are removed
+        let files = std::fs::read_dir(&tmp.0).unwrap().count();
+        assert_eq!(files, 3);
+
+        dir.retain("s2").unwrap();
+        assert_eq!(std::fs::read_dir(&tmp.0).unwrap().count(), 1);
     }
 }
@@ -1,5 +1,14 @@
 use super::msg::{ScheduleData, ScheduleEventResponse};
-use crate::cluster::snapshot::ClusterSnapshot;
+use super::network::ClusterNetwork;
+use super::shards::{shard_of, Ownership, ShardOwner};
+use super::snapshot::{self, ClusterSnapshot, SnapshotDir, SnapshotMeta};
+use crate::api::dto::ScheduleDto;
+use crate::config;
+use crate::db::schema::{ScheduleDocument, WorkflowDocument};
+use crate::db::{
+    Created, ExecutionRepository, ScheduleRepository, TriggerRepository, WorkflowRepository,
+};
+use crate::scheduler::chain;
 use anyhow::{Context, Result};
 use async_raft::raft::{Entry, EntryPayload, MembershipConfig};
 use async_raft::storage::{CurrentSnapshotData, HardState, InitialState};
@@ -7,9 +16,22 @@ use async_raft::{NodeId, RaftStorage};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use sled::transaction::{ConflictableTransactionError, TransactionError};
-use sled::Batch;
+use sled::{Batch, Tree};
+use std::collections::HashMap;
+use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, watch, Mutex, RwLock};
+
+/// Tree of log entries, keyed by big endian index
+const LOG_TREE: &str = "raft_log";
+/// Tree of the Raft state of this node, under the keys below
+const STATE_TREE: &str = "raft_state";
+const HARD_STATE: &[u8] = b"hard_state";
+const LAST_APPLIED: &[u8] = b"last_applied";
+/// Tree of member addresses replicated through the log, keyed by big endian node id
+const PEERS_TREE: &str = "raft_peers";
+/// Tree of shard owners, keyed by big endian shard
+const SHARDS_TREE: &str = "schedule_shards";
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct ClusterState {
@@ -20,30 +42,421 @@ pub struct ClusterState {
 pub struct SchedulerRaftStorage {
     // node id
     id: NodeId,
-    // sled db
+    // sled db, holding the log and Raft state next to the state machine
     db: sled::Db,
-    // current cluster state
+    log: Tree,
+    raft_state: Tree,
+    peers: Tree,
+    // addresses of members, updated as `peers` changes
+    network: Arc<ClusterNetwork>,
+    shard_owners: Tree,
+    shards: u32,
+    // shards owned by this node, published as `shard_owners` changes
+    ownership: watch::Sender<Ownership>,
+    // schedule changes applied, for the supervisor of this node
+    events: mpsc::UnboundedSender<ScheduleEventResponse>,
+    // current cluster state, as stored in `raft_state`
     state: RwLock<ClusterState>,
-    // current HardState
-    hard_state: RwLock<Option<HardState>>,
-    // current snapshot
-    current_snapshot: RwLock<Option<CurrentSnapshotData<ClusterSnapshot>>>,
+    // state machine
+    schedules: Arc<ScheduleRepository>,
+    executions: Arc<ExecutionRepository>,
+    // trigger ledger of this node, not replicated
+    triggers: Arc<TriggerRepository>,
+    // workflow definitions, on the trees of the repository the API uses
+    workflows: WorkflowRepository,
+    // trees of the state machine snapshots are taken of
+    snapshot_trees: Vec<&'static str>,
+    snapshots: SnapshotDir,
+    // latest snapshot, covering the log up to its index
+    current_snapshot: RwLock<Option<SnapshotMeta>>,
+    // compaction interval, in log entries
+    snapshot_logs: u64,
+    // held while compacting
+    compacting: Mutex<()>,
 }
 
 impl SchedulerRaftStorage {
-    pub fn new(id: NodeId) -> Self {
-        let db = sled::open("schedule-rs.mdb").unwrap();
-        let state = RwLock::new(ClusterState::default());
-        let hard_state = RwLock::new(None);
-        let current_snapshot = RwLock::new(None);
-        Self {
+    pub fn new(
+        id: NodeId,
+        db: sled::Db,
+        network: Arc<ClusterNetwork>,
+        schedules: Arc<ScheduleRepository>,
+        executions: Arc<ExecutionRepository>,
+        triggers: Arc<TriggerRepository>,
+        events: mpsc::UnboundedSender<ScheduleEventResponse>,
+    ) -> Self {
+        let log = db.open_tree(LOG_TREE).unwrap();
+        let raft_state = db.open_tree(STATE_TREE).unwrap();
+        let peers = db.open_tree(PEERS_TREE).unwrap();
+        let shard_owners = db.open_tree(SHARDS_TREE).unwrap();
+        // addresses given by SCHEDULERS_CLUSTER_PEERS take precedence over stored ones
+        for (id, address) in stored_peers(&peers).unwrap() {
+            if network.address(id).is_none() {
+                network.set_address(id, address);
+            }
+        }
+        let snapshots = SnapshotDir::new(config::cluster::snapshot_path())
+            .expect("Invalid SCHEDULERS_CLUSTER_SNAPSHOT_PATH");
+        let current_snapshot = snapshots.latest().unwrap();
+        let last_applied_log = raft_state
+            .get(LAST_APPLIED)
+            .unwrap()
+            .map(|v| u64::from_be_bytes(v.as_ref().try_into().unwrap()))
+            .unwrap_or(0);
+        let state = RwLock::new(ClusterState { last_applied_log });
+        let mut snapshot_trees = ScheduleRepository::TREES.to_vec();
+        snapshot_trees.extend([WorkflowRepository::TREE, PEERS_TREE, SHARDS_TREE]);
+        if config::cluster::snapshot_history() {
+            snapshot_trees.extend(ExecutionRepository::TREES);
+            snapshot_trees.push(WorkflowRepository::RUNS_TREE);
+        }
+        let workflows = WorkflowRepository::new(&db);
+        let storage = Self {
             id,
             db,
+            log,
+            raft_state,
+            peers,
+            network,
+            shard_owners,
+            shards: config::cluster::shards(),
+            ownership: watch::channel(Ownership::default()).0,
+            events,
             state,
-            hard_state,
-            current_snapshot,
+            schedules,
+            executions,
+            triggers,
+            workflows,
+            snapshot_trees,
+            snapshots,
+            current_snapshot: RwLock::new(current_snapshot),
+            snapshot_logs: config::cluster::snapshot_logs(),
+            compacting: Mutex::new(()),
+        };
+        storage.publish_ownership().unwrap();
+        storage
+    }
+
+    /// Shards owned by this node, updated as they are assigned.
+    pub fn ownership(&self) -> watch::Receiver<Ownership> {
+        self.ownership.subscribe()
+    }
+
+    /// Owner of every assigned shard.
+    pub fn shard_owners(&self) -> Result<HashMap<u32, ShardOwner>> {
+        let mut owners = HashMap::new();
+        for entry in self.shard_owners.iter() {
+            let (key, value) = entry?;
+            let shard = u32::from_be_bytes(key.as_ref().try_into()?);
+            owners.insert(shard, serde_json::from_slice(&value)?);
+        }
+        Ok(owners)
+    }
+
+    fn shard_owner(&self, shard: u32) -> Result<Option<ShardOwner>> {
+        match self.shard_owners.get(shard.to_be_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
         }
     }
+
+    /// Member owning the shard of the given schedule or workflow, if assigned.
+    pub fn owner_of(&self, id: &str) -> Result<Option<NodeId>> {
+        let owner = self.shard_owner(shard_of(id, self.shards))?;
+        Ok(owner.map(|o| o.node_id))
+    }
+
+    /// Whether the given member holds the shard of the schedule or workflow with the token.
+    fn holds(&self, id: &str, node_id: NodeId, token: u64) -> Result<bool> {
+        let owner = self.shard_owner(shard_of(id, self.shards))?;
+        Ok(owner == Some(ShardOwner { node_id, token }))
+    }
+
+    /// Tells this node which shards it owns.
+    fn publish_ownership(&self) -> Result<()> {
+        let owned = self
+            .shard_owners()?
+            .into_iter()
+            .filter(|(_, owner)| owner.node_id == self.id)
+            .map(|(shard, owner)| (shard, owner.token))
+            .collect();
+        self.ownership.send_replace(Ownership {
+            shards: self.shards,
+            owned,
+        });
+        Ok(())
+    }
+
+    /// Stores the index of the last entry applied. It isn't synced to disk: the log is, and
+    /// entries are applied again with the same result after a crash, so the state machine only
+    /// reaches the disk once per batch or on the database flush interval.
+    fn save_last_applied(&self, sm: &mut ClusterState, index: u64) -> Result<()> {
+        self.raft_state.insert(LAST_APPLIED, &index.to_be_bytes())?;
+        sm.last_applied_log = index;
+        Ok(())
+    }
+
+    /// Membership in effect at the given log index, set by the last change up to it.
+    fn membership_at(&self, index: u64) -> Result<Option<MembershipConfig>> {
+        for value_vec in self.log.range(..=index.to_be_bytes()).values().rev() {
+            let value = serde_json::from_slice::<Entry<ScheduleData>>(value_vec?.as_ref())?;
+            match value.payload {
+                EntryPayload::ConfigChange(cfg) => return Ok(Some(cfg.membership)),
+                EntryPayload::SnapshotPointer(snap) => return Ok(Some(snap.membership)),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replaces log entries up to `through`, or the whole log, by a pointer to the snapshot.
+    fn compact_log(&self, through: Option<u64>, meta: &SnapshotMeta) -> Result<()> {
+        let entries = match through {
+            Some(through) => self.log.range(..=through.to_be_bytes()),
+            None => self.log.iter(),
+        };
+        let mut batch = Batch::default();
+        for key in entries.keys() {
+            batch.remove(key?);
+        }
+        let pointer = Entry::<ScheduleData>::new_snapshot_pointer(
+            meta.index,
+            meta.term,
+            meta.id.clone(),
+            meta.membership.clone(),
+        );
+        batch.insert(
+            meta.index.to_be_bytes().to_vec(),
+            serde_json::to_vec(&pointer)?,
+        );
+        self.log.apply_batch(batch)?;
+        self.log.flush()?;
+        Ok(())
+    }
+
+    /// Snapshots the state machine at the last applied entry and replaces the log up to it by
+    /// a pointer to the snapshot.
+    async fn compact(&self) -> Result<SnapshotMeta> {
+        let _compacting = self.compacting.lock().await;
+        // entries aren't applied during the export, so it matches the last applied index
+        let sm = self.state.read().await;
+        let index = sm.last_applied_log;
+        let term = match self.log.get(index.to_be_bytes())? {
+            Some(value_vec) => serde_json::from_slice::<Entry<ScheduleData>>(&value_vec)?.term,
+            None => return Err(anyhow::anyhow!("log entry {} not found", index)),
+        };
+        let membership = self
+            .membership_at(index)?
+            .unwrap_or_else(|| MembershipConfig::new_initial(self.id));
+        let meta = SnapshotMeta {
+            id: format!(
+                "{}-{}-{}",
+                term,
+                index,
+                chrono::Utc::now().timestamp_millis()
+            ),
+            index,
+            term,
+            membership,
+        };
+        let (data, trees, m, path) = (
+            self.db.clone(),
+            self.snapshot_trees.clone(),
+            meta.clone(),
+            self.snapshots.path(&meta.id),
+        );
+        tokio::task::spawn_blocking(move || snapshot::write(&data, &trees, &m, &path)).await??;
+        // the entries dropped from the log below can't be applied again after a crash
+        self.db.flush_async().await?;
+        drop(sm);
+
+        self.compact_log(Some(index), &meta)?;
+        self.set_current_snapshot(meta.clone()).await?;
+        tracing::info!(index, term, "Log compacted");
+        Ok(meta)
+    }
+
+    /// Makes the given snapshot the current one, removing older ones.
+    async fn set_current_snapshot(&self, meta: SnapshotMeta) -> Result<()> {
+        self.snapshots.retain(&meta.id)?;
+        *self.current_snapshot.write().await = Some(meta);
+        Ok(())
+    }
+
+    /// Applies the entry at the given log index to the schedules tree. An entry applied again,
+    /// after a crash between applying it and saving the last applied index, gets the same
+    /// result, so every value it writes comes from the entry or its index.
+    async fn apply(&self, index: u64, data: &ScheduleData) -> Result<ScheduleEventResponse> {
+        let response = match data.clone() {
+            ScheduleData::Create(params, at) => {
+                // checked here rather than on the replica the request reached
+                let references = chain::check_references(
+                    &params.id,
+                    params.on_success.as_ref(),
+                    params.on_failure.as_ref(),
+                    &self.schedules,
+                )
+                .await;
+                match references {
+                    Err(e) => ScheduleEventResponse::Rejected(e),
+                    Ok(()) => match self.schedules.create_schedule_at(params, at).await? {
+                        Created::New { schedule, replaced } => {
+                            ScheduleEventResponse::Created { schedule, replaced }
+                        }
+                        Created::Duplicate(existing) => ScheduleEventResponse::Duplicate(existing),
+                    },
+                }
+            }
+            ScheduleData::Update(params, at) => {
+                let id = params.id.clone();
+                let references = chain::check_references(
+                    &id,
+                    params.on_success.as_ref(),
+                    params.on_failure.as_ref(),
+                    &self.schedules,
+                )
+                .await;
+                match references {
+                    Err(e) => ScheduleEventResponse::Rejected(e),
+                    // the index rather than an increment, so applying again keeps it
+                    Ok(()) => match self
+                        .schedules
+                        .update_schedule_to(params, at, Some(index))
+                        .await?
+                    {
+                        Some(schedule) => ScheduleEventResponse::Updated(schedule),
+                        None => ScheduleEventResponse::NotFound(id),
+                    },
+                }
+            }
+            ScheduleData::Delete(id, _) => {
+                let deleted = self.schedules.delete(id.clone()).await?;
+                // also when applied again after only the schedule was removed
+                self.executions.delete(id.clone()).await?;
+                self.triggers.delete(id.clone()).await?;
+                if deleted {
+                    ScheduleEventResponse::Deleted(id)
+                } else {
+                    ScheduleEventResponse::NotFound(id)
+                }
+            }
+            ScheduleData::SetPeer(id, address) => {
+                self.peers.insert(id.to_be_bytes(), address.as_bytes())?;
+                self.network.set_address(id, address);
+                ScheduleEventResponse::PeerSet(id)
+            }
+            ScheduleData::AssignShards(owners) => {
+                for (shard, owner) in owners {
+                    // tokens never go back, assignments decided on a stale view are ignored
+                    let current = self.shard_owner(shard)?.map(|o| o.token).unwrap_or(0);
+                    if owner.token > current {
+                        self.shard_owners
+                            .insert(shard.to_be_bytes(), serde_json::to_vec(&owner)?)?;
+                    }
+                }
+                self.publish_ownership()?;
+                ScheduleEventResponse::ShardsAssigned
+            }
+            ScheduleData::ClaimTick(claim, at) => {
+                let id = claim.schedule_id;
+                if !self.holds(&id, claim.node_id, claim.token)? {
+                    ScheduleEventResponse::TickFenced(id)
+                } else {
+                    match self.schedules.get::<ScheduleDocument>(id.clone()).await? {
+                        None => ScheduleEventResponse::NotFound(id),
+                        Some(schedule) if schedule.runs >= claim.run => {
+                            ScheduleEventResponse::TickAlreadyClaimed(id)
+                        }
+                        Some(mut schedule) => {
+                            // the next owner resumes after this run
+                            schedule.runs = claim.run;
+                            schedule.last_run = Some(claim.at);
+                            schedule.updated_at = at;
+                            self.schedules.save(schedule).await?;
+                            ScheduleEventResponse::TickClaimed(id)
+                        }
+                    }
+                }
+            }
+            ScheduleData::RecordRun(run, at) => {
+                let id = run.schedule_id.clone();
+                if self.schedules.record_run(run, at).await? {
+                    ScheduleEventResponse::RunRecorded(id)
+                } else {
+                    ScheduleEventResponse::RunDiscarded(id)
+                }
+            }
+            ScheduleData::CreateFollowUp(schedule) => {
+                let created = self.schedules.create_follow_up(*schedule.clone()).await?;
+                let schedule = ScheduleDto::from(*schedule);
+                if created {
+                    ScheduleEventResponse::Created {
+                        schedule,
+                        replaced: None,
+                    }
+                } else {
+                    ScheduleEventResponse::Duplicate(schedule)
+                }
+            }
+            ScheduleData::Expire(at, entries) => {
+                let expired = self.schedules.delete_expired_entries(at, entries).await?;
+                for id in &expired {
+                    self.executions.delete(id.clone()).await?;
+                    self.triggers.delete(id.clone()).await?;
+                }
+                ScheduleEventResponse::Expired(expired)
+            }
+            ScheduleData::CreateWorkflow(params, at) => {
+                ScheduleEventResponse::WorkflowCreated(self.workflows.create_at(params, at).await?)
+            }
+            ScheduleData::ClaimWorkflowRun(claim, at) => {
+                let id = claim.workflow_id;
+                if !self.holds(&id, claim.node_id, claim.token)? {
+                    ScheduleEventResponse::TickFenced(id)
+                } else {
+                    match self.workflows.get::<WorkflowDocument>(id.clone()).await? {
+                        None => ScheduleEventResponse::NotFound(id),
+                        Some(workflow) if workflow.runs >= claim.run => {
+                            ScheduleEventResponse::TickAlreadyClaimed(id)
+                        }
+                        Some(mut workflow) => {
+                            workflow.runs = claim.run;
+                            workflow.updated_at = at;
+                            self.workflows.save(workflow).await?;
+                            ScheduleEventResponse::TickClaimed(id)
+                        }
+                    }
+                }
+            }
+            ScheduleData::FinishWorkflowRun(finished, at) => {
+                let id = finished.workflow_id;
+                match self.workflows.get::<WorkflowDocument>(id.clone()).await? {
+                    Some(mut workflow) if workflow.runs == finished.run => {
+                        workflow.last_run = Some(finished.at);
+                        workflow.status = finished.status;
+                        workflow.updated_at = at;
+                        self.workflows.save(workflow).await?;
+                        ScheduleEventResponse::RunRecorded(id)
+                    }
+                    _ => ScheduleEventResponse::RunDiscarded(id),
+                }
+            }
+        };
+        // every member runs the schedules of its shards, not only the one proposing the write
+        let _ = self.events.send(response.clone());
+        Ok(response)
+    }
+}
+
+/// Member addresses stored in the given tree.
+fn stored_peers(peers: &Tree) -> Result<Vec<(NodeId, String)>> {
+    let mut stored = vec![];
+    for entry in peers.iter() {
+        let (key, value) = entry?;
+        let id = NodeId::from_be_bytes(key.as_ref().try_into()?);
+        stored.push((id, String::from_utf8(value.to_vec())?));
+    }
+    Ok(stored)
 }
 
 #[derive(Debug, Error)]
@@ -57,30 +470,19 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn get_membership_config(&self) -> Result<MembershipConfig> {
-        let mut current_key = u64::MAX.to_be_bytes().to_vec();
-        while let Some((key_vec, value_vec)) = self.db.get_lt(current_key.clone())? {
-            let value = serde_json::from_slice::<Entry<ScheduleData>>(value_vec.as_ref())?;
-            let cfg_opt = match value.payload {
-                EntryPayload::ConfigChange(cfg) => Some(cfg.membership.clone()),
-                EntryPayload::SnapshotPointer(snap) => Some(snap.membership.clone()),
-                _ => None,
-            };
-            if let Some(cfg) = cfg_opt {
-                return Ok(cfg);
-            }
-            current_key = key_vec.to_vec();
-        }
-        Ok(MembershipConfig::new_initial(self.id))
+        Ok(self
+            .membership_at(u64::MAX)?
+            .unwrap_or_else(|| MembershipConfig::new_initial(self.id)))
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn get_initial_state(&self) -> Result<InitialState> {
         let membership = self.get_membership_config().await?;
-        let mut hs = self.hard_state.write().await;
-        let log_entry = self.db.last()?;
+        let hard_state = self.raft_state.get(HARD_STATE)?;
+        let log_entry = self.log.last()?;
         let sm = self.state.read().await;
-        match &mut *hs {
-            Some(inner) => {
+        match hard_state {
+            Some(hard_state) => {
                 let (last_log_index, last_log_term) = match log_entry {
                     Some((_, value_vec)) => {
                         let value =
@@ -94,13 +496,13 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
                     last_log_index,
                     last_log_term,
                     last_applied_log,
-                    hard_state: inner.clone(),
+                    hard_state: serde_json::from_slice(&hard_state)?,
                     membership,
                 })
             }
             None => {
                 let new = InitialState::new_initial(self.id);
-                *hs = Some(new.hard_state.clone());
+                self.save_hard_state(&new.hard_state).await?;
                 Ok(new)
             }
         }
@@ -108,7 +510,10 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
 
     #[tracing::instrument(level = "trace", skip(self, hs))]
     async fn save_hard_state(&self, hs: &HardState) -> Result<()> {
-        *self.hard_state.write().await = Some(hs.clone());
+        // the term and vote must survive a restart, so they are synced to disk before returning
+        self.raft_state
+            .insert(HARD_STATE, serde_json::to_vec(hs)?)?;
+        self.db.flush_async().await?;
         Ok(())
     }
 
@@ -122,7 +527,7 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
         let start = start.to_be_bytes().to_vec();
         let stop = stop.to_be_bytes().to_vec();
         let mut entries = vec![];
-        for value_vec in self.db.range(start..stop).values() {
+        for value_vec in self.log.range(start..stop).values() {
             let value = serde_json::from_slice::<Entry<ScheduleData>>(value_vec?.as_ref())?;
             entries.push(value);
         }
@@ -144,7 +549,7 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
             for key in start..*stop {
                 batch.remove(key.to_be_bytes().to_vec());
             }
-            self.db
+            self.log
                 .transaction(|db| {
                     db.apply_batch(&batch)?;
                     // todo: is it necessary to flush here?
@@ -156,11 +561,9 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
                 })?;
             return Ok(());
         }
-        // Else, just split off the remainder.
-        let mut current_key = start.to_be_bytes().to_vec();
-        while let Some((key_vec, _)) = self.db.get_gt(current_key.clone())? {
-            self.db.remove(key_vec.to_vec())?;
-            current_key = key_vec.to_vec();
+        // Else, just split off the remainder, `start` included.
+        for key in self.log.range(start.to_be_bytes()..).keys() {
+            self.log.remove(key?)?;
         }
         Ok(())
     }
@@ -169,9 +572,9 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
     async fn append_entry_to_log(&self, entry: &Entry<ScheduleData>) -> Result<()> {
         let key = entry.index.to_be_bytes().to_vec();
         let value = serde_json::to_vec(entry)?;
-        self.db
+        self.log
             .transaction(|db| {
-                if let None = db.insert(key.as_slice(), value.as_slice())? {
+                if db.insert(key.as_slice(), value.as_slice())?.is_some() {
                     return Err(ConflictableTransactionError::Abort(anyhow::anyhow!(
                         "Key {} already exists.",
                         entry.index
@@ -195,7 +598,7 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
             );
         }
         // insert entries as batch in transaction
-        self.db
+        self.log
             .transaction(|db| {
                 db.apply_batch(&batch)?;
                 db.flush();
@@ -211,23 +614,37 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
         data: &ScheduleData,
     ) -> Result<ScheduleEventResponse> {
         let mut sm = self.state.write().await;
-        sm.last_applied_log = *index;
-        todo!("apply this to state machine, store in database")
+        let response = self.apply(*index, data).await?;
+        self.save_last_applied(&mut sm, *index)?;
+        Ok(response)
     }
 
     #[tracing::instrument(level = "trace", skip(self, entries))]
     async fn replicate_to_state_machine(&self, entries: &[(&u64, &ScheduleData)]) -> Result<()> {
-        todo!()
+        let mut sm = self.state.write().await;
+        for (index, data) in entries {
+            self.apply(**index, data).await?;
+            self.save_last_applied(&mut sm, **index)?;
+        }
+        self.db.flush_async().await?;
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn do_log_compaction(&self) -> Result<CurrentSnapshotData<Self::Snapshot>> {
-        todo!()
+        let meta = self.compact().await?;
+        Ok(CurrentSnapshotData {
+            term: meta.term,
+            index: meta.index,
+            snapshot: Box::new(ClusterSnapshot::open(self.snapshots.path(&meta.id)).await?),
+            membership: meta.membership,
+        })
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn create_snapshot(&self) -> Result<(String, Box<Self::Snapshot>)> {
-        todo!()
+        let snapshot = ClusterSnapshot::create(self.snapshots.part_path()).await?;
+        Ok((String::new(), Box::new(snapshot)))
     }
 
     #[tracing::instrument(level = "trace", skip(self, snapshot))]
@@ -236,14 +653,281 @@ impl RaftStorage<ScheduleData, ScheduleEventResponse> for SchedulerRaftStorage {
         index: u64,
         term: u64,
         delete_through: Option<u64>,
-        id: String,
+        _id: String,
         snapshot: Box<Self::Snapshot>,
     ) -> Result<()> {
-        todo!()
+        let part = snapshot.finish().await?;
+        let p = part.clone();
+        let meta = tokio::task::spawn_blocking(move || snapshot::verify(&p)).await??;
+        if meta.index != index || meta.term != term {
+            tokio::fs::remove_file(&part).await?;
+            return Err(anyhow::anyhow!(
+                "snapshot {} covers {}/{}, expected {}/{}",
+                meta.id,
+                meta.term,
+                meta.index,
+                term,
+                index
+            ));
+        }
+        // snapshots keep the id given by the member that took them
+        let path = self.snapshots.path(&meta.id);
+        tokio::fs::rename(&part, &path).await?;
+
+        let mut sm = self.state.write().await;
+        let data = self.db.clone();
+        let schedules = self.schedules.clone();
+        tokio::task::spawn_blocking(move || {
+            snapshot::restore(&data, &path)?;
+            schedules.recount()?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await??;
+        self.save_last_applied(&mut sm, index)?;
+        // the log entries the snapshot covers are dropped below, it can't be applied again
+        self.db.flush_async().await?;
+        drop(sm);
+        for (id, address) in stored_peers(&self.peers)? {
+            self.network.set_address(id, address);
+        }
+        self.publish_ownership()?;
+
+        self.compact_log(delete_through, &meta)?;
+        self.set_current_snapshot(meta).await?;
+        tracing::info!(index, term, "Snapshot installed");
+        Ok(())
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     async fn get_current_snapshot(&self) -> Result<Option<CurrentSnapshotData<Self::Snapshot>>> {
-        todo!()
+        // Raft only sends snapshots within half the compaction interval of the last entry, and
+        // only compacts once the interval is reached. Members needing a snapshot in between
+        // would wait for the next compaction, so they get a fresh one.
+        let last_applied = self.state.read().await.last_applied_log;
+        let stale = self
+            .current_snapshot
+            .read()
+            .await
+            .as_ref()
+            .map(|s| last_applied.saturating_sub(s.index) > self.snapshot_logs / 2)
+            .unwrap_or(false);
+        if stale {
+            self.compact().await?;
+        }
+        let current = self.current_snapshot.read().await;
+        match &*current {
+            Some(meta) => Ok(Some(CurrentSnapshotData {
+                term: meta.term,
+                index: meta.index,
+                membership: meta.membership.clone(),
+                snapshot: Box::new(ClusterSnapshot::open(self.snapshots.path(&meta.id)).await?),
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::dto::UpdateScheduleDto;
+    use crate::cluster::msg::TickClaim;
+
+    fn storage(
+        id: NodeId,
+    ) -> (
+        SchedulerRaftStorage,
+        mpsc::UnboundedReceiver<ScheduleEventResponse>,
+    ) {
+        // every test points snapshots at the same directory, out of the source tree
+        let snapshots =
+            std::env::temp_dir().join(format!("schedule-rs-store-{}", std::process::id()));
+        std::env::set_var("SCHEDULERS_CLUSTER_SNAPSHOT_PATH", snapshots);
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let network = Arc::new(ClusterNetwork::new(HashMap::new(), None));
+        let (events, received) = mpsc::unbounded_channel();
+        let storage = SchedulerRaftStorage::new(
+            id,
+            db.clone(),
+            network,
+            Arc::new(ScheduleRepository::new(&db)),
+            Arc::new(ExecutionRepository::new(&db)),
+            Arc::new(TriggerRepository::new(&db)),
+            events,
+        );
+        (storage, received)
+    }
+
+    fn create(id: &str) -> ScheduleData {
+        let params = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "request": {"url": "http://localhost/", "method": "GET"},
+            "schedule": "0 * * * * *",
+        }))
+        .unwrap();
+        ScheduleData::Create(params, chrono::Utc::now())
+    }
+
+    fn claim(run: u64, node_id: NodeId, token: u64) -> ScheduleData {
+        let now = chrono::Utc::now();
+        ScheduleData::ClaimTick(
+            TickClaim {
+                schedule_id: "a".to_string(),
+                run,
+                at: now,
+                node_id,
+                token,
+            },
+            now,
+        )
+    }
+
+    fn assign_all(storage: &SchedulerRaftStorage, node_id: NodeId, token: u64) -> ScheduleData {
+        let owner = ShardOwner { node_id, token };
+        ScheduleData::AssignShards((0..storage.shards).map(|s| (s, owner)).collect())
+    }
+
+    #[tokio::test]
+    async fn claims_ticks_with_the_token_of_the_owner() {
+        let (storage, _events) = storage(0);
+        storage.apply(1, &create("a")).await.unwrap();
+        storage.apply(2, &assign_all(&storage, 1, 1)).await.unwrap();
+
+        let fenced = storage.apply(3, &claim(1, 0, 1)).await.unwrap();
+        assert!(matches!(fenced, ScheduleEventResponse::TickFenced(_)));
+        let claimed = storage.apply(4, &claim(1, 1, 1)).await.unwrap();
+        assert!(matches!(claimed, ScheduleEventResponse::TickClaimed(_)));
+        let again = storage.apply(5, &claim(1, 1, 1)).await.unwrap();
+        assert!(matches!(
+            again,
+            ScheduleEventResponse::TickAlreadyClaimed(_)
+        ));
+        let runs = storage
+            .schedules
+            .get::<ScheduleDocument>("a".to_string())
+            .await
+            .unwrap()
+            .unwrap()
+            .runs;
+        assert_eq!(runs, 1);
+
+        // the former owner can't claim once the shard changed hands
+        storage.apply(6, &assign_all(&storage, 2, 2)).await.unwrap();
+        let fenced = storage.apply(7, &claim(2, 1, 1)).await.unwrap();
+        assert!(matches!(fenced, ScheduleEventResponse::TickFenced(_)));
+        let claimed = storage.apply(8, &claim(2, 2, 2)).await.unwrap();
+        assert!(matches!(claimed, ScheduleEventResponse::TickClaimed(_)));
+    }
+
+    #[tokio::test]
+    async fn publishes_owned_shards() {
+        let (storage, _events) = storage(1);
+        let ownership = storage.ownership();
+        assert!(ownership.borrow().owned.is_empty());
+
+        storage
+            .apply(
+                9,
+                &ScheduleData::AssignShards(vec![
+                    (
+                        0,
+                        ShardOwner {
+                            node_id: 1,
+                            token: 1,
+                        },
+                    ),
+                    (
+                        1,
+                        ShardOwner {
+                            node_id: 2,
+                            token: 1,
+                        },
+                    ),
+                    (
+                        2,
+                        ShardOwner {
+                            node_id: 1,
+                            token: 1,
+                        },
+                    ),
+                ]),
+            )
+            .await
+            .unwrap();
+        storage
+            .apply(
+                10,
+                &ScheduleData::AssignShards(vec![
+                    (
+                        2,
+                        ShardOwner {
+                            node_id: 2,
+                            token: 2,
+                        },
+                    ),
+                    (
+                        2,
+                        ShardOwner {
+                            node_id: 1,
+                            token: 3,
+                        },
+                    ),
+                    // decided on a stale view
+                    (
+                        0,
+                        ShardOwner {
+                            node_id: 2,
+                            token: 1,
+                        },
+                    ),
+                ]),
+            )
+            .await
+            .unwrap();
+        let owned = ownership.borrow().owned.clone();
+        assert_eq!(owned, HashMap::from([(0, 1), (2, 3)]));
+    }
+
+    #[tokio::test]
+    async fn applies_entries_again_with_the_same_result() {
+        let (storage, _events) = storage(1);
+        storage.apply(1, &create("a")).await.unwrap();
+
+        let assign = assign_all(&storage, 1, 1);
+        storage.apply(2, &assign).await.unwrap();
+        storage.apply(2, &assign).await.unwrap();
+        assert_eq!(storage.shard_owner(0).unwrap().unwrap().token, 1);
+        let claimed = storage.apply(3, &claim(1, 1, 1)).await.unwrap();
+        assert!(matches!(claimed, ScheduleEventResponse::TickClaimed(_)));
+
+        let params: UpdateScheduleDto = serde_json::from_value(serde_json::json!({
+            "id": "a",
+            "request": {"url": "http://localhost/other", "method": "GET"},
+        }))
+        .unwrap();
+        let update = ScheduleData::Update(params, chrono::Utc::now());
+        storage.apply(4, &update).await.unwrap();
+        storage.apply(4, &update).await.unwrap();
+        let mut schedule = storage
+            .schedules
+            .get::<ScheduleDocument>("a".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(schedule.revision, 4);
+
+        let now = chrono::Utc::now();
+        schedule.expires_at = Some(now);
+        storage.schedules.save(schedule).await.unwrap();
+        storage.apply(5, &create("b")).await.unwrap();
+        let entries = storage.schedules.expired(now, 10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        let expire = ScheduleData::Expire(now, entries);
+        let expired = storage.apply(6, &expire).await.unwrap();
+        assert!(matches!(expired, ScheduleEventResponse::Expired(ids) if ids == ["a"]));
+        let again = storage.apply(6, &expire).await.unwrap();
+        assert!(matches!(again, ScheduleEventResponse::Expired(ids) if ids.is_empty()));
+        let b = storage.schedules.get::<ScheduleDocument>("b".to_string());
+        assert!(b.await.unwrap().is_some());
     }
 }
@@ -1,3 +1,14 @@
+/// Duration in milliseconds from the given variable, panics if it isn't a number.
+fn millis(name: &str, default: u64) -> std::time::Duration {
+    let ms = std::env::var(name)
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| panic!("Invalid {}, should be a number", name))
+        })
+        .unwrap_or(default);
+    std::time::Duration::from_millis(ms)
+}
+
 pub mod db {
     pub trait SledConfigExt {
         fn from_env() -> Self;
@@ -8,7 +19,8 @@ pub mod db {
             sled::Config::new()
                 .cache_capacity(cache_capacity())
                 .path(path())
-                .flush_every_ms(flush_every_ms())
+                // flushes are run by the app, so their latency can be observed
+                .flush_every_ms(None)
         }
     }
     #[inline]
@@ -25,11 +37,49 @@ pub mod db {
         std::env::var("SCHEDULERS_DB_PATH").unwrap_or("data".to_string())
     }
 
-    #[inline]
-    fn flush_every_ms() -> Option<u64> {
+    /// Whether captured response bodies are stored gzip compressed, `SCHEDULERS_DB_COMPRESS_BODIES`
+    pub fn compress_bodies() -> bool {
+        std::env::var("SCHEDULERS_DB_COMPRESS_BODIES")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false)
+    }
+
+    /// How long successfully completed one time schedules are kept, `SCHEDULERS_RETENTION_POLICY`
+    /// in days, defaults to 30 days
+    pub fn retention() -> chrono::Duration {
+        let days: i64 = std::env::var("SCHEDULERS_RETENTION_POLICY")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_RETENTION_POLICY, should be a number of days")
+            })
+            .unwrap_or(30);
+        chrono::Duration::days(days)
+    }
+
+    /// Default window of schedule deduplication, `SCHEDULERS_DEDUP_WINDOW`, as a duration
+    /// like `10m`, duplicates are detected while the schedule is pending if unset
+    pub fn dedup_window() -> Option<chrono::Duration> {
+        std::env::var("SCHEDULERS_DEDUP_WINDOW")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .map(|v| {
+                crate::scheduler::ticker::parse_duration(&v)
+                    .unwrap_or_else(|e| panic!("Invalid SCHEDULERS_DEDUP_WINDOW: {}", e))
+            })
+    }
+
+    /// Interval between removals of expired schedules, `SCHEDULERS_RETENTION_SWEEP_INTERVAL_MS`,
+    /// defaults to 1 second
+    pub fn retention_sweep_interval() -> std::time::Duration {
+        super::millis("SCHEDULERS_RETENTION_SWEEP_INTERVAL_MS", 1_000)
+    }
+
+    /// Interval between flushes of the database, `SCHEDULERS_DB_FLUSH_EVERY_MS`, the database is
+    /// only flushed on shutdown if unset
+    pub fn flush_every_ms() -> Option<u64> {
         if let Ok(flush_every_ms) = std::env::var("SCHEDULERS_DB_FLUSH_EVERY_MS") {
             if flush_every_ms.is_empty() {
-                return None;
+                None
             } else {
                 let val: u64 = flush_every_ms
                     .parse()
@@ -42,6 +92,188 @@ pub mod db {
     }
 }
 
+pub mod dispatcher {
+    use super::millis;
+    use crate::db::schema::Priority;
+    use crate::scheduler::limiter::{LimiterGroup, LimiterSpec};
+    use crate::scheduler::queue::parse_reservation;
+
+    /// Limiter groups from `SCHEDULERS_RATE_LIMITS`, entries are separated by `;`,
+    /// e.g. `host:api.partner.com=20/s,5;tag:analytics=100/m;host:*=50/s`
+    pub fn rate_limits() -> Vec<(LimiterGroup, LimiterSpec)> {
+        let limits = std::env::var("SCHEDULERS_RATE_LIMITS").unwrap_or_default();
+        limits
+            .split(';')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|entry| {
+                let (group, spec) = entry
+                    .split_once('=')
+                    .unwrap_or_else(|| panic!("Invalid SCHEDULERS_RATE_LIMITS entry {}", entry));
+                let group: LimiterGroup = group
+                    .trim()
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid SCHEDULERS_RATE_LIMITS: {}", e));
+                let spec: LimiterSpec = spec
+                    .trim()
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid SCHEDULERS_RATE_LIMITS: {}", e));
+                (group, spec)
+            })
+            .collect()
+    }
+
+    /// Maximum number of requests in flight, `SCHEDULERS_DISPATCH_WORKERS`, defaults to 256
+    pub fn workers() -> usize {
+        std::env::var("SCHEDULERS_DISPATCH_WORKERS")
+            .map(|v| match v.parse() {
+                Ok(workers) if workers > 0 => workers,
+                _ => panic!("Invalid SCHEDULERS_DISPATCH_WORKERS, should be a positive number"),
+            })
+            .unwrap_or(256)
+    }
+
+    /// Workers reserved per priority class from `SCHEDULERS_DISPATCH_RESERVED`, entries are
+    /// separated by `,`, e.g. `critical=16,high=8`. Reservations can't exceed the workers.
+    pub fn reserved() -> Vec<(Priority, usize)> {
+        let reserved = std::env::var("SCHEDULERS_DISPATCH_RESERVED").unwrap_or_default();
+        let reserved: Vec<(Priority, usize)> = reserved
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                parse_reservation(entry)
+                    .unwrap_or_else(|e| panic!("Invalid SCHEDULERS_DISPATCH_RESERVED: {}", e))
+            })
+            .collect();
+        if reserved.iter().map(|(_, r)| r).sum::<usize>() > workers() {
+            panic!("Invalid SCHEDULERS_DISPATCH_RESERVED, reservations exceed dispatch workers");
+        }
+        reserved
+    }
+
+    /// Default connect timeout, `SCHEDULERS_HTTP_CONNECT_TIMEOUT_MS`, defaults to 5 seconds
+    pub fn connect_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_HTTP_CONNECT_TIMEOUT_MS", 5_000)
+    }
+
+    /// Default total request timeout, `SCHEDULERS_HTTP_TIMEOUT_MS`, defaults to 30 seconds
+    pub fn timeout() -> std::time::Duration {
+        millis("SCHEDULERS_HTTP_TIMEOUT_MS", 30_000)
+    }
+
+    /// Default number of redirects to follow, `SCHEDULERS_HTTP_MAX_REDIRECTS`, defaults to 10
+    pub fn max_redirects() -> usize {
+        std::env::var("SCHEDULERS_HTTP_MAX_REDIRECTS")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_HTTP_MAX_REDIRECTS, should be a number")
+            })
+            .unwrap_or(10)
+    }
+
+    /// Directory holding CA bundles as `ca/<name>.pem` and client identities as
+    /// `identity/<name>.pem` (certificate and private key), `SCHEDULERS_TLS_DIR`, defaults to `tls`
+    pub fn tls_dir() -> std::path::PathBuf {
+        std::env::var("SCHEDULERS_TLS_DIR")
+            .unwrap_or("tls".to_string())
+            .into()
+    }
+
+    /// Delays in seconds from the given variable, e.g. `1s,5s,30s`, panics if one isn't a number.
+    fn delays(name: &str, default: &str) -> Vec<u32> {
+        std::env::var(name)
+            .unwrap_or(default.to_string())
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.trim_end_matches('s').parse().unwrap_or_else(|_| {
+                    panic!("Invalid {}, should be seconds like 1s,5s,30s", name)
+                })
+            })
+            .collect()
+    }
+
+    /// Default delays between retries of a failed execution, `SCHEDULERS_RETRY_INTERVAL`,
+    /// e.g. `1s,5s,30s` retries three times. Defaults to no retries.
+    pub fn retry() -> Vec<u32> {
+        delays("SCHEDULERS_RETRY_INTERVAL", "")
+    }
+
+    /// Timeout of callback requests, `SCHEDULERS_CALLBACK_TIMEOUT` in seconds, defaults to 10 seconds
+    pub fn callback_timeout() -> std::time::Duration {
+        let secs = std::env::var("SCHEDULERS_CALLBACK_TIMEOUT")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_CALLBACK_TIMEOUT, should be a number")
+            })
+            .unwrap_or(10);
+        std::time::Duration::from_secs(secs)
+    }
+
+    /// Delays between retries of a failed callback, `SCHEDULERS_CALLBACK_RETRY_INTERVAL`,
+    /// defaults to `1s,5s,30s`
+    pub fn callback_retry() -> Vec<u32> {
+        delays("SCHEDULERS_CALLBACK_RETRY_INTERVAL", "1s,5s,30s")
+    }
+
+    /// Whether response bodies are captured unless schedule says otherwise, `SCHEDULERS_CAPTURE_BODY`
+    pub fn capture_body() -> bool {
+        std::env::var("SCHEDULERS_CAPTURE_BODY")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(true)
+    }
+
+    /// Default maximum number of captured body bytes, `SCHEDULERS_CAPTURE_MAX_BODY_BYTES`,
+    /// defaults to 64KiB
+    pub fn capture_max_body_bytes() -> usize {
+        std::env::var("SCHEDULERS_CAPTURE_MAX_BODY_BYTES")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_CAPTURE_MAX_BODY_BYTES, should be a number")
+            })
+            .unwrap_or(65536)
+    }
+
+    /// Maximum number of body bytes read to evaluate `json_path` and `body_regex` assertions,
+    /// `SCHEDULERS_ASSERT_MAX_BODY_BYTES`, defaults to 1MiB
+    pub fn assert_max_body_bytes() -> usize {
+        std::env::var("SCHEDULERS_ASSERT_MAX_BODY_BYTES")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_ASSERT_MAX_BODY_BYTES, should be a number")
+            })
+            .unwrap_or(1024 * 1024)
+    }
+
+    /// Default response headers to capture, `SCHEDULERS_CAPTURE_HEADERS`, comma separated,
+    /// defaults to `content-type`
+    pub fn capture_headers() -> Vec<String> {
+        std::env::var("SCHEDULERS_CAPTURE_HEADERS")
+            .unwrap_or("content-type".to_string())
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Forces every schedule into dry-run mode, `SCHEDULERS_DRY_RUN`, e.g. for staging
+    /// environments restored from production data
+    pub fn dry_run() -> bool {
+        std::env::var("SCHEDULERS_DRY_RUN")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false)
+    }
+
+    /// Whether schedules may disable certificate verification, `SCHEDULERS_ALLOW_INSECURE_TLS`
+    pub fn allow_insecure_tls() -> bool {
+        std::env::var("SCHEDULERS_ALLOW_INSECURE_TLS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false)
+    }
+}
+
 pub mod web {
     use actix_http::body::MessageBody;
     use actix_http::{Request, Response};
@@ -100,3 +332,274 @@ pub mod web {
         }
     }
 }
+
+pub mod telemetry {
+    use std::str::FromStr;
+
+    /// Where spans are exported
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Exporter {
+        /// Spans are only logged
+        None,
+        /// Spans are printed to stdout as JSON lines
+        Stdout,
+        /// Jaeger agent over UDP
+        Jaeger,
+        /// OTLP over gRPC
+        OtlpGrpc,
+        /// OTLP over HTTP with protobuf payloads
+        OtlpHttp,
+    }
+
+    impl FromStr for Exporter {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "none" => Ok(Exporter::None),
+                "stdout" => Ok(Exporter::Stdout),
+                "jaeger" => Ok(Exporter::Jaeger),
+                "otlp" | "otlp-grpc" => Ok(Exporter::OtlpGrpc),
+                "otlp-http" => Ok(Exporter::OtlpHttp),
+                _ => Err(format!(
+                    "unknown exporter {}, expected none, stdout, jaeger, otlp-grpc or otlp-http",
+                    s
+                )),
+            }
+        }
+    }
+
+    /// Span exporter, `SCHEDULERS_TRACE_EXPORTER`, defaults to the Jaeger agent
+    pub fn exporter() -> Exporter {
+        std::env::var("SCHEDULERS_TRACE_EXPORTER")
+            .map(|v| {
+                v.parse()
+                    .unwrap_or_else(|e| panic!("Invalid SCHEDULERS_TRACE_EXPORTER: {}", e))
+            })
+            .unwrap_or(Exporter::Jaeger)
+    }
+
+    /// Exporter endpoint, `SCHEDULERS_TRACE_ENDPOINT`, e.g. `localhost:6831` for the Jaeger agent
+    /// or `http://collector:4317` for OTLP. Exporters fall back to their `OTEL_EXPORTER_*`
+    /// variables if unset
+    pub fn endpoint() -> Option<String> {
+        std::env::var("SCHEDULERS_TRACE_ENDPOINT")
+            .ok()
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Ratio of traces sampled, `SCHEDULERS_TRACE_SAMPLE_RATIO`, between 0 and 1, defaults to 1.
+    /// Traces continued from a sampled parent are always sampled
+    pub fn sample_ratio() -> f64 {
+        let ratio: f64 = std::env::var("SCHEDULERS_TRACE_SAMPLE_RATIO")
+            .map(|v| {
+                v.parse()
+                    .expect("Invalid SCHEDULERS_TRACE_SAMPLE_RATIO, should be a number")
+            })
+            .unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&ratio) {
+            panic!("Invalid SCHEDULERS_TRACE_SAMPLE_RATIO, should be between 0 and 1");
+        }
+        ratio
+    }
+
+    /// Resource attributes added to every span, `SCHEDULERS_TRACE_RESOURCE_ATTRIBUTES`, as `,`
+    /// separated `<key>=<value>` pairs, e.g. `deployment.environment=prod,team=platform`
+    pub fn resource_attributes() -> Vec<(String, String)> {
+        let attributes = std::env::var("SCHEDULERS_TRACE_RESOURCE_ATTRIBUTES").unwrap_or_default();
+        attributes
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (key, value) = s.split_once('=').unwrap_or_else(|| {
+                    panic!(
+                        "Invalid SCHEDULERS_TRACE_RESOURCE_ATTRIBUTES: {} should be <key>=<value>",
+                        s
+                    )
+                });
+                (key.trim().to_string(), value.trim().to_string())
+            })
+            .collect()
+    }
+}
+
+pub mod cluster {
+    use super::millis;
+    use async_raft::NodeId;
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+
+    /// Id of this node, `SCHEDULERS_CLUSTER_NODE_ID`. If unset while `SCHEDULERS_CLUSTER_PEERS`
+    /// is, the id is the ordinal ending `SCHEDULERS_HTTP_HOSTNAME`, e.g. `2` for the
+    /// StatefulSet pod `schedule-rs-2`. The node runs standalone if neither is set
+    pub fn node_id() -> Option<NodeId> {
+        let id = std::env::var("SCHEDULERS_CLUSTER_NODE_ID")
+            .ok()
+            .filter(|v| !v.is_empty());
+        if let Some(id) = id {
+            return Some(
+                id.parse()
+                    .expect("Invalid SCHEDULERS_CLUSTER_NODE_ID, should be a number"),
+            );
+        }
+        if peers().is_empty() {
+            return None;
+        }
+        let hostname = std::env::var("SCHEDULERS_HTTP_HOSTNAME").expect(
+            "SCHEDULERS_CLUSTER_NODE_ID or SCHEDULERS_HTTP_HOSTNAME should be set with SCHEDULERS_CLUSTER_PEERS",
+        );
+        Some(ordinal(&hostname).unwrap_or_else(|| {
+            panic!(
+                "Invalid SCHEDULERS_HTTP_HOSTNAME: {} should end with a number, e.g. schedule-rs-0",
+                hostname
+            )
+        }))
+    }
+
+    /// Number after the last `-` of the first label of a hostname.
+    fn ordinal(hostname: &str) -> Option<NodeId> {
+        let name = hostname.split('.').next()?;
+        name.rsplit_once('-')?.1.parse().ok()
+    }
+
+    /// Members the cluster is initialized with and their addresses, `SCHEDULERS_CLUSTER_PEERS`,
+    /// as `,` separated `<node id>=<url>` entries pointing at the cluster listener, e.g.
+    /// `0=http://schedule-rs-0.schedule-rs:8081,1=http://schedule-rs-1.schedule-rs:8081`
+    pub fn peers() -> HashMap<NodeId, String> {
+        let peers = std::env::var("SCHEDULERS_CLUSTER_PEERS").unwrap_or_default();
+        peers
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (id, url) = s.split_once('=').unwrap_or_else(|| {
+                    panic!(
+                        "Invalid SCHEDULERS_CLUSTER_PEERS: {} should be <node id>=<url>",
+                        s
+                    )
+                });
+                let id = id.trim().parse().unwrap_or_else(|_| {
+                    panic!("Invalid SCHEDULERS_CLUSTER_PEERS: invalid node id in {}", s)
+                });
+                (id, url.trim().trim_end_matches('/').to_string())
+            })
+            .collect()
+    }
+
+    /// Directory of Raft snapshots, `SCHEDULERS_CLUSTER_SNAPSHOT_PATH`, defaults to `snapshots`
+    pub fn snapshot_path() -> String {
+        std::env::var("SCHEDULERS_CLUSTER_SNAPSHOT_PATH").unwrap_or("snapshots".to_string())
+    }
+
+    /// Number of log entries since the last snapshot that triggers a new one, compacting the
+    /// log, `SCHEDULERS_CLUSTER_SNAPSHOT_LOGS`, defaults to 5000
+    pub fn snapshot_logs() -> u64 {
+        std::env::var("SCHEDULERS_CLUSTER_SNAPSHOT_LOGS")
+            .map(|v| match v.parse() {
+                Ok(logs) if logs > 0 => logs,
+                _ => {
+                    panic!("Invalid SCHEDULERS_CLUSTER_SNAPSHOT_LOGS, should be a positive number")
+                }
+            })
+            .unwrap_or(5000)
+    }
+
+    /// Whether snapshots include the execution history, `SCHEDULERS_CLUSTER_SNAPSHOT_HISTORY`.
+    /// Members installing a snapshot replace their history with the leader's
+    pub fn snapshot_history() -> bool {
+        std::env::var("SCHEDULERS_CLUSTER_SNAPSHOT_HISTORY")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false)
+    }
+
+    /// Address of the listener serving Raft RPCs and forwarded writes to other members,
+    /// `SCHEDULERS_CLUSTER_LISTEN`, defaults to `0.0.0.0:8081`
+    pub fn listen() -> SocketAddr {
+        let listen = std::env::var("SCHEDULERS_CLUSTER_LISTEN")
+            .unwrap_or_else(|_| format!("{}:{}", Ipv4Addr::UNSPECIFIED, 8081));
+        listen
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .unwrap_or_else(|| panic!("Invalid SCHEDULERS_CLUSTER_LISTEN {}", listen))
+    }
+
+    /// Secret shared by the members, `SCHEDULERS_CLUSTER_SECRET`. Requests between members
+    /// carry it as a bearer token and requests without it are rejected. Unauthenticated if unset,
+    /// in which case membership changes and the cluster status aren't served
+    pub fn secret() -> Option<String> {
+        std::env::var("SCHEDULERS_CLUSTER_SECRET")
+            .ok()
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Timeout of append entries and vote requests to other members,
+    /// `SCHEDULERS_CLUSTER_RPC_TIMEOUT_MS`, defaults to 500ms
+    pub fn rpc_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_RPC_TIMEOUT_MS", 500)
+    }
+
+    /// Timeout of sending a snapshot chunk to another member,
+    /// `SCHEDULERS_CLUSTER_SNAPSHOT_TIMEOUT_MS`, defaults to 30 seconds
+    pub fn snapshot_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_SNAPSHOT_TIMEOUT_MS", 30_000)
+    }
+
+    /// Interval of the leader's heartbeats, `SCHEDULERS_CLUSTER_HEARTBEAT_MS`, defaults to 100ms
+    pub fn heartbeat_interval() -> u64 {
+        millis("SCHEDULERS_CLUSTER_HEARTBEAT_MS", 100).as_millis() as u64
+    }
+
+    /// Range of the randomized election timeout, `SCHEDULERS_CLUSTER_ELECTION_TIMEOUT_MS` as
+    /// `<min>-<max>`, defaults to `500-1000`. It should be well above the heartbeat interval
+    pub fn election_timeout() -> (u64, u64) {
+        let timeout = std::env::var("SCHEDULERS_CLUSTER_ELECTION_TIMEOUT_MS")
+            .unwrap_or("500-1000".to_string());
+        timeout
+            .split_once('-')
+            .and_then(|(min, max)| Some((min.trim().parse().ok()?, max.trim().parse().ok()?)))
+            .filter(|(min, max)| min < max)
+            .unwrap_or_else(|| {
+                panic!(
+                    "Invalid SCHEDULERS_CLUSTER_ELECTION_TIMEOUT_MS {}, should be <min>-<max>",
+                    timeout
+                )
+            })
+    }
+
+    /// Number of shards schedules are spread over, by a hash of their id,
+    /// `SCHEDULERS_CLUSTER_SHARDS`, defaults to 64. Should be the same on every member
+    pub fn shards() -> u32 {
+        std::env::var("SCHEDULERS_CLUSTER_SHARDS")
+            .map(|v| match v.parse() {
+                Ok(shards) if shards > 0 => shards,
+                _ => panic!("Invalid SCHEDULERS_CLUSTER_SHARDS, should be a positive number"),
+            })
+            .unwrap_or(64)
+    }
+
+    /// How long a member may not answer the leader before its shards are reassigned,
+    /// `SCHEDULERS_CLUSTER_OWNER_TIMEOUT_MS`, defaults to 3000ms
+    pub fn owner_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_OWNER_TIMEOUT_MS", 3000)
+    }
+
+    /// How long a linearizable read waits for this node to apply the writes committed before
+    /// it, `SCHEDULERS_CLUSTER_READ_TIMEOUT_MS`, defaults to 5000ms
+    pub fn read_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_READ_TIMEOUT_MS", 5000)
+    }
+
+    /// How long a leader that stepped down waits for the new leader to add it back,
+    /// `SCHEDULERS_CLUSTER_JOIN_TIMEOUT_MS`, defaults to 60 seconds
+    pub fn join_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_JOIN_TIMEOUT_MS", 60_000)
+    }
+
+    /// How long a write waits to be committed before it's answered as unavailable,
+    /// `SCHEDULERS_CLUSTER_WRITE_TIMEOUT_MS`, defaults to 5000ms
+    pub fn write_timeout() -> std::time::Duration {
+        millis("SCHEDULERS_CLUSTER_WRITE_TIMEOUT_MS", 5000)
+    }
+}
@@ -1,19 +1,124 @@
-use crate::api::dto::{CreateScheduleDto, ScheduleDto};
-use crate::db::schema::{ScheduleDocument, ScheduleId};
-use sled::Tree;
+use crate::api::dto::{CreateScheduleDto, OnDuplicate, ScheduleDto, UpdateScheduleDto};
+use crate::config;
+use crate::db::schema::{DedupDocument, RunDocument, ScheduleDocument, ScheduleId, ScheduleStatus};
+use crate::metrics::metrics;
+use crate::scheduler::ticker::parse_duration;
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::{Transactional, Tree};
+use std::ops::Bound;
 use tracing::{span, Level};
 
+mod executions;
 pub(crate) mod schema;
+mod triggers;
+mod workflows;
+
+pub use executions::ExecutionRepository;
+pub use triggers::{trigger_id, TriggerRepository};
+pub use workflows::WorkflowRepository;
 
 pub struct ScheduleRepository {
     schedules: Tree,
+    /// Index of schedules to remove, keyed by expiry instant and schedule id
+    expiry: Tree,
+    /// Index of pending schedules by dedup key
+    dedup: Tree,
+}
+
+fn expiry_key(at: &chrono::DateTime<chrono::Utc>, id: &str) -> Vec<u8> {
+    let mut key = (at.timestamp_millis().max(0) as u64).to_be_bytes().to_vec();
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+#[derive(serde::Deserialize)]
+struct StatusOnly {
+    #[serde(default)]
+    status: ScheduleStatus,
+}
+
+/// Status of a stored schedule or workflow, without parsing the rest of the document.
+fn status_of(bytes: &[u8]) -> Option<ScheduleStatus> {
+    serde_json::from_slice::<StatusOnly>(bytes)
+        .ok()
+        .map(|s| s.status)
+}
+
+/// Moves a schedule between the per-status gauges, `None` standing for a missing schedule.
+fn track(from: Option<ScheduleStatus>, to: Option<ScheduleStatus>) {
+    if from == to {
+        return;
+    }
+    let gauges = &metrics().schedules;
+    if let Some(from) = from {
+        gauges.with_label_values(&[from.as_str()]).dec();
+    }
+    if let Some(to) = to {
+        gauges.with_label_values(&[to.as_str()]).inc();
+    }
+}
+
+/// Ids of active documents among a page of a tree, see [page_active_ids].
+#[derive(Debug, Default)]
+pub(crate) struct IdPage {
+    pub ids: Vec<String>,
+    /// Last id read, the next page starts after it. `None` once the whole tree was read
+    pub next: Option<String>,
+}
+
+/// Reads up to `page` documents of a tree keyed by id, starting after the given id, and returns
+/// the ids of the active ones. Completed, failed and paused documents kept until their
+/// retention ends are skipped.
+pub(crate) fn page_active_ids(
+    tree: &Tree,
+    after: Option<String>,
+    page: usize,
+) -> std::io::Result<IdPage> {
+    let entries = match after {
+        Some(after) => {
+            tree.range::<&[u8], _>((Bound::Excluded(after.as_bytes()), Bound::Unbounded))
+        }
+        None => tree.iter(),
+    };
+    let mut ids = Vec::new();
+    let mut read = 0;
+    let mut last = None;
+    for entry in entries.take(page) {
+        let (key, value) = entry?;
+        let id = String::from_utf8_lossy(&key).into_owned();
+        read += 1;
+        // unreadable documents are left to their actor to report
+        let active = status_of(&value).map(|s| s.is_active()).unwrap_or(true);
+        if active {
+            ids.push(id.clone());
+        }
+        last = Some(id);
+    }
+    Ok(IdPage {
+        ids,
+        next: last.filter(|_| read == page),
+    })
+}
+
+fn transaction_error<E: std::fmt::Debug>(
+    e: sled::transaction::TransactionError<E>,
+) -> std::io::Error {
+    std::io::Error::other(format!("{:?}", e))
 }
 
 impl ScheduleRepository {
+    /// Trees holding schedules and their indices
+    pub const TREES: [&'static str; 3] = ["schedules", "schedule_expiry", "schedule_dedup"];
+
     pub fn new(db: &sled::Db) -> Self {
-        Self {
-            schedules: db.open_tree("schedules").unwrap(),
-        }
+        let [schedules, expiry, dedup] = Self::TREES;
+        let repo = Self {
+            schedules: db.open_tree(schedules).unwrap(),
+            expiry: db.open_tree(expiry).unwrap(),
+            dedup: db.open_tree(dedup).unwrap(),
+        };
+        repo.recount().unwrap();
+        repo
     }
 
     #[tracing::instrument(skip(self))]
@@ -38,6 +143,23 @@ impl ScheduleRepository {
         .await?
     }
 
+    /// Returns the ids of the active schedules among the `page` schedules after the given id,
+    /// in order, reading only their status.
+    #[tracing::instrument(skip(self))]
+    pub async fn active_ids(
+        &self,
+        after: Option<ScheduleId>,
+        page: usize,
+    ) -> std::io::Result<IdPage> {
+        let schedules = self.schedules.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.ids", page = %page);
+            let _enter = span.enter();
+            page_active_ids(&schedules, after, page)
+        })
+        .await?
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn get<T>(&self, id: ScheduleId) -> std::io::Result<Option<T>>
     where
@@ -60,15 +182,264 @@ impl ScheduleRepository {
         .await?
     }
 
-    #[tracing::instrument(skip(self))]
+    /// Sets the per-status gauges from the stored schedules, which writes keep up to date
+    /// afterwards. Scans all schedules, done when the database is opened or replaced.
+    pub fn recount(&self) -> std::io::Result<()> {
+        let mut counts = [0; 5];
+        for schedule in self.schedules.iter().values() {
+            if let Some(status) = status_of(&schedule?) {
+                counts[status as usize] += 1;
+            }
+        }
+        for (status, count) in ScheduleStatus::ALL.iter().zip(counts) {
+            metrics()
+                .schedules
+                .with_label_values(&[status.as_str()])
+                .set(count);
+        }
+        Ok(())
+    }
+
+    /// Stores the schedule, schedules with `expires_at` are indexed for removal in the same
+    /// transaction.
+    #[tracing::instrument(skip(self, schedule), fields(id = %schedule.id))]
+    pub async fn save(&self, schedule: ScheduleDocument) -> std::io::Result<()> {
+        let schedules = self.schedules.clone();
+        let expiry = self.expiry.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.save", id = %schedule.id);
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&schedule)?;
+            let previous = match schedule.expires_at {
+                Some(expires_at) => {
+                    let key = expiry_key(&expires_at, &schedule.id);
+                    (&schedules, &expiry)
+                        .transaction(|(schedules, expiry)| {
+                            let previous =
+                                schedules.insert(schedule.id.as_str(), bytes.as_slice())?;
+                            expiry.insert(key.as_slice(), schedule.id.as_str())?;
+                            Ok::<_, sled::transaction::ConflictableTransactionError>(previous)
+                        })
+                        .map_err(transaction_error)?
+                }
+                None => schedules.insert(schedule.id.as_str(), bytes)?,
+            };
+            track(
+                previous.as_deref().and_then(status_of),
+                Some(schedule.status),
+            );
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Stores a schedule created by a run, e.g. an inline follow-up. Returns false without
+    /// storing it if a schedule with its id exists.
+    #[tracing::instrument(skip(self, schedule), fields(id = %schedule.id))]
+    pub async fn create_follow_up(&self, schedule: ScheduleDocument) -> std::io::Result<bool> {
+        let schedules = self.schedules.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.create_follow_up", id = %schedule.id);
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&schedule)?;
+            let created = schedules
+                .compare_and_swap(schedule.id.as_str(), None as Option<&[u8]>, Some(bytes))?
+                .is_ok();
+            if created {
+                track(None, Some(schedule.status));
+            }
+            Ok(created)
+        })
+        .await?
+    }
+
+    /// Stores the outcome of a run on its schedule, updating only the run fields. Returns false
+    /// without storing anything if the schedule is gone, its definition was replaced since the
+    /// run started, or a later run was recorded.
+    #[tracing::instrument(skip(self, run), fields(id = %run.schedule_id, run = run.run))]
+    pub async fn record_run(
+        &self,
+        run: RunDocument,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> std::io::Result<bool> {
+        let schedules = self.schedules.clone();
+        let expiry = self.expiry.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.record_run", id = %run.schedule_id);
+            let _enter = span.enter();
+            let recorded = (&schedules, &expiry)
+                .transaction(|(schedules, expiry)| {
+                    let mut schedule = match schedules
+                        .get(run.schedule_id.as_bytes())?
+                        .and_then(|s| serde_json::from_slice::<ScheduleDocument>(&s).ok())
+                    {
+                        Some(schedule)
+                            if schedule.revision == run.revision && schedule.runs <= run.run =>
+                        {
+                            schedule
+                        }
+                        _ => return Ok(None),
+                    };
+                    let previous = schedule.status;
+                    if let Some(expires_at) = &schedule.expires_at {
+                        expiry.remove(expiry_key(expires_at, &schedule.id))?;
+                    }
+                    schedule.runs = run.run;
+                    schedule.last_run = Some(run.at);
+                    schedule.status = run.status;
+                    schedule.expires_at = run.expires_at;
+                    schedule.updated_at = now;
+                    if let Some(expires_at) = &schedule.expires_at {
+                        expiry
+                            .insert(expiry_key(expires_at, &schedule.id), schedule.id.as_str())?;
+                    }
+                    let bytes = serde_json::to_vec(&schedule)
+                        .map_err(|e| ConflictableTransactionError::Abort(e.into()))?;
+                    schedules.insert(schedule.id.as_bytes(), bytes)?;
+                    Ok::<_, ConflictableTransactionError<std::io::Error>>(Some((
+                        previous,
+                        schedule.status,
+                    )))
+                })
+                .map_err(|e| match e {
+                    TransactionError::Abort(e) => e,
+                    TransactionError::Storage(e) => e.into(),
+                })?;
+            Ok(match recorded {
+                Some((from, to)) => {
+                    track(Some(from), Some(to));
+                    true
+                }
+                None => false,
+            })
+        })
+        .await?
+    }
+
+    /// Removes up to `limit` schedules that expired before `now`, returns their ids.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn delete_expired(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> std::io::Result<Vec<ScheduleId>> {
+        let entries = self.expired(now, limit).await?;
+        self.delete_expired_entries(now, entries).await
+    }
+
+    /// Returns up to `limit` entries of the expiry index due before `now`, as the expiry
+    /// instant and the schedule id.
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub async fn expired(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        limit: usize,
+    ) -> std::io::Result<Vec<(chrono::DateTime<chrono::Utc>, ScheduleId)>> {
+        let expiry = self.expiry.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::DEBUG, "schedules.expired");
+            let _enter = span.enter();
+            let end = ((now.timestamp_millis().max(0) as u64) + 1).to_be_bytes();
+            let mut entries = Vec::new();
+            for entry in expiry.range(..end.as_slice()).take(limit) {
+                let (key, id) = entry?;
+                let millis = u64::from_be_bytes(key[..8].try_into().unwrap());
+                let at = chrono::TimeZone::timestamp_millis_opt(&chrono::Utc, millis as i64)
+                    .single()
+                    .unwrap_or(now);
+                entries.push((at, String::from_utf8_lossy(&id).to_string()));
+            }
+            Ok(entries)
+        })
+        .await?
+    }
+
+    /// Removes the given entries of the expiry index along with their schedules, if the
+    /// schedules are still expired by `now`, returns the ids of the removed schedules. Removing
+    /// the same entries again removes nothing more.
+    #[tracing::instrument(level = "debug", skip(self, entries))]
+    pub async fn delete_expired_entries(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        entries: Vec<(chrono::DateTime<chrono::Utc>, ScheduleId)>,
+    ) -> std::io::Result<Vec<ScheduleId>> {
+        let schedules = self.schedules.clone();
+        let expiry = self.expiry.clone();
+        let dedup = self.dedup.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::DEBUG, "schedules.delete_expired");
+            let _enter = span.enter();
+            let mut deleted = Vec::new();
+            for (at, id) in entries {
+                let key = expiry_key(&at, &id);
+                let removed = (&schedules, &expiry, &dedup)
+                    .transaction(|(schedules, expiry, dedup)| {
+                        expiry.remove(key.as_slice())?;
+                        // index entries of schedules saved again with another expiry are stale
+                        let current = schedules
+                            .get(id.as_bytes())?
+                            .and_then(|s| serde_json::from_slice::<ScheduleDocument>(&s).ok())
+                            .filter(|s| s.expires_at.map(|at| at <= now).unwrap_or(false));
+                        let current = match current {
+                            Some(current) => current,
+                            None => return Ok(None),
+                        };
+                        schedules.remove(id.as_bytes())?;
+                        if let Some(dedup_key) = &current.dedup_key {
+                            let holds_key = dedup
+                                .get(dedup_key.as_bytes())?
+                                .and_then(|e| serde_json::from_slice::<DedupDocument>(&e).ok())
+                                .map(|e| e.schedule_id == current.id)
+                                .unwrap_or(false);
+                            if holds_key {
+                                dedup.remove(dedup_key.as_bytes())?;
+                            }
+                        }
+                        Ok::<_, sled::transaction::ConflictableTransactionError>(Some(
+                            current.status,
+                        ))
+                    })
+                    .map_err(transaction_error)?;
+                if let Some(status) = removed {
+                    track(Some(status), None);
+                    deleted.push(id);
+                }
+            }
+            Ok(deleted)
+        })
+        .await?
+    }
+
+    /// Stores a new schedule. Schedules with a `dedup_key` are checked against the dedup index
+    /// and indexed in the same transaction, so concurrent creates with one key can't both win.
     pub(crate) async fn create_schedule(
         &self,
         params: CreateScheduleDto,
-    ) -> std::io::Result<ScheduleDto> {
+    ) -> std::io::Result<Created> {
+        self.create_schedule_at(params, chrono::Utc::now()).await
+    }
+
+    /// Stores a new schedule as if created at `now`, replicated creates are applied with the
+    /// instant they were proposed.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn create_schedule_at(
+        &self,
+        params: CreateScheduleDto,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> std::io::Result<Created> {
         let schedules = self.schedules.clone();
+        let dedup = self.dedup.clone();
         let id = params.id.clone();
         tokio::spawn(async move {
             let span = span!(Level::INFO, "schedules.create");
+            let window = match &params.dedup_window {
+                Some(window) => Some(
+                    parse_duration(window)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?,
+                ),
+                None => config::db::dedup_window(),
+            };
+            let on_duplicate = params.on_duplicate.unwrap_or_default();
             let schedule = ScheduleDto {
                 id: id.clone(),
                 tags: params.tags,
@@ -76,17 +447,425 @@ impl ScheduleRepository {
                 schedule: params.schedule,
                 schedule_at: params.schedule_at,
                 callback: params.callback,
-                created_at: chrono::Utc::now(),
-                updated_at: chrono::Utc::now(),
+                success: params.success,
+                capture: params.capture,
+                dry_run: params.dry_run,
+                on_success: params.on_success,
+                on_failure: params.on_failure,
+                retention: params.retention,
+                expires_at: None,
+                dedup_key: params.dedup_key,
+                dedup_window: params.dedup_window,
+                priority: params.priority,
+                created_at: now,
+                updated_at: now,
                 status: schema::ScheduleStatus::Scheduled,
             };
-            {
-                let _enter = span.enter();
-                let bytes = serde_json::to_vec(&schedule)?;
-                let _ = schedules.insert(id, bytes)?;
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&schedule)?;
+            let key = match &schedule.dedup_key {
+                Some(key) => key.clone(),
+                None => {
+                    let previous = schedules.insert(id, bytes)?;
+                    track(
+                        previous.as_deref().and_then(status_of),
+                        Some(ScheduleStatus::Scheduled),
+                    );
+                    return Ok(Created::New {
+                        schedule,
+                        replaced: None,
+                    });
+                }
+            };
+            let entry = serde_json::to_vec(&DedupDocument {
+                schedule_id: id.clone(),
+                until: window.and_then(|w| now.checked_add_signed(w)),
+            })?;
+            let (created, previous) = (&schedules, &dedup)
+                .transaction(|(schedules, dedup)| {
+                    let pending = dedup
+                        .get(key.as_bytes())?
+                        .and_then(|e| serde_json::from_slice::<DedupDocument>(&e).ok())
+                        .filter(|e| e.until.map(|until| until > now).unwrap_or(true))
+                        .and_then(|e| schedules.get(e.schedule_id.as_bytes()).transpose())
+                        .transpose()?
+                        .and_then(|s| serde_json::from_slice::<ScheduleDocument>(&s).ok())
+                        .filter(|s| {
+                            s.runs == 0 && matches!(s.status, schema::ScheduleStatus::Scheduled)
+                        });
+                    let mut replaced = None;
+                    if let Some(existing) = pending {
+                        if on_duplicate != OnDuplicate::Replace {
+                            return Ok((Created::Duplicate(existing.into()), None));
+                        }
+                        if existing.id != id {
+                            schedules.remove(existing.id.as_bytes())?;
+                            replaced = Some(existing.id);
+                        }
+                    }
+                    let previous = schedules.insert(id.as_bytes(), bytes.as_slice())?;
+                    dedup.insert(key.as_bytes(), entry.as_slice())?;
+                    let created = Created::New {
+                        schedule: schedule.clone(),
+                        replaced,
+                    };
+                    Ok::<_, sled::transaction::ConflictableTransactionError>((
+                        created,
+                        Some(previous.as_deref().and_then(status_of)),
+                    ))
+                })
+                .map_err(transaction_error)?;
+            // `previous` is set when the schedule was stored
+            if let Some(previous) = previous {
+                if let Created::New {
+                    replaced: Some(_), ..
+                } = &created
+                {
+                    // only pending schedules are replaced
+                    track(Some(ScheduleStatus::Scheduled), None);
+                }
+                track(previous, Some(ScheduleStatus::Scheduled));
             }
-            Ok(schedule)
+            Ok(created)
+        })
+        .await?
+    }
+
+    /// Replaces the fields of the definition of a schedule that are set, keeping its runs. A
+    /// completed or failed schedule given another `schedule` or `schedule_at` is scheduled
+    /// again. Returns `None` if the schedule doesn't exist.
+    #[tracing::instrument(skip(self, params), fields(id = %params.id))]
+    pub(crate) async fn update_schedule(
+        &self,
+        params: UpdateScheduleDto,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> std::io::Result<Option<ScheduleDto>> {
+        self.update_schedule_to(params, now, None).await
+    }
+
+    /// Like [`Self::update_schedule`], but sets the revision to `revision` rather than
+    /// incrementing it, so updating again with the same revision gives the same result.
+    #[tracing::instrument(skip(self, params), fields(id = %params.id))]
+    pub(crate) async fn update_schedule_to(
+        &self,
+        params: UpdateScheduleDto,
+        now: chrono::DateTime<chrono::Utc>,
+        revision: Option<u64>,
+    ) -> std::io::Result<Option<ScheduleDto>> {
+        let schedules = self.schedules.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.update", id = %params.id);
+            let _enter = span.enter();
+            let mut update = match serde_json::to_value(&params)? {
+                serde_json::Value::Object(update) => update,
+                _ => unreachable!("schedule updates serialize to objects"),
+            };
+            // fields left out keep their stored values, but `schedule` and `schedule_at` are
+            // replaced together as only one of them applies
+            update.retain(|_, value| !value.is_null());
+            if update.contains_key("schedule") || update.contains_key("schedule_at") {
+                for key in ["schedule", "schedule_at"] {
+                    update.entry(key).or_insert(serde_json::Value::Null);
+                }
+            }
+            let updated = schedules
+                .transaction(|schedules| {
+                    let mut schedule = match schedules.get(params.id.as_bytes())? {
+                        Some(bytes) => match serde_json::from_slice(&bytes) {
+                            Ok(serde_json::Value::Object(schedule)) => schedule,
+                            _ => return Ok(None),
+                        },
+                        None => return Ok(None),
+                    };
+                    let timing = |s: &serde_json::Map<String, serde_json::Value>| {
+                        (s.get("schedule").cloned(), s.get("schedule_at").cloned())
+                    };
+                    let before = timing(&schedule);
+                    for (key, value) in &update {
+                        schedule.insert(key.clone(), value.clone());
+                    }
+                    let retimed = timing(&schedule) != before;
+                    schedule.insert("updated_at".to_string(), serde_json::json!(now));
+                    let mut schedule: ScheduleDocument =
+                        match serde_json::from_value(serde_json::Value::Object(schedule)) {
+                            Ok(schedule) => schedule,
+                            Err(e) => {
+                                return Err(ConflictableTransactionError::Abort(
+                                    std::io::Error::other(e),
+                                ))
+                            }
+                        };
+                    // runs of the former definition finishing later aren't recorded
+                    schedule.revision = revision.unwrap_or(schedule.revision + 1);
+                    let status = schedule.status;
+                    if retimed
+                        && matches!(status, ScheduleStatus::Completed | ScheduleStatus::Failed)
+                    {
+                        // kept until its retention ends otherwise, its expiry index entry is
+                        // ignored once it is no longer expired
+                        schedule.status = ScheduleStatus::Scheduled;
+                        schedule.expires_at = None;
+                    }
+                    let bytes = serde_json::to_vec(&schedule)
+                        .map_err(|e| ConflictableTransactionError::Abort(e.into()))?;
+                    schedules.insert(schedule.id.as_bytes(), bytes)?;
+                    Ok(Some((status, schedule)))
+                })
+                .map_err(|e| match e {
+                    TransactionError::Abort(e) => e,
+                    TransactionError::Storage(e) => e.into(),
+                })?;
+            Ok(updated.map(|(status, schedule)| {
+                track(Some(status), Some(schedule.status));
+                ScheduleDto::from(schedule)
+            }))
+        })
+        .await?
+    }
+
+    /// Removes a schedule with its dedup and expiry index entries. Returns false if the schedule
+    /// doesn't exist.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn delete(&self, id: ScheduleId) -> std::io::Result<bool> {
+        let schedules = self.schedules.clone();
+        let expiry = self.expiry.clone();
+        let dedup = self.dedup.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "schedules.delete", id = %id);
+            let _enter = span.enter();
+            (&schedules, &expiry, &dedup)
+                .transaction(|(schedules, expiry, dedup)| {
+                    let removed = match schedules.remove(id.as_bytes())? {
+                        Some(bytes) => bytes,
+                        None => return Ok(None),
+                    };
+                    let status = status_of(&removed);
+                    let schedule = match serde_json::from_slice::<ScheduleDocument>(&removed) {
+                        Ok(schedule) => schedule,
+                        Err(_) => return Ok(Some(status)),
+                    };
+                    if let Some(expires_at) = &schedule.expires_at {
+                        expiry.remove(expiry_key(expires_at, &schedule.id))?;
+                    }
+                    if let Some(dedup_key) = &schedule.dedup_key {
+                        let holds_key = dedup
+                            .get(dedup_key.as_bytes())?
+                            .and_then(|e| serde_json::from_slice::<DedupDocument>(&e).ok())
+                            .map(|e| e.schedule_id == schedule.id)
+                            .unwrap_or(false);
+                        if holds_key {
+                            dedup.remove(dedup_key.as_bytes())?;
+                        }
+                    }
+                    Ok::<_, ConflictableTransactionError>(Some(status))
+                })
+                .map_err(transaction_error)
+                .map(|removed| match removed {
+                    Some(status) => {
+                        track(status, None);
+                        true
+                    }
+                    None => false,
+                })
         })
         .await?
     }
 }
+
+/// Result of creating a schedule.
+pub enum Created {
+    /// Schedule was stored, replacing the pending schedule with the same dedup key, if any
+    New {
+        schedule: ScheduleDto,
+        replaced: Option<ScheduleId>,
+    },
+    /// Schedule was not stored, a pending schedule with the same dedup key exists
+    Duplicate(ScheduleDto),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo() -> ScheduleRepository {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        ScheduleRepository::new(&db)
+    }
+
+    fn request(id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "request": {"url": "http://localhost/", "method": "GET"},
+            "schedule": "0 * * * * *",
+        })
+    }
+
+    async fn create(repo: &ScheduleRepository, id: &str) {
+        let params = serde_json::from_value(request(id)).unwrap();
+        repo.create_schedule_at(params, chrono::Utc::now())
+            .await
+            .unwrap();
+    }
+
+    async fn get(repo: &ScheduleRepository, id: &str) -> Option<ScheduleDocument> {
+        repo.get(id.to_string()).await.unwrap()
+    }
+
+    fn run(id: &str, revision: u64, run: u64, status: ScheduleStatus) -> RunDocument {
+        RunDocument {
+            schedule_id: id.to_string(),
+            revision,
+            run,
+            at: chrono::Utc::now(),
+            status,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn records_runs_of_the_current_definition() {
+        let repo = repo();
+        create(&repo, "a").await;
+        let now = chrono::Utc::now();
+        assert!(repo
+            .record_run(run("a", 0, 1, ScheduleStatus::Scheduled), now)
+            .await
+            .unwrap());
+        let mut completed = run("a", 0, 2, ScheduleStatus::Completed);
+        completed.expires_at = Some(now);
+        assert!(repo.record_run(completed, now).await.unwrap());
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.runs, 2);
+        assert_eq!(schedule.status, ScheduleStatus::Completed);
+        // the expiry index picks the schedule up
+        let deleted = repo.delete_expired(now, 10).await.unwrap();
+        assert_eq!(deleted, vec!["a".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn ignores_runs_of_replaced_definitions() {
+        let repo = repo();
+        create(&repo, "a").await;
+        let mut update = request("a");
+        update["schedule"] = serde_json::json!("0 0 * * * *");
+        let update = serde_json::from_value(update).unwrap();
+        let updated = repo
+            .update_schedule(update, chrono::Utc::now())
+            .await
+            .unwrap();
+        assert!(updated.is_some());
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.revision, 1);
+
+        let stale = run("a", 0, 1, ScheduleStatus::Completed);
+        assert!(!repo.record_run(stale, chrono::Utc::now()).await.unwrap());
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.runs, 0);
+        assert_eq!(schedule.status, ScheduleStatus::Scheduled);
+        assert_eq!(schedule.schedule.as_deref(), Some("0 0 * * * *"));
+    }
+
+    #[tokio::test]
+    async fn keeps_fields_left_out_of_updates() {
+        let repo = repo();
+        let mut params = request("a");
+        params["tags"] = serde_json::json!(["billing"]);
+        params["retention"] = serde_json::json!("1h");
+        let params = serde_json::from_value(params).unwrap();
+        repo.create_schedule_at(params, chrono::Utc::now())
+            .await
+            .unwrap();
+        let mut update = request("a");
+        update["tags"] = serde_json::Value::Null;
+        update.as_object_mut().unwrap().remove("schedule");
+        update["request"]["url"] = serde_json::json!("http://localhost/other");
+        let update = serde_json::from_value(update).unwrap();
+        repo.update_schedule(update, chrono::Utc::now())
+            .await
+            .unwrap();
+
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.request.url, "http://localhost/other");
+        assert_eq!(schedule.tags, Some(vec!["billing".to_string()]));
+        assert_eq!(schedule.retention.as_deref(), Some("1h"));
+        assert_eq!(schedule.schedule.as_deref(), Some("0 * * * * *"));
+    }
+
+    #[tokio::test]
+    async fn schedules_completed_schedules_given_another_time() {
+        let repo = repo();
+        create(&repo, "a").await;
+        let now = chrono::Utc::now();
+        let mut completed = run("a", 0, 1, ScheduleStatus::Completed);
+        completed.expires_at = Some(now + chrono::Duration::hours(1));
+        assert!(repo.record_run(completed, now).await.unwrap());
+
+        // the same timing leaves it completed
+        let update = serde_json::from_value(request("a")).unwrap();
+        repo.update_schedule(update, now).await.unwrap();
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.status, ScheduleStatus::Completed);
+
+        let mut update = request("a");
+        update["schedule"] = serde_json::Value::Null;
+        update["schedule_at"] = serde_json::json!("2030-01-01T00:00:00Z");
+        let update = serde_json::from_value(update).unwrap();
+        let updated = repo.update_schedule(update, now).await.unwrap().unwrap();
+        assert_eq!(updated.status, ScheduleStatus::Scheduled);
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.status, ScheduleStatus::Scheduled);
+        assert_eq!(schedule.expires_at, None);
+        assert_eq!(schedule.runs, 1);
+        assert_eq!(schedule.schedule, None);
+        let later = now + chrono::Duration::hours(2);
+        assert!(repo.delete_expired(later, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_runs_of_deleted_schedules() {
+        let repo = repo();
+        create(&repo, "a").await;
+        assert!(repo.delete("a".to_string()).await.unwrap());
+        let late = run("a", 0, 1, ScheduleStatus::Completed);
+        assert!(!repo.record_run(late, chrono::Utc::now()).await.unwrap());
+        assert!(get(&repo, "a").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn ignores_earlier_runs() {
+        let repo = repo();
+        create(&repo, "a").await;
+        let now = chrono::Utc::now();
+        assert!(repo
+            .record_run(run("a", 0, 3, ScheduleStatus::Scheduled), now)
+            .await
+            .unwrap());
+        assert!(!repo
+            .record_run(run("a", 0, 2, ScheduleStatus::Failed), now)
+            .await
+            .unwrap());
+        let schedule = get(&repo, "a").await.unwrap();
+        assert_eq!(schedule.runs, 3);
+        assert_eq!(schedule.status, ScheduleStatus::Scheduled);
+    }
+
+    #[tokio::test]
+    async fn pages_active_ids_in_order() {
+        let repo = repo();
+        for id in ["c", "a", "b", "d"] {
+            create(&repo, id).await;
+        }
+        let mut completed = get(&repo, "b").await.unwrap();
+        completed.status = ScheduleStatus::Completed;
+        repo.save(completed).await.unwrap();
+
+        let page = repo.active_ids(None, 2).await.unwrap();
+        assert_eq!(page.ids, ["a"]);
+        assert_eq!(page.next.as_deref(), Some("b"));
+        let page = repo.active_ids(page.next, 2).await.unwrap();
+        assert_eq!(page.ids, ["c", "d"]);
+        let page = repo.active_ids(page.next, 2).await.unwrap();
+        assert!(page.ids.is_empty());
+        assert!(page.next.is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::config;
+use crate::db::schema::{ExecutionDocument, ScheduleId};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sled::Tree;
+use std::io::{Read, Write};
+use tracing::{span, Level};
+
+/// Marks how a captured body is stored, written as the first byte of the value
+const BODY_RAW: u8 = 0;
+const BODY_GZIP: u8 = 1;
+
+/// Execution history, keyed by schedule id and execution id so that
+/// executions of a schedule are stored next to each other in order.
+/// Captured response bodies are kept in a separate tree under the same keys.
+pub struct ExecutionRepository {
+    db: sled::Db,
+    executions: Tree,
+    bodies: Tree,
+    compress_bodies: bool,
+}
+
+fn prefix(schedule_id: &str) -> Vec<u8> {
+    let mut key = schedule_id.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn key(schedule_id: &str, id: u64) -> Vec<u8> {
+    let mut key = prefix(schedule_id);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+impl ExecutionRepository {
+    /// Trees holding executions and their captured bodies
+    pub const TREES: [&'static str; 2] = ["executions", "execution_bodies"];
+
+    pub fn new(db: &sled::Db) -> Self {
+        let [executions, bodies] = Self::TREES;
+        Self {
+            db: db.clone(),
+            executions: db.open_tree(executions).unwrap(),
+            bodies: db.open_tree(bodies).unwrap(),
+            compress_bodies: config::db::compress_bodies(),
+        }
+    }
+
+    /// Returns a new, monotonically increasing execution id.
+    pub fn next_id(&self) -> std::io::Result<u64> {
+        Ok(self.db.generate_id()?)
+    }
+
+    #[tracing::instrument(skip(self, execution), fields(id = %execution.id))]
+    pub async fn save(&self, execution: ExecutionDocument) -> std::io::Result<()> {
+        let executions = self.executions.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.save", id = %execution.id);
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&execution)?;
+            let _ = executions.insert(key(&execution.schedule_id, execution.id), bytes)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self, body))]
+    pub async fn save_body(
+        &self,
+        schedule_id: ScheduleId,
+        id: u64,
+        body: Vec<u8>,
+    ) -> std::io::Result<()> {
+        let bodies = self.bodies.clone();
+        let compress = self.compress_bodies;
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.save_body", id = %id);
+            let _enter = span.enter();
+            let value = if compress {
+                let mut encoder = GzEncoder::new(vec![BODY_GZIP], Compression::default());
+                encoder.write_all(&body)?;
+                encoder.finish()?
+            } else {
+                let mut value = Vec::with_capacity(body.len() + 1);
+                value.push(BODY_RAW);
+                value.extend_from_slice(&body);
+                value
+            };
+            let _ = bodies.insert(key(&schedule_id, id), value)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get<T>(&self, schedule_id: ScheduleId, id: u64) -> std::io::Result<Option<T>>
+    where
+        T: From<ExecutionDocument> + Send + Sync + 'static,
+    {
+        let executions = self.executions.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.get", id = %id);
+            let _enter = span.enter();
+            match executions.get(key(&schedule_id, id))? {
+                Some(bytes) => {
+                    let execution: ExecutionDocument = serde_json::from_slice(&bytes)?;
+                    Ok(Some(execution.into()))
+                }
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    /// Returns the captured response body of an execution, decompressed.
+    #[tracing::instrument(skip(self))]
+    pub async fn get_body(
+        &self,
+        schedule_id: ScheduleId,
+        id: u64,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let bodies = self.bodies.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.get_body", id = %id);
+            let _enter = span.enter();
+            let value = match bodies.get(key(&schedule_id, id))? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+            match value.first() {
+                Some(&BODY_GZIP) => {
+                    let mut body = Vec::new();
+                    GzDecoder::new(&value[1..]).read_to_end(&mut body)?;
+                    Ok(Some(body))
+                }
+                Some(_) => Ok(Some(value[1..].to_vec())),
+                None => Ok(Some(Vec::new())),
+            }
+        })
+        .await?
+    }
+
+    /// Removes executions of a schedule and their captured bodies.
+    #[tracing::instrument(skip(self))]
+    pub async fn delete(&self, schedule_id: ScheduleId) -> std::io::Result<()> {
+        let executions = self.executions.clone();
+        let bodies = self.bodies.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.delete", schedule_id = %schedule_id);
+            let _enter = span.enter();
+            for tree in [&executions, &bodies] {
+                for key in tree.scan_prefix(prefix(&schedule_id)).keys() {
+                    tree.remove(key?)?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Lists executions of a schedule, newest first.
+    #[tracing::instrument(skip(self))]
+    pub async fn list<T>(
+        &self,
+        schedule_id: ScheduleId,
+        page: usize,
+        skip: usize,
+    ) -> std::io::Result<Vec<T>>
+    where
+        T: From<ExecutionDocument> + Send + Sync + 'static,
+    {
+        let executions = self.executions.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "executions.list", schedule_id = %schedule_id);
+            let _enter = span.enter();
+            let mut result: Vec<T> = Vec::new();
+            for execution in executions
+                .scan_prefix(prefix(&schedule_id))
+                .rev()
+                .skip(skip)
+                .take(page)
+            {
+                let execution: ExecutionDocument = serde_json::from_slice(&execution?.1)?;
+                result.push(execution.into());
+            }
+            Ok(result)
+        })
+        .await?
+    }
+}
@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 pub type RequestHeaders = HashMap<String, String>;
 pub type Tags = Vec<String>;
@@ -14,9 +14,84 @@ pub struct RequestDocument {
     /// Optional request headers
     pub headers: Option<RequestHeaders>,
     /// Optional request body
-    pub body: Option<String>,
+    pub body: Option<RequestBody>,
     /// If set, overrides the default retry delay
     pub retry: Option<Vec<u32>>,
+    /// Optional rate limiter group, overrides grouping by tag or host
+    pub rate_limit_key: Option<String>,
+    /// If set, overrides the default connect timeout, in milliseconds
+    pub connect_timeout_ms: Option<u64>,
+    /// If set, overrides the default total request timeout, in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// If set, overrides the default number of redirects to follow, 0 disables redirects
+    pub max_redirects: Option<usize>,
+    /// Name of the CA bundle used to verify the server, in addition to system roots
+    pub ca_bundle: Option<String>,
+    /// Name of the client identity (certificate and key) presented for mTLS
+    pub client_identity: Option<String>,
+    /// Skip server certificate verification, only allowed if enabled by the admin
+    pub insecure_skip_verify: Option<bool>,
+    /// If true, url, header values and body are rendered as templates on each execution
+    pub template: Option<bool>,
+}
+
+/// Request body, plain string is sent as text, other kinds are written as
+/// single key objects, e.g. `{"json": {...}}`, `{"base64": "..."}` or `{"form": {...}}`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RequestBody {
+    /// Text sent as is
+    Text(String),
+    /// JSON document, sent without additional encoding
+    Json { json: serde_json::Value },
+    /// Binary payload encoded as standard base64
+    Base64 { base64: String },
+    /// Fields sent as `application/x-www-form-urlencoded`
+    Form { form: BTreeMap<String, String> },
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SuccessDocument {
+    /// Accepted status codes or ranges, e.g. `["200-299", "304"]`, defaults to `["200-299"]`
+    pub status: Option<Vec<String>>,
+    /// JSONPath query on the response body, it must select at least one value
+    pub json_path: Option<String>,
+    /// Regular expression the response body must match
+    pub body_regex: Option<String>,
+    /// Maximum accepted latency in milliseconds
+    pub max_latency_ms: Option<u64>,
+}
+
+/// Follow-up of a schedule, either id of an existing schedule or an inline request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FollowUpDocument {
+    /// Existing schedule, triggered out of band
+    Schedule(ScheduleId),
+    /// Request sent once, as a one time schedule
+    Request { request: Box<RequestDocument> },
+}
+
+/// Execution that caused a follow-up to run
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UpstreamDocument {
+    pub schedule_id: ScheduleId,
+    pub execution_id: u64,
+    pub run: u64,
+    pub succeeded: bool,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CaptureDocument {
+    /// Keep the response body, set to false for sensitive responses
+    pub body: Option<bool>,
+    /// Response headers to keep, overrides the default list
+    pub headers: Option<Vec<String>>,
+    /// Maximum number of body bytes to keep, the rest is truncated
+    pub max_body_bytes: Option<usize>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -42,20 +117,114 @@ pub struct ScheduleDocument {
     pub schedule_at: Option<String>,
     /// Callback to be executed after request is executed, this is optional
     pub callback: Option<CallbackDocument>,
+    /// Criteria deciding whether an execution succeeded, defaults to any 2xx status
+    pub success: Option<SuccessDocument>,
+    /// What to keep from upstream responses, defaults to server settings
+    pub capture: Option<CaptureDocument>,
+    /// If true, executions record the request that would be sent instead of sending it
+    pub dry_run: Option<bool>,
+    /// Follow-ups fired after a run succeeds
+    pub on_success: Option<Vec<FollowUpDocument>>,
+    /// Follow-ups fired after a run fails, once retries are exhausted
+    pub on_failure: Option<Vec<FollowUpDocument>>,
+    /// Execution that created this schedule, for inline follow-ups
+    pub upstream: Option<UpstreamDocument>,
+    /// How long the schedule is kept once a one time run succeeded, e.g. `1h`,
+    /// defaults to the server retention policy
+    pub retention: Option<String>,
+    /// Instant the schedule and its executions are removed, set once it completed successfully
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Key identifying duplicates of the schedule while it is pending
+    pub dedup_key: Option<String>,
+    /// How long after creation duplicates are detected, e.g. `10m`, unlimited while pending if unset
+    pub dedup_window: Option<String>,
+    /// Dispatch priority class, defaults to `normal`
+    pub priority: Option<Priority>,
     /// Created at
     pub created_at: chrono::DateTime<chrono::Utc>,
     /// Updated at
     pub updated_at: chrono::DateTime<chrono::Utc>,
-    /// last successful run
+    /// last finished run
     pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    /// number of executed runs
+    #[serde(default)]
+    pub runs: u64,
     /// status
     #[serde(default = "ScheduleStatus::default")]
     pub status: ScheduleStatus,
+    /// Revision of the definition, bumped each time it is replaced
+    #[serde(default)]
+    pub revision: u64,
 }
 
+/// Outcome of a run, stored on its schedule only if the definition it ran is still current.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunDocument {
+    pub schedule_id: ScheduleId,
+    /// Revision of the definition the run was executed with
+    pub revision: u64,
+    pub run: u64,
+    /// Instant the run was scheduled at
+    pub at: chrono::DateTime<chrono::Utc>,
+    pub status: ScheduleStatus,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Dispatch priority class. When dispatch workers are saturated, requests of higher classes
+/// are dispatched first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Critical,
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// All classes, highest first
+    pub const ALL: [Priority; 4] = [
+        Priority::Critical,
+        Priority::High,
+        Priority::Normal,
+        Priority::Low,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Critical => "critical",
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Low => "low",
+        }
+    }
+}
+
+impl std::str::FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|p| p.as_str() == s)
+            .ok_or_else(|| format!("unknown priority {}, use critical, high, normal or low", s))
+    }
+}
+
+/// Entry of the dedup index, keyed by dedup key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DedupDocument {
+    /// Schedule holding the key
+    pub schedule_id: ScheduleId,
+    /// End of the dedup window, duplicates are detected while the schedule is pending if unset
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ScheduleStatus {
     #[serde(rename = "scheduled")]
+    #[default]
     Scheduled,
     #[serde(rename = "executing")]
     Executing,
@@ -67,8 +236,199 @@ pub enum ScheduleStatus {
     Failed,
 }
 
-impl Default for ScheduleStatus {
-    fn default() -> Self {
-        Self::Scheduled
+impl ScheduleStatus {
+    pub const ALL: [ScheduleStatus; 5] = [
+        ScheduleStatus::Scheduled,
+        ScheduleStatus::Executing,
+        ScheduleStatus::Completed,
+        ScheduleStatus::Paused,
+        ScheduleStatus::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduleStatus::Scheduled => "scheduled",
+            ScheduleStatus::Executing => "executing",
+            ScheduleStatus::Completed => "completed",
+            ScheduleStatus::Paused => "paused",
+            ScheduleStatus::Failed => "failed",
+        }
     }
+
+    /// Whether schedules or workflows with this status still have runs ahead.
+    pub fn is_active(&self) -> bool {
+        matches!(self, ScheduleStatus::Scheduled | ScheduleStatus::Executing)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExecutionDocument {
+    /// Unique identifier for execution
+    pub id: u64,
+    /// Executed schedule
+    pub schedule_id: ScheduleId,
+    /// Run number of the schedule, starting from 1
+    pub run: u64,
+    /// Attempt number within the run, starting from 1
+    pub attempt: u32,
+    /// Whether the run was triggered manually, manual runs share the number of the last run
+    #[serde(default)]
+    pub manual: bool,
+    /// Instant the run was scheduled for
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    /// Started at
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Finished at
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    /// Response status code, if upstream responded
+    pub status_code: Option<u16>,
+    /// Response latency in milliseconds, if upstream responded
+    pub latency_ms: Option<u64>,
+    /// Whether the execution met the success criteria
+    #[serde(default)]
+    pub succeeded: bool,
+    /// Error that prevented the request from completing, or failed success criteria
+    pub error: Option<String>,
+    /// Values matched by the success criteria
+    pub matches: Option<MatchesDocument>,
+    /// Captured upstream response
+    pub response: Option<ResponseDocument>,
+    /// Whether the request was only built and not sent
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Request as it would be sent, recorded for dry runs
+    pub request: Option<OutboundRequestDocument>,
+    /// Execution that triggered this one, for follow-ups
+    pub upstream: Option<UpstreamDocument>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutboundRequestDocument {
+    pub method: String,
+    pub url: String,
+    /// Headers, including the ones set by the dispatcher
+    pub headers: RequestHeaders,
+    /// Body, text if it is valid UTF-8, base64 otherwise
+    pub body: Option<RequestBody>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResponseDocument {
+    /// Captured response headers
+    pub headers: RequestHeaders,
+    /// Whether the body was captured, it is stored separately from the execution
+    pub body_captured: bool,
+    /// Number of captured body bytes
+    pub body_size: usize,
+    /// Whether the body was longer than the capture limit and was truncated
+    pub body_truncated: bool,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MatchesDocument {
+    /// Values selected by `json_path`
+    pub json_path: Option<Vec<serde_json::Value>>,
+    /// Match of `body_regex` followed by its capture groups
+    pub body_regex: Option<Vec<Option<String>>>,
+}
+
+pub type WorkflowId = String;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowDocument {
+    /// Unique identifier for workflow
+    pub id: WorkflowId,
+    /// Optional tags to group workflows, also used to group step requests for rate limiting
+    pub tags: Option<Tags>,
+    /// Schedule in cron format
+    pub schedule: Option<String>,
+    /// Schedule in ISO 8601 format
+    pub schedule_at: Option<String>,
+    /// Steps of the workflow, forming a directed acyclic graph through `depends_on`
+    pub steps: Vec<WorkflowStepDocument>,
+    /// Created at
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Updated at
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// last finished run
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    /// number of started runs
+    #[serde(default)]
+    pub runs: u64,
+    /// status, same lifecycle as schedules
+    #[serde(default = "ScheduleStatus::default")]
+    pub status: ScheduleStatus,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowStepDocument {
+    /// Name of the step, unique within the workflow
+    pub name: String,
+    /// Request sent by the step, retried after `request.retry` delays
+    pub request: RequestDocument,
+    /// Steps that must succeed before this one runs
+    pub depends_on: Option<Vec<String>>,
+    /// Criteria deciding whether the step succeeded, defaults to any 2xx status
+    pub success: Option<SuccessDocument>,
+    /// Deadline of the step including retries, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowRunStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    /// Waiting for its dependencies
+    Pending,
+    /// Request in flight, or waiting for a retry
+    Running,
+    Succeeded,
+    Failed,
+    /// Not run because a dependency did not succeed
+    Skipped,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorkflowRunDocument {
+    /// Unique identifier for run
+    pub id: u64,
+    pub workflow_id: WorkflowId,
+    /// Run number of the workflow, starting from 1
+    pub run: u64,
+    /// Instant the run was scheduled for
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    /// Started at
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Finished at, once the run is no longer running
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: WorkflowRunStatus,
+    /// State of each step, in workflow order
+    pub steps: Vec<StepRunDocument>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepRunDocument {
+    pub name: String,
+    pub status: StepStatus,
+    /// Number of attempts made so far
+    pub attempts: u32,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Response status code of the last attempt, if upstream responded
+    pub status_code: Option<u16>,
+    /// Response latency of the last attempt in milliseconds, if upstream responded
+    pub latency_ms: Option<u64>,
+    /// Error of the last attempt
+    pub error: Option<String>,
+    /// Values matched by the success criteria
+    pub matches: Option<MatchesDocument>,
 }
@@ -0,0 +1,245 @@
+use crate::api::dto::{TriggerDto, TriggerStatus};
+use crate::db::schema::ScheduleId;
+use sled::Tree;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tracing::{span, Level};
+
+/// Ledger of schedule runs, keyed by schedule id, run and scheduled instant so that triggers
+/// of a schedule are stored next to each other in order. A run is recorded before its request
+/// is sent and completed once it finished, so recovery tells runs never sent, absent from the
+/// ledger, from runs in flight, which may have been sent, and completed ones. Only the latest
+/// completed trigger of a schedule is kept.
+pub struct TriggerRepository {
+    triggers: Tree,
+    // runs in flight, counted once when the ledger is opened and kept up to date on writes
+    in_flight: Arc<AtomicUsize>,
+}
+
+fn prefix(schedule_id: &str) -> Vec<u8> {
+    let mut key = schedule_id.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn key(trigger: &TriggerDto) -> Vec<u8> {
+    let mut key = prefix(&trigger.schedule_id);
+    key.extend_from_slice(&trigger.run.to_be_bytes());
+    key.extend_from_slice(&(trigger.at.timestamp_millis().max(0) as u64).to_be_bytes());
+    key
+}
+
+fn is_in_flight(value: Option<&[u8]>) -> std::io::Result<bool> {
+    Ok(match value {
+        Some(value) => {
+            serde_json::from_slice::<TriggerDto>(value)?.status == TriggerStatus::InFlight
+        }
+        None => false,
+    })
+}
+
+/// Id of the trigger of a run, the same every time the run is attempted or resumed.
+pub fn trigger_id(schedule_id: &str, run: u64, at: &chrono::DateTime<chrono::Utc>) -> String {
+    format!("{}-{}-{}", schedule_id, run, at.timestamp_millis())
+}
+
+impl TriggerRepository {
+    pub fn new(db: &sled::Db) -> Self {
+        let triggers = db.open_tree("triggers").unwrap();
+        let in_flight = Self::resolve_interrupted(&triggers).unwrap();
+        Self {
+            triggers,
+            in_flight: Arc::new(AtomicUsize::new(in_flight)),
+        }
+    }
+
+    /// Completes manual triggers left in flight by the previous run of the node, as they aren't
+    /// sent again, and counts the ticks in flight that schedules resume.
+    fn resolve_interrupted(triggers: &Tree) -> std::io::Result<usize> {
+        let mut in_flight = 0;
+        for entry in triggers.iter() {
+            let (key, value) = entry?;
+            let mut trigger: TriggerDto = serde_json::from_slice(&value)?;
+            if trigger.status != TriggerStatus::InFlight {
+                continue;
+            }
+            if trigger.manual {
+                log::warn!(
+                    "Manual run {} of {} at {} was interrupted, it isn't sent again",
+                    trigger.run,
+                    trigger.schedule_id,
+                    trigger.at
+                );
+                trigger.status = TriggerStatus::Completed;
+                triggers.insert(key, serde_json::to_vec(&trigger)?)?;
+            } else {
+                in_flight += 1;
+            }
+        }
+        Ok(in_flight)
+    }
+
+    /// Records a run about to be sent, synced to disk before returning.
+    #[tracing::instrument(skip(self, trigger), fields(id = %trigger.id))]
+    pub async fn record(&self, trigger: TriggerDto) -> std::io::Result<()> {
+        let triggers = self.triggers.clone();
+        let in_flight = self.in_flight.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "triggers.record", id = %trigger.id);
+            let _enter = span.enter();
+            let previous = triggers.insert(key(&trigger), serde_json::to_vec(&trigger)?)?;
+            if !is_in_flight(previous.as_deref())? {
+                in_flight.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok::<_, std::io::Error>(())
+        })
+        .await??;
+        self.triggers.flush_async().await?;
+        Ok(())
+    }
+
+    /// Marks a run as completed, removing completed triggers of the schedule before it.
+    #[tracing::instrument(skip(self, trigger), fields(id = %trigger.id))]
+    pub async fn complete(&self, mut trigger: TriggerDto) -> std::io::Result<()> {
+        let triggers = self.triggers.clone();
+        let in_flight = self.in_flight.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "triggers.complete", id = %trigger.id);
+            let _enter = span.enter();
+            trigger.status = TriggerStatus::Completed;
+            let key = key(&trigger);
+            let previous = triggers.insert(key.as_slice(), serde_json::to_vec(&trigger)?)?;
+            if is_in_flight(previous.as_deref())? {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+            for entry in triggers.range(prefix(&trigger.schedule_id)..key) {
+                let (key, value) = entry?;
+                let older: TriggerDto = serde_json::from_slice(&value)?;
+                if older.status == TriggerStatus::Completed {
+                    triggers.remove(key)?;
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Returns the trigger of the latest tick of a schedule, manual triggers aside.
+    #[tracing::instrument(skip(self))]
+    pub async fn latest(&self, schedule_id: ScheduleId) -> std::io::Result<Option<TriggerDto>> {
+        let triggers = self.triggers.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "triggers.latest", schedule_id = %schedule_id);
+            let _enter = span.enter();
+            for entry in triggers.scan_prefix(prefix(&schedule_id)).values().rev() {
+                let trigger: TriggerDto = serde_json::from_slice(&entry?)?;
+                if !trigger.manual {
+                    return Ok(Some(trigger));
+                }
+            }
+            Ok(None)
+        })
+        .await?
+    }
+
+    /// Number of runs in flight, across schedules.
+    pub fn count_in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Removes triggers of a schedule.
+    #[tracing::instrument(skip(self))]
+    pub async fn delete(&self, schedule_id: ScheduleId) -> std::io::Result<()> {
+        let triggers = self.triggers.clone();
+        let in_flight = self.in_flight.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "triggers.delete", schedule_id = %schedule_id);
+            let _enter = span.enter();
+            for key in triggers.scan_prefix(prefix(&schedule_id)).keys() {
+                let removed = triggers.remove(key?)?;
+                if is_in_flight(removed.as_deref())? {
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(schedule_id: &str, run: u64, manual: bool) -> TriggerDto {
+        let at = chrono::Utc::now() + chrono::Duration::seconds(run as i64);
+        TriggerDto {
+            id: trigger_id(schedule_id, run, &at),
+            schedule_id: schedule_id.to_string(),
+            run,
+            at,
+            node_id: None,
+            manual,
+            status: TriggerStatus::InFlight,
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_runs_in_flight() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let repo = TriggerRepository::new(&db);
+        let first = trigger("a", 1, false);
+        repo.record(first.clone()).await.unwrap();
+        // a resumed run is recorded again
+        repo.record(first.clone()).await.unwrap();
+        repo.record(trigger("b", 1, false)).await.unwrap();
+        assert_eq!(repo.count_in_flight(), 2);
+
+        repo.complete(first.clone()).await.unwrap();
+        repo.complete(first).await.unwrap();
+        assert_eq!(repo.count_in_flight(), 1);
+
+        repo.delete("b".to_string()).await.unwrap();
+        assert_eq!(repo.count_in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_latest_completed_tick() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let repo = TriggerRepository::new(&db);
+        let first = trigger("a", 1, false);
+        let second = trigger("a", 2, false);
+        repo.record(first.clone()).await.unwrap();
+        repo.complete(first).await.unwrap();
+        repo.record(second.clone()).await.unwrap();
+        let latest = repo.latest("a".to_string()).await.unwrap().unwrap();
+        assert_eq!(latest.run, 2);
+        assert_eq!(latest.status, TriggerStatus::InFlight);
+
+        repo.complete(second).await.unwrap();
+        repo.record(trigger("a", 3, true)).await.unwrap();
+        // manual triggers aside
+        let latest = repo.latest("a".to_string()).await.unwrap().unwrap();
+        assert_eq!(latest.run, 2);
+        assert_eq!(latest.status, TriggerStatus::Completed);
+        // completed triggers before the latest one are removed
+        assert_eq!(repo.triggers.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn resolves_manual_runs_interrupted_before_opening() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let repo = TriggerRepository::new(&db);
+        repo.record(trigger("a", 1, false)).await.unwrap();
+        let mut manual = trigger("a", 1, true);
+        manual.at += chrono::Duration::seconds(30);
+        repo.record(manual).await.unwrap();
+        assert_eq!(repo.count_in_flight(), 2);
+
+        let reopened = TriggerRepository::new(&db);
+        assert_eq!(reopened.count_in_flight(), 1);
+        let latest = reopened.latest("a".to_string()).await.unwrap().unwrap();
+        assert!(!latest.manual);
+        assert_eq!(latest.status, TriggerStatus::InFlight);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::api::dto::{CreateWorkflowDto, WorkflowDto};
+use crate::db::schema::{ScheduleStatus, WorkflowDocument, WorkflowId, WorkflowRunDocument};
+use crate::db::{page_active_ids, IdPage};
+use sled::Tree;
+use tracing::{span, Level};
+
+/// Workflows and their runs. Runs are keyed by workflow id and run id so that
+/// runs of a workflow are stored next to each other in order.
+pub struct WorkflowRepository {
+    db: sled::Db,
+    workflows: Tree,
+    runs: Tree,
+}
+
+fn prefix(workflow_id: &str) -> Vec<u8> {
+    let mut key = workflow_id.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn key(workflow_id: &str, id: u64) -> Vec<u8> {
+    let mut key = prefix(workflow_id);
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+impl WorkflowRepository {
+    /// Tree of workflow definitions, replicated on cluster members
+    pub const TREE: &'static str = "workflows";
+    /// Tree of runs, recorded by the node that ran them
+    pub const RUNS_TREE: &'static str = "workflow_runs";
+
+    pub fn new(db: &sled::Db) -> Self {
+        Self {
+            db: db.clone(),
+            workflows: db.open_tree(Self::TREE).unwrap(),
+            runs: db.open_tree(Self::RUNS_TREE).unwrap(),
+        }
+    }
+
+    /// Returns a new, monotonically increasing run id.
+    pub fn next_run_id(&self) -> std::io::Result<u64> {
+        Ok(self.db.generate_id()?)
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn list<T>(&self, page: usize, skip: usize) -> std::io::Result<Vec<T>>
+    where
+        T: From<WorkflowDocument> + Send + Sync + 'static,
+    {
+        let workflows = self.workflows.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.list", page = %page, skip = %skip);
+            let _enter = span.enter();
+            let mut result: Vec<T> = Vec::new();
+            for workflow in workflows.iter().skip(skip).take(page) {
+                let workflow: WorkflowDocument = serde_json::from_slice(&workflow?.1)?;
+                result.push(workflow.into());
+            }
+            Ok(result)
+        })
+        .await?
+    }
+
+    /// Returns the ids of the active workflows among the `page` workflows after the given id,
+    /// in order, reading only their status.
+    #[tracing::instrument(skip(self))]
+    pub async fn active_ids(
+        &self,
+        after: Option<WorkflowId>,
+        page: usize,
+    ) -> std::io::Result<IdPage> {
+        let workflows = self.workflows.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.ids", page = %page);
+            let _enter = span.enter();
+            page_active_ids(&workflows, after, page)
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get<T>(&self, id: WorkflowId) -> std::io::Result<Option<T>>
+    where
+        T: From<WorkflowDocument> + Send + Sync + 'static,
+    {
+        let workflows = self.workflows.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.get", id = %id);
+            let _enter = span.enter();
+            match workflows.get(id)? {
+                Some(bytes) => {
+                    let workflow: WorkflowDocument = serde_json::from_slice(&bytes)?;
+                    Ok(Some(workflow.into()))
+                }
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self, workflow), fields(id = %workflow.id))]
+    pub async fn save(&self, workflow: WorkflowDocument) -> std::io::Result<()> {
+        let workflows = self.workflows.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.save", id = %workflow.id);
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&workflow)?;
+            let _ = workflows.insert(workflow.id.as_str(), bytes)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    pub async fn create(&self, params: CreateWorkflowDto) -> std::io::Result<WorkflowDto> {
+        self.create_at(params, chrono::Utc::now()).await
+    }
+
+    /// Stores a new workflow as if created at `now`, replicated creates are applied with the
+    /// instant they were proposed.
+    #[tracing::instrument(skip(self))]
+    pub async fn create_at(
+        &self,
+        params: CreateWorkflowDto,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> std::io::Result<WorkflowDto> {
+        let workflows = self.workflows.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.create", id = %params.id);
+            let workflow = WorkflowDto {
+                id: params.id,
+                tags: params.tags,
+                schedule: params.schedule,
+                schedule_at: params.schedule_at,
+                steps: params.steps,
+                created_at: now,
+                updated_at: now,
+                status: ScheduleStatus::Scheduled,
+            };
+            {
+                let _enter = span.enter();
+                let bytes = serde_json::to_vec(&workflow)?;
+                let _ = workflows.insert(workflow.id.as_str(), bytes)?;
+            }
+            Ok(workflow)
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self, run), fields(id = %run.id))]
+    pub async fn save_run(&self, run: WorkflowRunDocument) -> std::io::Result<()> {
+        let runs = self.runs.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.save_run", id = %run.id);
+            let _enter = span.enter();
+            let bytes = serde_json::to_vec(&run)?;
+            let _ = runs.insert(key(&run.workflow_id, run.id), bytes)?;
+            Ok(())
+        })
+        .await?
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub async fn get_run<T>(&self, workflow_id: WorkflowId, id: u64) -> std::io::Result<Option<T>>
+    where
+        T: From<WorkflowRunDocument> + Send + Sync + 'static,
+    {
+        let runs = self.runs.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.get_run", id = %id);
+            let _enter = span.enter();
+            match runs.get(key(&workflow_id, id))? {
+                Some(bytes) => {
+                    let run: WorkflowRunDocument = serde_json::from_slice(&bytes)?;
+                    Ok(Some(run.into()))
+                }
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
+    /// Lists runs of a workflow, newest first.
+    #[tracing::instrument(skip(self))]
+    pub async fn list_runs<T>(
+        &self,
+        workflow_id: WorkflowId,
+        page: usize,
+        skip: usize,
+    ) -> std::io::Result<Vec<T>>
+    where
+        T: From<WorkflowRunDocument> + Send + Sync + 'static,
+    {
+        let runs = self.runs.clone();
+        tokio::spawn(async move {
+            let span = span!(Level::INFO, "workflows.list_runs", workflow_id = %workflow_id);
+            let _enter = span.enter();
+            let mut result: Vec<T> = Vec::new();
+            for run in runs
+                .scan_prefix(prefix(&workflow_id))
+                .rev()
+                .skip(skip)
+                .take(page)
+            {
+                let run: WorkflowRunDocument = serde_json::from_slice(&run?.1)?;
+                result.push(run.into());
+            }
+            Ok(result)
+        })
+        .await?
+    }
+}
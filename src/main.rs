@@ -1,6 +1,8 @@
-use crate::api::schedule;
+use crate::api::{
+    cluster as cluster_api, dispatcher, health, metrics as metrics_api, schedule, workflow,
+};
 use crate::config::web::HttpServerExt;
-use crate::metrics::init_telemetry;
+use crate::metrics::{init_telemetry, metrics};
 use actix_web::dev::Service;
 use actix_web::http::header::{HeaderName, HeaderValue};
 use actix_web::middleware::Logger;
@@ -11,12 +13,15 @@ use tracing_actix_web::{RequestId, TracingLogger};
 
 mod api;
 pub(crate) mod app_context;
-// mod cluster;
+mod cluster;
 pub(crate) mod config;
 mod db;
 mod metrics;
 mod scheduler;
 
+/// Maximum body size of requests between cluster members
+const CLUSTER_PAYLOAD_LIMIT: usize = 32 * 1024 * 1024;
+
 const HTML_404: &str = include_str!("404.html");
 async fn not_found() -> impl Responder {
     HttpResponse::NotFound().body(HTML_404)
@@ -27,7 +32,8 @@ async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(Env::default().default_filter_or("debug"));
     init_telemetry("schedule-rs");
     let ctx = Arc::new(app_context::ApiContext::new());
-    HttpServer::new(move || {
+    let cluster_ctx = ctx.clone();
+    let api = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(ctx.clone()))
             .wrap(Logger::default())
@@ -45,7 +51,32 @@ async fn main() -> std::io::Result<()> {
                     Ok(res)
                 }
             })
+            .wrap_fn(|req, srv| {
+                let method = req.method().to_string();
+                // route patterns keep the number of series bounded, unmatched paths are grouped
+                let path = req
+                    .match_pattern()
+                    .unwrap_or_else(|| "unmatched".to_string());
+                let started = std::time::Instant::now();
+                let res = srv.call(req);
+                async move {
+                    let res = res.await?;
+                    let m = metrics();
+                    m.http_requests
+                        .with_label_values(&[&method, &path, res.status().as_str()])
+                        .inc();
+                    m.http_duration
+                        .with_label_values(&[&method, &path])
+                        .observe(started.elapsed().as_secs_f64());
+                    Ok(res)
+                }
+            })
             .wrap(TracingLogger::default())
+            .service(metrics_api::scrape)
+            .service(health::endpoints())
+            .service(cluster_api::admin_endpoints())
+            .service(dispatcher::endpoints())
+            .service(workflow::endpoints())
             .service(schedule::endpoints())
             .default_service(actix_web::web::route().to(not_found))
     })
@@ -53,8 +84,26 @@ async fn main() -> std::io::Result<()> {
     .set_workers_from_env()
     .set_hostname_from_env()
     .shutdown_timeout(30)
-    .run()
-    .await?;
+    .run();
 
+    if cluster_ctx.cluster.is_none() {
+        return api.await;
+    }
+    // members talk to each other on their own listener, away from the public API
+    let listen = config::cluster::listen();
+    log::info!("Serving cluster requests on {}", listen);
+    let internal = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(cluster_ctx.clone()))
+            // entries and snapshot chunks are larger than the default limit
+            .app_data(web::JsonConfig::default().limit(CLUSTER_PAYLOAD_LIMIT))
+            .service(cluster_api::endpoints())
+            .default_service(actix_web::web::route().to(HttpResponse::NotFound))
+    })
+    .bind(listen)?
+    .workers(2)
+    .shutdown_timeout(30)
+    .run();
+    tokio::try_join!(api, internal)?;
     Ok(())
 }
@@ -1,3 +1,18 @@
+use crate::config::telemetry::{self, Exporter};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::{self as sdktrace, Sampler, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, TextEncoder,
+};
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::OnceLock;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
@@ -7,19 +22,23 @@ use opentelemetry::{
 };
 
 pub(crate) fn init_telemetry(service_name: &str) {
-    // Start a new Jaeger trace pipeline.
-    // Spans are exported in batch - recommended setup for a production application.
+    // W3C trace context, used for incoming API requests and outbound dispatch requests
     global::set_text_map_propagator(TraceContextPropagator::new());
-    let tracer = opentelemetry_jaeger::new_agent_pipeline()
-        .with_service_name(service_name)
-        .install_batch(TokioCurrentThread)
-        .expect("Failed to install OpenTelemetry tracer.");
+    let exporter = telemetry::exporter();
+    // Tracing stays usable for logs if the exporter can't be installed
+    let tracer = match install_tracer(exporter, service_name) {
+        Ok(tracer) => tracer,
+        Err(e) => {
+            log::error!("Failed to install {:?} trace exporter: {}", exporter, e);
+            None
+        }
+    };
 
     // Filter based on level - trace, debug, info, warn, error
     // Tunable via `RUST_LOG` env variable
     let env_filter = EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new("info"));
-    // Create a `tracing` layer using the Jaeger tracer
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+    // Create a `tracing` layer using the configured tracer, if any
+    let telemetry = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
     // Create a `tracing` layer to emit spans as structured logs to stdout
     let formatting_layer = BunyanFormattingLayer::new(service_name.into(), std::io::stdout);
     // Combined them all together in a `tracing` subscriber
@@ -31,3 +50,321 @@ pub(crate) fn init_telemetry(service_name: &str) {
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed to install `tracing` subscriber.")
 }
+
+fn trace_config(service_name: &str) -> sdktrace::Config {
+    let mut attributes = vec![
+        KeyValue::new("service.name", service_name.to_string()),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ];
+    if let Ok(hostname) = std::env::var("SCHEDULERS_HTTP_HOSTNAME") {
+        attributes.push(KeyValue::new("service.instance.id", hostname));
+    }
+    attributes.extend(
+        telemetry::resource_attributes()
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key, value)),
+    );
+    // configured attributes win over the ones detected from `OTEL_RESOURCE_ATTRIBUTES`
+    let resource = Resource::default().merge(&Resource::new(attributes));
+    sdktrace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            telemetry::sample_ratio(),
+        ))))
+        .with_resource(resource)
+}
+
+fn install_tracer(exporter: Exporter, service_name: &str) -> Result<Option<Tracer>, TraceError> {
+    let config = trace_config(service_name);
+    let endpoint = telemetry::endpoint();
+    // Spans of remote exporters are exported in batch - recommended setup for a production
+    // application.
+    let tracer = match exporter {
+        Exporter::None => return Ok(None),
+        Exporter::Stdout => {
+            let provider = TracerProvider::builder()
+                .with_simple_exporter(StdoutExporter)
+                .with_config(config)
+                .build();
+            let tracer = provider.versioned_tracer(
+                "schedule-rs",
+                Some(env!("CARGO_PKG_VERSION")),
+                None::<&str>,
+                None,
+            );
+            global::set_tracer_provider(provider);
+            tracer
+        }
+        Exporter::Jaeger => {
+            let mut pipeline = opentelemetry_jaeger::new_agent_pipeline()
+                .with_service_name(service_name)
+                .with_trace_config(config);
+            if let Some(endpoint) = endpoint {
+                pipeline = pipeline.with_endpoint(endpoint);
+            }
+            pipeline.install_batch(TokioCurrentThread)?
+        }
+        Exporter::OtlpGrpc => {
+            let mut exporter = opentelemetry_otlp::new_exporter().tonic();
+            if let Some(endpoint) = endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(config)
+                .install_batch(TokioCurrentThread)?
+        }
+        Exporter::OtlpHttp => {
+            let mut exporter = opentelemetry_otlp::new_exporter().http();
+            if let Some(endpoint) = endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(config)
+                .install_batch(TokioCurrentThread)?
+        }
+    };
+    Ok(Some(tracer))
+}
+
+/// Prints finished spans to stdout, one JSON object per line.
+#[derive(Debug)]
+struct StdoutExporter;
+
+impl SpanExporter for StdoutExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        let mut stdout = std::io::stdout().lock();
+        for span in batch {
+            let attributes: serde_json::Map<String, serde_json::Value> = span
+                .attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string().into()))
+                .collect();
+            let line = serde_json::json!({
+                "trace_id": span.span_context.trace_id().to_string(),
+                "span_id": span.span_context.span_id().to_string(),
+                "parent_span_id": span.parent_span_id.to_string(),
+                "name": span.name,
+                "kind": format!("{:?}", span.span_kind),
+                "start_time": chrono::DateTime::<chrono::Utc>::from(span.start_time).to_rfc3339(),
+                "end_time": chrono::DateTime::<chrono::Utc>::from(span.end_time).to_rfc3339(),
+                "status": format!("{:?}", span.status),
+                "attributes": attributes,
+            });
+            let _ = writeln!(stdout, "{}", line);
+        }
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Prometheus metrics of the scheduler, the dispatcher, storage and the HTTP API, exposed
+/// on `GET /metrics`.
+pub(crate) struct Metrics {
+    registry: prometheus::Registry,
+    /// Schedules by status, updated as schedules are written
+    pub schedules: IntGaugeVec,
+    /// Ticks fired by schedule actors
+    pub ticks: IntCounter,
+    /// Time from the scheduled instant of a run to its first attempt
+    pub lag: Histogram,
+    /// Lag of the latest tick
+    pub lag_last: Gauge,
+    /// Time from sending a request to receiving the response headers
+    pub dispatch_latency: Histogram,
+    /// Dispatched requests by status code class, `2xx` to `5xx`, or `error`
+    pub dispatch_outcomes: IntCounterVec,
+    /// Retried attempts of schedule runs and workflow steps
+    pub retries: IntCounterVec,
+    /// Callbacks by result, `delivered` or `failed`
+    pub callbacks: IntCounterVec,
+    /// Due requests waiting for a dispatch worker by priority, refreshed on scrape
+    pub dispatch_queued: IntGaugeVec,
+    /// Requests in flight by priority, refreshed on scrape
+    pub dispatch_in_flight: IntGaugeVec,
+    /// Running schedule and workflow actors
+    pub actors: IntGaugeVec,
+    /// Size of the database on disk, refreshed on scrape
+    pub db_size: IntGauge,
+    /// Duration of database flushes
+    pub db_flush: Histogram,
+    /// API requests by method, route pattern and status code
+    pub http_requests: IntCounterVec,
+    /// Duration of API requests by method and route pattern
+    pub http_duration: HistogramVec,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &prometheus::Registry,
+    collector: T,
+) -> T {
+    registry
+        .register(Box::new(collector.clone()))
+        .expect("Failed to register metric");
+    collector
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = prometheus::Registry::new_custom(Some("schedulers".to_string()), None)
+            .expect("Failed to create metrics registry");
+        let r = &registry;
+        let metrics = Self {
+            schedules: register(
+                r,
+                IntGaugeVec::new(Opts::new("schedules", "Schedules by status"), &["status"])
+                    .unwrap(),
+            ),
+            ticks: register(
+                r,
+                IntCounter::new("ticks_fired_total", "Ticks fired by schedules").unwrap(),
+            ),
+            lag: register(
+                r,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "scheduling_lag_seconds",
+                        "Time from the scheduled instant of a run to its first attempt",
+                    )
+                    .buckets(vec![
+                        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+                    ]),
+                )
+                .unwrap(),
+            ),
+            lag_last: register(
+                r,
+                Gauge::new("scheduling_lag_last_seconds", "Lag of the latest tick").unwrap(),
+            ),
+            dispatch_latency: register(
+                r,
+                Histogram::with_opts(HistogramOpts::new(
+                    "dispatch_latency_seconds",
+                    "Time from sending a request to receiving the response headers",
+                ))
+                .unwrap(),
+            ),
+            dispatch_outcomes: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new(
+                        "dispatch_outcomes_total",
+                        "Dispatched requests by status code class",
+                    ),
+                    &["class"],
+                )
+                .unwrap(),
+            ),
+            retries: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new("retries_total", "Retried attempts by kind"),
+                    &["kind"],
+                )
+                .unwrap(),
+            ),
+            callbacks: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new("callbacks_total", "Callbacks by result"),
+                    &["result"],
+                )
+                .unwrap(),
+            ),
+            dispatch_queued: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new(
+                        "dispatch_queued",
+                        "Due requests waiting for a dispatch worker by priority",
+                    ),
+                    &["priority"],
+                )
+                .unwrap(),
+            ),
+            dispatch_in_flight: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new("dispatch_in_flight", "Requests in flight by priority"),
+                    &["priority"],
+                )
+                .unwrap(),
+            ),
+            actors: register(
+                r,
+                IntGaugeVec::new(
+                    Opts::new("active_actors", "Running actors by kind"),
+                    &["kind"],
+                )
+                .unwrap(),
+            ),
+            db_size: register(
+                r,
+                IntGauge::new("db_size_bytes", "Size of the database on disk").unwrap(),
+            ),
+            db_flush: register(
+                r,
+                Histogram::with_opts(HistogramOpts::new(
+                    "db_flush_seconds",
+                    "Duration of database flushes",
+                ))
+                .unwrap(),
+            ),
+            http_requests: register(
+                r,
+                IntCounterVec::new(
+                    Opts::new("http_requests_total", "API requests"),
+                    &["method", "path", "status"],
+                )
+                .unwrap(),
+            ),
+            http_duration: register(
+                r,
+                HistogramVec::new(
+                    HistogramOpts::new("http_request_duration_seconds", "Duration of API requests"),
+                    &["method", "path"],
+                )
+                .unwrap(),
+            ),
+            registry,
+        };
+        // series of rare events are exported from the start, so rates over them are defined
+        for result in ["delivered", "failed"] {
+            metrics.callbacks.with_label_values(&[result]);
+        }
+        // every kind is exported from the start, before any of its actors runs
+        for kind in ["schedule", "workflow"] {
+            metrics.actors.with_label_values(&[kind]);
+        }
+        metrics
+    }
+
+    /// Counts the outcome of a dispatched request by status code class.
+    pub fn record_outcome(&self, status: Option<u16>) {
+        let class = match status {
+            Some(100..=199) => "1xx",
+            Some(200..=299) => "2xx",
+            Some(300..=399) => "3xx",
+            Some(400..=499) => "4xx",
+            Some(500..=599) => "5xx",
+            _ => "error",
+        };
+        self.dispatch_outcomes.with_label_values(&[class]).inc();
+    }
+
+    /// Metrics in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf).into_owned())
+    }
+}
+
+pub(crate) fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
@@ -0,0 +1,255 @@
+use crate::api::dto::SuccessDto;
+use crate::db::schema::{MatchesDocument, SuccessDocument};
+use crate::scheduler::dispatcher::DispatchOutcome;
+use regex::Regex;
+use serde_json_path::JsonPath;
+
+/// Result of evaluating success criteria against a response.
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    pub succeeded: bool,
+    /// Reason of the first failed criterion
+    pub error: Option<String>,
+    pub matches: Option<MatchesDocument>,
+}
+
+/// Parses `200-299`, `2xx` or `304` into an inclusive range.
+fn status_range(s: &str) -> Result<(u16, u16), String> {
+    let s = s.trim();
+    let invalid = || format!("invalid status range {}", s);
+    if let Some((from, to)) = s.split_once('-') {
+        let from = from.trim().parse().map_err(|_| invalid())?;
+        let to = to.trim().parse().map_err(|_| invalid())?;
+        if from > to {
+            return Err(invalid());
+        }
+        Ok((from, to))
+    } else if let Some(class) = s.strip_suffix("xx").or_else(|| s.strip_suffix("XX")) {
+        let class: u16 = class.parse().map_err(|_| invalid())?;
+        if !(1..=5).contains(&class) {
+            return Err(invalid());
+        }
+        Ok((class * 100, class * 100 + 99))
+    } else {
+        let code = s.parse().map_err(|_| invalid())?;
+        Ok((code, code))
+    }
+}
+
+/// Validates success criteria before the schedule is stored.
+pub fn check(success: &SuccessDto) -> Result<(), String> {
+    for range in success.status.iter().flatten() {
+        status_range(range)?;
+    }
+    if let Some(path) = &success.json_path {
+        JsonPath::parse(path).map_err(|e| format!("invalid json_path: {}", e))?;
+    }
+    if let Some(regex) = &success.body_regex {
+        Regex::new(regex).map_err(|e| format!("invalid body_regex: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Whether evaluating the criteria requires the response body.
+pub fn needs_body(success: Option<&SuccessDocument>) -> bool {
+    success
+        .map(|s| s.json_path.is_some() || s.body_regex.is_some())
+        .unwrap_or(false)
+}
+
+pub fn evaluate(success: Option<&SuccessDocument>, outcome: &DispatchOutcome) -> Evaluation {
+    let default = SuccessDocument::default();
+    let success = success.unwrap_or(&default);
+    let mut errors: Vec<String> = Vec::new();
+    let mut matches = MatchesDocument::default();
+
+    let accepted = match &success.status {
+        Some(ranges) => ranges
+            .iter()
+            .filter_map(|r| status_range(r).ok())
+            .any(|(from, to)| (from..=to).contains(&outcome.status)),
+        None => (200..300).contains(&outcome.status),
+    };
+    if !accepted {
+        errors.push(format!("status {} is not accepted", outcome.status));
+    }
+
+    if let Some(max) = success.max_latency_ms {
+        let latency = outcome.latency.as_millis() as u64;
+        if latency > max {
+            errors.push(format!("latency {}ms exceeds {}ms", latency, max));
+        }
+    }
+
+    let body = outcome.body.as_deref().unwrap_or_default();
+    if outcome.body_truncated && needs_body(Some(success)) {
+        // criteria can't be evaluated on part of the body
+        errors.push(format!(
+            "response body exceeds {} bytes read for assertions",
+            body.len()
+        ));
+    } else if let Some(path) = &success.json_path {
+        match (
+            JsonPath::parse(path),
+            serde_json::from_slice::<serde_json::Value>(body),
+        ) {
+            (Ok(path), Ok(json)) => {
+                let selected: Vec<serde_json::Value> =
+                    path.query(&json).all().into_iter().cloned().collect();
+                if selected.is_empty() {
+                    errors.push("json_path selected nothing".to_string());
+                }
+                matches.json_path = Some(selected);
+            }
+            (Err(e), _) => errors.push(format!("invalid json_path: {}", e)),
+            (_, Err(e)) => errors.push(format!("response body is not JSON: {}", e)),
+        }
+    }
+
+    if let Some(regex) = success
+        .body_regex
+        .as_ref()
+        .filter(|_| !outcome.body_truncated)
+    {
+        match Regex::new(regex) {
+            Ok(regex) => {
+                let body = String::from_utf8_lossy(body);
+                match regex.captures(&body) {
+                    Some(captures) => {
+                        matches.body_regex = Some(
+                            captures
+                                .iter()
+                                .map(|c| c.map(|c| c.as_str().to_string()))
+                                .collect(),
+                        )
+                    }
+                    None => errors.push("body_regex did not match".to_string()),
+                }
+            }
+            Err(e) => errors.push(format!("invalid body_regex: {}", e)),
+        }
+    }
+
+    let has_matches = matches.json_path.is_some() || matches.body_regex.is_some();
+    Evaluation {
+        succeeded: errors.is_empty(),
+        error: if errors.is_empty() {
+            None
+        } else {
+            Some(errors.join("; "))
+        },
+        matches: has_matches.then_some(matches),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    fn outcome(status: u16, body: &str, truncated: bool) -> DispatchOutcome {
+        DispatchOutcome {
+            status,
+            latency: Duration::from_millis(10),
+            headers: HeaderMap::new(),
+            body: Some(body.as_bytes().to_vec()),
+            body_truncated: truncated,
+        }
+    }
+
+    fn success(json_path: Option<&str>, body_regex: Option<&str>) -> SuccessDocument {
+        SuccessDocument {
+            json_path: json_path.map(String::from),
+            body_regex: body_regex.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_status_ranges() {
+        assert_eq!(status_range("200-299"), Ok((200, 299)));
+        assert_eq!(status_range("4xx"), Ok((400, 499)));
+        assert_eq!(status_range(" 304 "), Ok((304, 304)));
+        assert!(status_range("299-200").is_err());
+        assert!(status_range("6xx").is_err());
+        assert!(status_range("ok").is_err());
+    }
+
+    #[test]
+    fn defaults_to_2xx() {
+        assert!(evaluate(None, &outcome(204, "", false)).succeeded);
+        let evaluation = evaluate(None, &outcome(500, "", false));
+        assert!(!evaluation.succeeded);
+        assert_eq!(
+            evaluation.error.as_deref(),
+            Some("status 500 is not accepted")
+        );
+    }
+
+    #[test]
+    fn checks_latency() {
+        let success = SuccessDocument {
+            max_latency_ms: Some(5),
+            ..Default::default()
+        };
+        assert!(!evaluate(Some(&success), &outcome(200, "", false)).succeeded);
+    }
+
+    #[test]
+    fn selects_json_path() {
+        let success = success(Some("$.items[?@.state == 'done']"), None);
+        let evaluation = evaluate(
+            Some(&success),
+            &outcome(200, r#"{"items":[{"state":"done"}]}"#, false),
+        );
+        assert!(evaluation.succeeded);
+        assert_eq!(evaluation.matches.unwrap().json_path.unwrap().len(), 1);
+
+        let evaluation = evaluate(
+            Some(&success),
+            &outcome(200, r#"{"items":[{"state":"pending"}]}"#, false),
+        );
+        assert!(!evaluation.succeeded);
+        assert!(!evaluate(Some(&success), &outcome(200, "not json", false)).succeeded);
+    }
+
+    #[test]
+    fn captures_body_regex() {
+        let success = success(None, Some(r"processed (\d+) rows"));
+        let evaluation = evaluate(Some(&success), &outcome(200, "processed 42 rows", false));
+        assert!(evaluation.succeeded);
+        assert_eq!(
+            evaluation.matches.unwrap().body_regex.unwrap(),
+            vec![
+                Some("processed 42 rows".to_string()),
+                Some("42".to_string())
+            ]
+        );
+        assert!(!evaluate(Some(&success), &outcome(200, "nothing", false)).succeeded);
+    }
+
+    #[test]
+    fn fails_on_truncated_body() {
+        let success = success(Some("$.ok"), Some("ok"));
+        let evaluation = evaluate(Some(&success), &outcome(200, r#"{"ok":true"#, true));
+        assert!(!evaluation.succeeded);
+        assert!(evaluation.matches.is_none());
+        assert!(evaluation.error.unwrap().contains("exceeds"));
+        // a truncated body doesn't matter when no criterion needs it
+        assert!(evaluate(None, &outcome(200, "ok", true)).succeeded);
+    }
+
+    #[test]
+    fn rejects_invalid_criteria() {
+        let dto = |json_path: Option<&str>, body_regex: Option<&str>| SuccessDto {
+            status: Some(vec!["2xx".to_string()]),
+            json_path: json_path.map(String::from),
+            body_regex: body_regex.map(String::from),
+            max_latency_ms: None,
+        };
+        assert!(check(&dto(Some("$.a"), Some("a+"))).is_ok());
+        assert!(check(&dto(Some("$["), None)).is_err());
+        assert!(check(&dto(None, Some("("))).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+
+use crate::api::dto::FollowUpDto;
+use crate::db::schema::{FollowUpDocument, ScheduleDocument, ScheduleId};
+use crate::db::ScheduleRepository;
+use crate::scheduler::dispatcher::Dispatcher;
+
+/// Ids of schedules referenced by follow-ups, inline requests are skipped.
+fn referenced<'a>(
+    on_success: Option<&'a Vec<FollowUpDocument>>,
+    on_failure: Option<&'a Vec<FollowUpDocument>>,
+) -> impl Iterator<Item = &'a ScheduleId> {
+    on_success
+        .into_iter()
+        .chain(on_failure)
+        .flatten()
+        .filter_map(|f| match f {
+            FollowUpDocument::Schedule(id) => Some(id),
+            FollowUpDocument::Request { .. } => None,
+        })
+}
+
+/// Validates follow-ups of schedule `id` before it is stored. Inline requests must be valid,
+/// referenced schedules must exist and following the chain must never lead back to `id`.
+pub async fn check(
+    id: &ScheduleId,
+    on_success: Option<&Vec<FollowUpDto>>,
+    on_failure: Option<&Vec<FollowUpDto>>,
+    repo: &ScheduleRepository,
+    dispatcher: &Dispatcher,
+) -> Result<(), String> {
+    check_requests(on_success, on_failure, dispatcher)?;
+    check_references(id, on_success, on_failure, repo).await
+}
+
+/// Validates inline requests of follow-ups.
+pub fn check_requests(
+    on_success: Option<&Vec<FollowUpDto>>,
+    on_failure: Option<&Vec<FollowUpDto>>,
+    dispatcher: &Dispatcher,
+) -> Result<(), String> {
+    for follow_up in on_success.into_iter().chain(on_failure).flatten() {
+        if let FollowUpDto::Request { request } = follow_up {
+            dispatcher
+                .check_request(request)
+                .map_err(|e| format!("invalid follow-up request: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Validates the schedules referenced by follow-ups of schedule `id` against the stored ones.
+/// Cluster members check them when they apply the write, where writes are serialized, as a
+/// replica may be behind and concurrent writes may together form a cycle.
+pub async fn check_references(
+    id: &ScheduleId,
+    on_success: Option<&Vec<FollowUpDto>>,
+    on_failure: Option<&Vec<FollowUpDto>>,
+    repo: &ScheduleRepository,
+) -> Result<(), String> {
+    let mut pending: Vec<ScheduleId> = on_success
+        .into_iter()
+        .chain(on_failure)
+        .flatten()
+        .filter_map(|f| match f {
+            FollowUpDto::Schedule(next) => Some(next.clone()),
+            FollowUpDto::Request { .. } => None,
+        })
+        .collect();
+    for next in &pending {
+        let exists = repo
+            .get::<ScheduleDocument>(next.clone())
+            .await
+            .map_err(|e| e.to_string())?
+            .is_some();
+        if !exists && next != id {
+            return Err(format!("follow-up schedule {} not found", next));
+        }
+    }
+
+    let mut visited: HashSet<ScheduleId> = HashSet::new();
+    while let Some(next) = pending.pop() {
+        if &next == id {
+            return Err(format!("follow-ups of {} form a cycle", id));
+        }
+        if !visited.insert(next.clone()) {
+            continue;
+        }
+        let schedule = repo
+            .get::<ScheduleDocument>(next)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(schedule) = schedule {
+            pending.extend(
+                referenced(schedule.on_success.as_ref(), schedule.on_failure.as_ref()).cloned(),
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn follow_ups(ids: &[&str]) -> Option<Vec<FollowUpDto>> {
+        Some(
+            ids.iter()
+                .map(|id| FollowUpDto::Schedule(id.to_string()))
+                .collect(),
+        )
+    }
+
+    async fn create(repo: &ScheduleRepository, id: &str, on_success: &[&str]) {
+        let params = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "request": {"url": "http://localhost/", "method": "GET"},
+            "schedule": "0 * * * * *",
+            "on_success": on_success,
+        }))
+        .unwrap();
+        repo.create_schedule(params).await.unwrap();
+    }
+
+    async fn check(repo: &ScheduleRepository, id: &str, on_success: &[&str]) -> Result<(), String> {
+        check_references(&id.to_string(), follow_ups(on_success).as_ref(), None, repo).await
+    }
+
+    fn repo() -> ScheduleRepository {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        ScheduleRepository::new(&db)
+    }
+
+    #[tokio::test]
+    async fn accepts_chains_without_cycles() {
+        let repo = repo();
+        create(&repo, "c", &[]).await;
+        create(&repo, "b", &["c"]).await;
+        assert!(check(&repo, "a", &["b", "c"]).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_schedules() {
+        let repo = repo();
+        let error = check(&repo, "a", &["b"]).await.unwrap_err();
+        assert_eq!(error, "follow-up schedule b not found");
+    }
+
+    #[tokio::test]
+    async fn rejects_cycles() {
+        let repo = repo();
+        assert!(check(&repo, "a", &["a"]).await.is_err());
+
+        create(&repo, "c", &[]).await;
+        create(&repo, "b", &["c"]).await;
+        create(&repo, "a", &["b"]).await;
+        // c following a closes the cycle a -> b -> c -> a
+        let error = check(&repo, "c", &["a"]).await.unwrap_err();
+        assert_eq!(error, "follow-ups of c form a cycle");
+    }
+}
@@ -0,0 +1,678 @@
+use crate::api::dto::{CallbackDto, DispatchQueueDto, LimiterStatusDto, RequestDto};
+use crate::config;
+use crate::db::schema::{
+    CallbackDocument, CaptureDocument, OutboundRequestDocument, RequestBody, RequestDocument,
+    RequestHeaders, ResponseDocument, ScheduleDocument, ScheduleId,
+};
+use crate::metrics::metrics;
+use crate::scheduler::assertion;
+use crate::scheduler::limiter::Limiters;
+use crate::scheduler::queue::DispatchQueue;
+use crate::scheduler::template::{self, TemplateContext};
+use base64::Engine;
+use opentelemetry::global;
+use opentelemetry::propagation::Injector;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Identity, Method};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{event, Level};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+#[derive(Debug, Error)]
+pub enum DispatchError {
+    #[error("invalid request method: {0}")]
+    InvalidMethod(String),
+    #[error("invalid request url: {0}")]
+    InvalidUrl(String),
+    #[error("invalid request header: {0}")]
+    InvalidHeader(String),
+    #[error("invalid request body: {0}")]
+    InvalidBody(String),
+    #[error("{0}")]
+    Template(String),
+    #[error("invalid TLS configuration: {0}")]
+    Tls(String),
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("unexpected response status {0}")]
+    Status(u16),
+}
+
+/// Result of a request that reached the upstream.
+#[derive(Clone, Debug)]
+pub struct DispatchOutcome {
+    /// Response status code
+    pub status: u16,
+    /// Time from sending the request until response headers were received,
+    /// time spent waiting for the limiter is not included
+    pub latency: Duration,
+    /// Response headers
+    pub headers: HeaderMap,
+    /// Response body, read if the success criteria need it or it is captured
+    pub body: Option<Vec<u8>>,
+    /// Whether reading the body stopped at the capture limit
+    pub body_truncated: bool,
+}
+
+/// Body posted to the callback url of a schedule when one of its runs finished.
+#[derive(Clone, Debug, Serialize)]
+pub struct CallbackPayload {
+    /// Schedule id
+    pub id: ScheduleId,
+    /// Execution of the run's last attempt
+    pub execution_id: u64,
+    pub run: u64,
+    /// Whether the run was triggered manually
+    pub manual: bool,
+    /// `succeeded` or `failed`
+    pub status: &'static str,
+    /// Response status code, unset if no response was received
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub scheduled_at: chrono::DateTime<chrono::Utc>,
+    pub executed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Settings that can only be applied to the whole client, requests sharing them share a client.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ClientOptions {
+    connect_timeout: Duration,
+    max_redirects: usize,
+    ca_bundle: Option<String>,
+    client_identity: Option<String>,
+    insecure_skip_verify: bool,
+}
+
+/// Sends scheduled requests to their destinations.
+pub struct Dispatcher {
+    clients: Mutex<HashMap<ClientOptions, reqwest::Client>>,
+    limiters: Limiters,
+    queue: Arc<DispatchQueue>,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_redirects: usize,
+    tls_dir: PathBuf,
+    allow_insecure_tls: bool,
+    retry: Vec<u32>,
+    capture_body: bool,
+    capture_max_body_bytes: usize,
+    assert_max_body_bytes: usize,
+    capture_headers: Vec<String>,
+    dry_run: bool,
+    callback_client: reqwest::Client,
+    callback_retry: Vec<u32>,
+}
+
+impl Dispatcher {
+    pub fn from_env() -> Self {
+        Self {
+            clients: Mutex::new(HashMap::new()),
+            limiters: Limiters::new(config::dispatcher::rate_limits()),
+            queue: DispatchQueue::new(
+                config::dispatcher::workers(),
+                config::dispatcher::reserved(),
+            ),
+            connect_timeout: config::dispatcher::connect_timeout(),
+            timeout: config::dispatcher::timeout(),
+            max_redirects: config::dispatcher::max_redirects(),
+            tls_dir: config::dispatcher::tls_dir(),
+            allow_insecure_tls: config::dispatcher::allow_insecure_tls(),
+            retry: config::dispatcher::retry(),
+            capture_body: config::dispatcher::capture_body(),
+            capture_max_body_bytes: config::dispatcher::capture_max_body_bytes(),
+            assert_max_body_bytes: config::dispatcher::assert_max_body_bytes(),
+            capture_headers: config::dispatcher::capture_headers(),
+            dry_run: config::dispatcher::dry_run(),
+            callback_client: reqwest::Client::builder()
+                .use_rustls_tls()
+                .timeout(config::dispatcher::callback_timeout())
+                .build()
+                .expect("Failed to build callback client"),
+            callback_retry: config::dispatcher::callback_retry(),
+        }
+    }
+
+    fn body_capture_limit(&self, capture: Option<&CaptureDocument>) -> Option<usize> {
+        let capture_body = capture.and_then(|c| c.body).unwrap_or(self.capture_body);
+        if capture_body {
+            Some(
+                capture
+                    .and_then(|c| c.max_body_bytes)
+                    .unwrap_or(self.capture_max_body_bytes),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// Snapshot of the response to keep with the execution, along with the captured body.
+    pub fn capture(
+        &self,
+        capture: Option<&CaptureDocument>,
+        outcome: &DispatchOutcome,
+    ) -> (ResponseDocument, Option<Vec<u8>>) {
+        let names = capture
+            .and_then(|c| c.headers.as_ref())
+            .unwrap_or(&self.capture_headers);
+        let headers = names
+            .iter()
+            .filter_map(|name| {
+                let value = outcome.headers.get(name.as_str())?.to_str().ok()?;
+                Some((name.to_lowercase(), value.to_string()))
+            })
+            .collect();
+        let body = match (self.body_capture_limit(capture), &outcome.body) {
+            (Some(limit), Some(body)) => Some(body[..body.len().min(limit)].to_vec()),
+            _ => None,
+        };
+        let body_truncated = match (&body, &outcome.body) {
+            (Some(captured), Some(body)) => captured.len() < body.len() || outcome.body_truncated,
+            _ => false,
+        };
+        let response = ResponseDocument {
+            headers,
+            body_captured: body.is_some(),
+            body_size: body.as_ref().map(|b| b.len()).unwrap_or_default(),
+            body_truncated,
+        };
+        (response, body)
+    }
+
+    /// Delays in seconds before each retry of a failed execution.
+    pub fn retry_delays(&self, request: &RequestDocument) -> Vec<u32> {
+        request.retry.clone().unwrap_or_else(|| self.retry.clone())
+    }
+
+    /// Checks a request before it is stored, so that misconfigured
+    /// schedules are rejected instead of failing on every tick.
+    pub fn check_request(&self, request: &RequestDto) -> Result<(), DispatchError> {
+        if let Some(body) = &request.body {
+            encode_body(body)?;
+        }
+        if request.template.unwrap_or(false) {
+            template::check(
+                &request.url,
+                request.headers.as_ref(),
+                request.body.as_ref(),
+            )
+            .map_err(DispatchError::Template)?;
+        }
+        self.check_tls(
+            request.ca_bundle.as_deref(),
+            request.client_identity.as_deref(),
+            request.insecure_skip_verify.unwrap_or(false),
+        )
+    }
+
+    /// Checks a callback before it is stored.
+    pub fn check_callback(&self, callback: &CallbackDto) -> Result<(), DispatchError> {
+        reqwest::Url::parse(&callback.url)
+            .map_err(|e| DispatchError::InvalidUrl(format!("{}: {}", callback.url, e)))?;
+        header_map(callback.headers.as_ref()).map(|_| ())
+    }
+
+    fn check_tls(
+        &self,
+        ca_bundle: Option<&str>,
+        client_identity: Option<&str>,
+        insecure_skip_verify: bool,
+    ) -> Result<(), DispatchError> {
+        if insecure_skip_verify && !self.allow_insecure_tls {
+            return Err(DispatchError::Tls(
+                "insecure_skip_verify is not allowed on this server".to_string(),
+            ));
+        }
+        if let Some(name) = ca_bundle {
+            self.tls_file("ca", name)?;
+        }
+        if let Some(name) = client_identity {
+            self.tls_file("identity", name)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a named TLS file, names are plain file names without extension.
+    fn tls_file(&self, kind: &str, name: &str) -> Result<PathBuf, DispatchError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            && !name.starts_with('.');
+        if !valid {
+            return Err(DispatchError::Tls(format!(
+                "invalid {} name {}",
+                kind, name
+            )));
+        }
+        let path = self.tls_dir.join(kind).join(format!("{}.pem", name));
+        if !path.is_file() {
+            return Err(DispatchError::Tls(format!("{} {} not found", kind, name)));
+        }
+        Ok(path)
+    }
+
+    fn client(&self, request: &RequestDocument) -> Result<reqwest::Client, DispatchError> {
+        let options = ClientOptions {
+            connect_timeout: request
+                .connect_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(self.connect_timeout),
+            max_redirects: request.max_redirects.unwrap_or(self.max_redirects),
+            ca_bundle: request.ca_bundle.clone(),
+            client_identity: request.client_identity.clone(),
+            insecure_skip_verify: request.insecure_skip_verify.unwrap_or(false),
+        };
+        if let Some(client) = self.clients.lock().unwrap().get(&options) {
+            return Ok(client.clone());
+        }
+        self.check_tls(
+            options.ca_bundle.as_deref(),
+            options.client_identity.as_deref(),
+            options.insecure_skip_verify,
+        )?;
+
+        let redirect = if options.max_redirects == 0 {
+            Policy::none()
+        } else {
+            Policy::limited(options.max_redirects)
+        };
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .connect_timeout(options.connect_timeout)
+            .redirect(redirect)
+            .danger_accept_invalid_certs(options.insecure_skip_verify);
+        if let Some(name) = &options.ca_bundle {
+            let pem = std::fs::read(self.tls_file("ca", name)?)
+                .map_err(|e| DispatchError::Tls(format!("reading ca {}: {}", name, e)))?;
+            for cert in Certificate::from_pem_bundle(&pem)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(name) = &options.client_identity {
+            let pem = std::fs::read(self.tls_file("identity", name)?)
+                .map_err(|e| DispatchError::Tls(format!("reading identity {}: {}", name, e)))?;
+            builder = builder.identity(Identity::from_pem(&pem)?);
+        }
+        let client = builder.build()?;
+        self.clients.lock().unwrap().insert(options, client.clone());
+        Ok(client)
+    }
+
+    /// Renders the request of the schedule if it is templated and builds it.
+    fn prepare(
+        &self,
+        schedule: &ScheduleDocument,
+        ctx: &TemplateContext,
+    ) -> Result<(RequestDocument, reqwest::Request), DispatchError> {
+        let request = if schedule.request.template.unwrap_or(false) {
+            template::render(&schedule.request, ctx).map_err(DispatchError::Template)?
+        } else {
+            schedule.request.clone()
+        };
+        let method = Method::from_str(&request.method.to_uppercase())
+            .map_err(|_| DispatchError::InvalidMethod(request.method.clone()))?;
+        let url = reqwest::Url::parse(&request.url)
+            .map_err(|e| DispatchError::InvalidUrl(format!("{}: {}", request.url, e)))?;
+        let mut headers = header_map(request.headers.as_ref())?;
+        let mut req = reqwest::Request::new(method, url);
+        if let Some(body) = &request.body {
+            let (bytes, content_type) = encode_body(body)?;
+            if !headers.contains_key(CONTENT_TYPE) {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            }
+            *req.body_mut() = Some(bytes.into());
+        }
+        *req.headers_mut() = headers;
+        *req.timeout_mut() = Some(
+            request
+                .timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(self.timeout),
+        );
+        Ok((request, req))
+    }
+
+    /// Whether all schedules run in dry-run mode regardless of their settings.
+    pub fn dry_run_forced(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Builds the request exactly as it would be sent, without sending it.
+    #[tracing::instrument(skip(self, schedule, ctx), fields(id = %schedule.id))]
+    pub fn dry_run(
+        &self,
+        schedule: &ScheduleDocument,
+        ctx: &TemplateContext,
+    ) -> Result<OutboundRequestDocument, DispatchError> {
+        let (_, req) = self.prepare(schedule, ctx)?;
+        let headers = req
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).to_string(),
+                )
+            })
+            .collect();
+        let body = req.body().and_then(|b| b.as_bytes()).map(|bytes| {
+            match String::from_utf8(bytes.to_vec()) {
+                Ok(text) => RequestBody::Text(text),
+                Err(_) => RequestBody::Base64 {
+                    base64: base64::engine::general_purpose::STANDARD.encode(bytes),
+                },
+            }
+        });
+        Ok(OutboundRequestDocument {
+            method: req.method().to_string(),
+            url: req.url().to_string(),
+            headers,
+            body,
+        })
+    }
+
+    #[tracing::instrument(
+        skip(self, schedule, ctx),
+        fields(id = %schedule.id, run = ctx.run, attempt = ctx.attempt, otel.kind = "client")
+    )]
+    pub async fn dispatch(
+        &self,
+        schedule: &ScheduleDocument,
+        ctx: &TemplateContext,
+    ) -> Result<DispatchOutcome, DispatchError> {
+        let (request, mut req) = self.prepare(schedule, ctx)?;
+        inject_trace_context(req.headers_mut());
+        let request = &request;
+        let client = self.client(request)?;
+
+        let tags = schedule.tags.as_deref().unwrap_or_default();
+        let priority = schedule.priority.unwrap_or_default();
+        // requests of a throttled group wait for their limiter without holding a worker, so
+        // only that group queues, and go through it by priority
+        let _permit = match self.limiters.resolve(request, tags) {
+            Some(limiter) => {
+                if limiter.queued() > 0 {
+                    event!(
+                        Level::DEBUG,
+                        queued = limiter.queued(),
+                        "Waiting for limiter"
+                    );
+                }
+                Some(limiter.acquire(priority).await)
+            }
+            None => None,
+        };
+        let _worker = self.queue.acquire(priority).await;
+
+        let started = std::time::Instant::now();
+        let response = client
+            .execute(req)
+            .await
+            .inspect_err(|_| metrics().record_outcome(None))?;
+        let latency = started.elapsed();
+        let status = response.status().as_u16();
+        metrics().dispatch_latency.observe(latency.as_secs_f64());
+        metrics().record_outcome(Some(status));
+        let headers = response.headers().clone();
+        let capture_limit = self.body_capture_limit(schedule.capture.as_ref());
+        let limit = if assertion::needs_body(schedule.success.as_ref()) {
+            Some(capture_limit.map_or(self.assert_max_body_bytes, |limit| {
+                limit.max(self.assert_max_body_bytes)
+            }))
+        } else {
+            capture_limit
+        };
+        let (body, body_truncated) = match limit {
+            Some(limit) => read_body(response, limit).await?,
+            None => (None, false),
+        };
+        Ok(DispatchOutcome {
+            status,
+            latency,
+            headers,
+            body,
+            body_truncated,
+        })
+    }
+
+    /// Posts the outcome of a run to the schedule's callback url, retrying while retry delays
+    /// last. Callbacks don't wait for dispatch workers or limiters.
+    #[tracing::instrument(
+        skip(self, callback, payload),
+        fields(id = %payload.id, run = payload.run, otel.kind = "client")
+    )]
+    pub async fn callback(&self, callback: &CallbackDocument, payload: &CallbackPayload) {
+        let mut delays = self.callback_retry.iter();
+        loop {
+            let error = match self.send_callback(callback, payload).await {
+                Ok(()) => {
+                    metrics().callbacks.with_label_values(&["delivered"]).inc();
+                    return;
+                }
+                Err(e) => e,
+            };
+            match delays.next() {
+                Some(delay) => {
+                    metrics().retries.with_label_values(&["callback"]).inc();
+                    log::warn!(
+                        "Callback of run {} of {} failed: {}, retrying in {}s",
+                        payload.run,
+                        payload.id,
+                        error,
+                        delay
+                    );
+                    tokio::time::sleep(Duration::from_secs(*delay as u64)).await;
+                }
+                None => {
+                    metrics().callbacks.with_label_values(&["failed"]).inc();
+                    log::error!(
+                        "Callback of run {} of {} failed: {}",
+                        payload.run,
+                        payload.id,
+                        error
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn send_callback(
+        &self,
+        callback: &CallbackDocument,
+        payload: &CallbackPayload,
+    ) -> Result<(), DispatchError> {
+        let mut headers = header_map(callback.headers.as_ref())?;
+        inject_trace_context(&mut headers);
+        let response = self
+            .callback_client
+            .post(&callback.url)
+            .headers(headers)
+            .json(payload)
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => Err(DispatchError::Status(status.as_u16())),
+        }
+    }
+
+    pub fn limiter_status(&self) -> Vec<LimiterStatusDto> {
+        self.limiters.status()
+    }
+
+    pub fn queue_status(&self) -> DispatchQueueDto {
+        self.queue.status()
+    }
+}
+
+/// Reads the response body, stopping after `limit` bytes.
+async fn read_body(
+    mut response: reqwest::Response,
+    limit: usize,
+) -> Result<(Option<Vec<u8>>, bool), reqwest::Error> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > limit {
+            body.truncate(limit);
+            return Ok((Some(body), true));
+        }
+    }
+    Ok((Some(body), false))
+}
+
+/// Encodes the body and returns it with the Content-Type used when the request doesn't set one.
+pub fn encode_body(body: &RequestBody) -> Result<(Vec<u8>, &'static str), DispatchError> {
+    match body {
+        RequestBody::Text(text) => Ok((text.as_bytes().to_vec(), "text/plain; charset=utf-8")),
+        RequestBody::Json { json } => Ok((
+            serde_json::to_vec(json).map_err(|e| DispatchError::InvalidBody(e.to_string()))?,
+            "application/json",
+        )),
+        RequestBody::Base64 { base64 } => Ok((
+            base64::engine::general_purpose::STANDARD
+                .decode(base64)
+                .map_err(|e| DispatchError::InvalidBody(format!("invalid base64: {}", e)))?,
+            "application/octet-stream",
+        )),
+        RequestBody::Form { form } => Ok((
+            serde_urlencoded::to_string(form)
+                .map_err(|e| DispatchError::InvalidBody(e.to_string()))?
+                .into_bytes(),
+            "application/x-www-form-urlencoded",
+        )),
+    }
+}
+
+fn header_map(headers: Option<&RequestHeaders>) -> Result<HeaderMap, DispatchError> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers.into_iter().flatten() {
+        let name =
+            HeaderName::from_str(name).map_err(|_| DispatchError::InvalidHeader(name.clone()))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| DispatchError::InvalidHeader(name.to_string()))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // trace headers set on the schedule are kept
+        if self.0.contains_key(key) {
+            return;
+        }
+        if let (Ok(name), Ok(value)) = (HeaderName::from_str(key), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Adds W3C `traceparent` of the current span, so the downstream's traces link to the dispatch.
+fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers each connection with the next status and sends back the request bodies.
+    async fn serve(statuses: Vec<u16>) -> (String, tokio::sync::mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/callback", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::mpsc::channel(statuses.len());
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                // the body is the last part sent, a JSON object
+                while !request.ends_with(b"}") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let body = request.split("\r\n\r\n").nth(1).unwrap_or_default();
+                tx.send(body.to_string()).await.unwrap();
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn payload() -> CallbackPayload {
+        let at = chrono::Utc::now();
+        CallbackPayload {
+            id: "nightly".to_string(),
+            execution_id: 3,
+            run: 1,
+            manual: true,
+            status: "failed",
+            status_code: Some(500),
+            error: None,
+            scheduled_at: at,
+            executed_at: at,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_callbacks_until_delivered() {
+        let (url, mut bodies) = serve(vec![503, 200]).await;
+        let mut dispatcher = Dispatcher::from_env();
+        dispatcher.callback_retry = vec![0, 0];
+        let callback = CallbackDocument { url, headers: None };
+        let delivered = metrics().callbacks.with_label_values(&["delivered"]).get();
+
+        dispatcher.callback(&callback, &payload()).await;
+
+        for _ in 0..2 {
+            let body: serde_json::Value =
+                serde_json::from_str(&bodies.recv().await.unwrap()).unwrap();
+            assert_eq!(body["id"], "nightly");
+            assert_eq!(body["status"], "failed");
+            assert_eq!(body["manual"], true);
+        }
+        assert_eq!(
+            metrics().callbacks.with_label_values(&["delivered"]).get(),
+            delivered + 1
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_callbacks() {
+        let dispatcher = Dispatcher::from_env();
+        let callback = |url: &str, header: &str| CallbackDto {
+            url: url.to_string(),
+            headers: Some([(header.to_string(), "x".to_string())].into()),
+        };
+        assert!(dispatcher
+            .check_callback(&callback("https://example.com", "X-Token"))
+            .is_ok());
+        assert!(dispatcher
+            .check_callback(&callback("example.com", "X-Token"))
+            .is_err());
+        assert!(dispatcher
+            .check_callback(&callback("https://example.com", "bad header"))
+            .is_err());
+    }
+}
@@ -0,0 +1,465 @@
+use crate::api::dto::LimiterStatusDto;
+use crate::db::schema::{Priority, RequestDocument};
+use crate::scheduler::queue;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Identifies a group of outbound requests sharing the same limits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LimiterGroup {
+    /// Requests targeting the same host
+    Host(String),
+    /// Requests of schedules carrying the tag
+    Tag(String),
+    /// Requests with explicit `rate_limit_key`
+    Key(String),
+}
+
+impl Display for LimiterGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimiterGroup::Host(host) => write!(f, "host:{}", host),
+            LimiterGroup::Tag(tag) => write!(f, "tag:{}", tag),
+            LimiterGroup::Key(key) => write!(f, "key:{}", key),
+        }
+    }
+}
+
+impl FromStr for LimiterGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("host", host)) if !host.is_empty() => Ok(Self::Host(host.to_lowercase())),
+            Some(("tag", tag)) if !tag.is_empty() => Ok(Self::Tag(tag.to_string())),
+            Some(("key", key)) if !key.is_empty() => Ok(Self::Key(key.to_string())),
+            _ => Err(format!(
+                "limiter group should be host:<host>, tag:<tag> or key:<key>, got {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Limits applied to a group, written as `<rate>/<s|m|h>[,<concurrency>]`, e.g. `20/s,5`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimiterSpec {
+    /// Number of requests allowed per `per`, also the bucket capacity
+    pub rate: u32,
+    /// Period in which `rate` requests are allowed
+    pub per: Duration,
+    /// Maximum number of requests in flight, unlimited if not set
+    pub concurrency: Option<usize>,
+}
+
+impl FromStr for LimiterSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rate, concurrency) = match s.split_once(',') {
+            Some((rate, concurrency)) => (rate, Some(concurrency)),
+            None => (s, None),
+        };
+        let (rate, per) = rate
+            .split_once('/')
+            .ok_or_else(|| format!("rate limit should be <rate>/<s|m|h>, got {}", s))?;
+        let rate: u32 = rate
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid rate in {}", s))?;
+        if rate == 0 {
+            return Err(format!("rate should be greater than zero in {}", s));
+        }
+        let per = match per.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(format!("invalid rate period in {}, use s, m or h", s)),
+        };
+        let concurrency = match concurrency {
+            Some(c) => match c.trim().parse::<usize>() {
+                Ok(c) if c > 0 => Some(c),
+                _ => return Err(format!("invalid concurrency in {}", s)),
+            },
+            None => None,
+        };
+        Ok(Self {
+            rate,
+            per,
+            concurrency,
+        })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Token bucket with optional concurrency cap. Callers wait by priority class, in arrival
+/// order within a class, until both a token and a concurrency slot are available, nothing is
+/// ever dropped.
+pub struct Limiter {
+    group: LimiterGroup,
+    spec: LimiterSpec,
+    bucket: tokio::sync::Mutex<Bucket>,
+    slots: Option<Arc<Semaphore>>,
+    /// Waiting callers by class then arrival, the first one is the next to go through
+    line: Mutex<BTreeSet<(usize, u64)>>,
+    arrivals: AtomicU64,
+    /// Woken whenever a caller leaves the line or a concurrency slot is released
+    turn: Arc<Notify>,
+    queued: AtomicUsize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Held while the request is in flight, releases the concurrency slot on drop.
+pub struct LimiterPermit {
+    slot: Option<OwnedSemaphorePermit>,
+    in_flight: Arc<AtomicUsize>,
+    turn: Arc<Notify>,
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if self.slot.take().is_some() {
+            // the first caller in line takes the released slot
+            self.turn.notify_waiters();
+        }
+    }
+}
+
+/// Place of a caller in the line, left once it went through or gave up waiting.
+struct Place<'a> {
+    limiter: &'a Limiter,
+    ticket: (usize, u64),
+}
+
+impl Drop for Place<'_> {
+    fn drop(&mut self) {
+        self.limiter.line.lock().unwrap().remove(&self.ticket);
+        self.limiter.queued.fetch_sub(1, Ordering::SeqCst);
+        self.limiter.turn.notify_waiters();
+    }
+}
+
+impl Limiter {
+    pub fn new(group: LimiterGroup, spec: LimiterSpec) -> Self {
+        let slots = spec.concurrency.map(|c| Arc::new(Semaphore::new(c)));
+        Self {
+            group,
+            bucket: tokio::sync::Mutex::new(Bucket {
+                tokens: spec.rate as f64,
+                refilled_at: Instant::now(),
+            }),
+            spec,
+            slots,
+            line: Mutex::new(BTreeSet::new()),
+            arrivals: AtomicU64::new(0),
+            turn: Arc::new(Notify::new()),
+            queued: AtomicUsize::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Waits until the request is allowed to go out. Higher classes go through first.
+    pub async fn acquire(&self, priority: Priority) -> LimiterPermit {
+        let ticket = (
+            queue::index(priority),
+            self.arrivals.fetch_add(1, Ordering::SeqCst),
+        );
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.line.lock().unwrap().insert(ticket);
+        let place = Place {
+            limiter: self,
+            ticket,
+        };
+        // only the first caller in line takes a slot, so callers arriving later in a higher
+        // class still go first while every slot is taken
+        let slot = loop {
+            let turn = self.turn.notified();
+            tokio::pin!(turn);
+            // registered before checking, so a caller leaving meanwhile isn't missed
+            turn.as_mut().enable();
+            if self.line.lock().unwrap().first() == Some(&ticket) {
+                match &self.slots {
+                    None => break None,
+                    Some(slots) => {
+                        if let Ok(slot) = slots.clone().try_acquire_owned() {
+                            break Some(slot);
+                        }
+                    }
+                }
+            }
+            turn.await;
+        };
+        {
+            let mut bucket = self.bucket.lock().await;
+            loop {
+                let now = Instant::now();
+                let per_token = self.spec.per.as_secs_f64() / self.spec.rate as f64;
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed / per_token).min(self.spec.rate as f64);
+                bucket.refilled_at = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    break;
+                }
+                let wait = (1.0 - bucket.tokens) * per_token;
+                tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+            }
+        }
+        drop(place);
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        LimiterPermit {
+            slot,
+            in_flight: self.in_flight.clone(),
+            turn: self.turn.clone(),
+        }
+    }
+
+    /// Number of requests waiting for a token or a concurrency slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn status(&self) -> LimiterStatusDto {
+        LimiterStatusDto {
+            group: self.group.to_string(),
+            rate: self.spec.rate,
+            per_seconds: self.spec.per.as_secs(),
+            concurrency: self.spec.concurrency,
+            queued: self.queued(),
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Resolves outbound requests to their limiter groups.
+///
+/// Explicit `rate_limit_key` wins over tags, tags win over the host. A `host:*` entry
+/// applies its limits to every host without a dedicated entry, each host getting its own bucket.
+pub struct Limiters {
+    specs: HashMap<LimiterGroup, LimiterSpec>,
+    any_host: Option<LimiterSpec>,
+    active: Mutex<HashMap<LimiterGroup, Arc<Limiter>>>,
+}
+
+impl Limiters {
+    pub fn new(groups: Vec<(LimiterGroup, LimiterSpec)>) -> Self {
+        let mut specs = HashMap::new();
+        let mut any_host = None;
+        for (group, spec) in groups {
+            match group {
+                LimiterGroup::Host(ref host) if host == "*" => any_host = Some(spec),
+                group => {
+                    specs.insert(group, spec);
+                }
+            }
+        }
+        Self {
+            specs,
+            any_host,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the limiter for the request, or None if it is not limited.
+    pub fn resolve(&self, request: &RequestDocument, tags: &[String]) -> Option<Arc<Limiter>> {
+        let (group, spec) = self.group_for(request, tags)?;
+        let mut active = self.active.lock().unwrap();
+        let limiter = active
+            .entry(group.clone())
+            .or_insert_with(|| Arc::new(Limiter::new(group, spec)));
+        Some(limiter.clone())
+    }
+
+    fn group_for(
+        &self,
+        request: &RequestDocument,
+        tags: &[String],
+    ) -> Option<(LimiterGroup, LimiterSpec)> {
+        if let Some(key) = &request.rate_limit_key {
+            let group = LimiterGroup::Key(key.clone());
+            if let Some(spec) = self.specs.get(&group) {
+                return Some((group, spec.clone()));
+            }
+            log::debug!("No limits configured for {}", group);
+        }
+        for tag in tags {
+            let group = LimiterGroup::Tag(tag.clone());
+            if let Some(spec) = self.specs.get(&group) {
+                return Some((group, spec.clone()));
+            }
+        }
+        let host = reqwest::Url::parse(&request.url)
+            .ok()?
+            .host_str()?
+            .to_lowercase();
+        let group = LimiterGroup::Host(host);
+        match self.specs.get(&group) {
+            Some(spec) => Some((group, spec.clone())),
+            None => self.any_host.clone().map(|spec| (group, spec)),
+        }
+    }
+
+    /// Status of every limiter that has seen traffic.
+    pub fn status(&self) -> Vec<LimiterStatusDto> {
+        let active = self.active.lock().unwrap();
+        let mut status: Vec<LimiterStatusDto> = active.values().map(|l| l.status()).collect();
+        status.sort_by(|a, b| a.group.cmp(&b.group));
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str, rate_limit_key: Option<&str>) -> RequestDocument {
+        serde_json::from_value(serde_json::json!({
+            "method": "GET",
+            "url": url,
+            "rate_limit_key": rate_limit_key,
+        }))
+        .unwrap()
+    }
+
+    fn limiters(groups: &[(&str, &str)]) -> Limiters {
+        Limiters::new(
+            groups
+                .iter()
+                .map(|(group, spec)| (group.parse().unwrap(), spec.parse().unwrap()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_specs() {
+        let spec: LimiterSpec = "20/s,5".parse().unwrap();
+        assert_eq!(spec.rate, 20);
+        assert_eq!(spec.per, Duration::from_secs(1));
+        assert_eq!(spec.concurrency, Some(5));
+        let spec: LimiterSpec = "100/m".parse().unwrap();
+        assert_eq!(spec.per, Duration::from_secs(60));
+        assert_eq!(spec.concurrency, None);
+        for invalid in ["", "20", "0/s", "20/d", "20/s,0", "x/s"] {
+            assert!(invalid.parse::<LimiterSpec>().is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            "host:Example.com".parse::<LimiterGroup>(),
+            Ok(LimiterGroup::Host("example.com".to_string()))
+        );
+        assert!("host:".parse::<LimiterGroup>().is_err());
+        assert!("example.com".parse::<LimiterGroup>().is_err());
+    }
+
+    #[test]
+    fn resolves_key_then_tags_then_host() {
+        let limiters = limiters(&[
+            ("key:billing", "1/s"),
+            ("tag:reports", "2/s"),
+            ("host:example.com", "3/s"),
+            ("host:*", "4/s"),
+        ]);
+        let tags = vec!["reports".to_string()];
+        let group = |url, key, tags: &[String]| {
+            limiters
+                .resolve(&request(url, key), tags)
+                .map(|l| l.status().group)
+        };
+        assert_eq!(
+            group("http://example.com/", Some("billing"), &tags).as_deref(),
+            Some("key:billing")
+        );
+        // keys without limits fall back to tags
+        assert_eq!(
+            group("http://example.com/", Some("other"), &tags).as_deref(),
+            Some("tag:reports")
+        );
+        assert_eq!(
+            group("http://EXAMPLE.com/", None, &[]).as_deref(),
+            Some("host:example.com")
+        );
+        assert_eq!(
+            group("http://other.com/", None, &[]).as_deref(),
+            Some("host:other.com")
+        );
+        assert_eq!(limiters.status().len(), 4);
+        let unlimited = Limiters::new(vec![]);
+        assert!(unlimited
+            .resolve(&request("http://example.com/", None), &[])
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn waits_for_tokens() {
+        let limiter = Limiter::new(LimiterGroup::Key("k".to_string()), "20/s".parse().unwrap());
+        let started = Instant::now();
+        for _ in 0..20 {
+            drop(limiter.acquire(Priority::Normal).await);
+        }
+        assert!(started.elapsed() < Duration::from_millis(40));
+        // the bucket is empty, the next token comes after 50ms
+        drop(limiter.acquire(Priority::Normal).await);
+        assert!(started.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[tokio::test]
+    async fn caps_requests_in_flight() {
+        let limiter = Limiter::new(
+            LimiterGroup::Key("k".to_string()),
+            "1000/s,1".parse().unwrap(),
+        );
+        let permit = limiter.acquire(Priority::Normal).await;
+        assert_eq!(limiter.status().in_flight, 1);
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire(Priority::Normal))
+                .await;
+        assert!(waiting.is_err());
+        assert_eq!(limiter.queued(), 0);
+        drop(permit);
+        let permit =
+            tokio::time::timeout(Duration::from_millis(50), limiter.acquire(Priority::Normal))
+                .await;
+        assert!(permit.is_ok());
+        assert_eq!(limiter.status().in_flight, 1);
+    }
+
+    #[tokio::test]
+    async fn lets_higher_classes_through_first() {
+        let limiter = Arc::new(Limiter::new(
+            LimiterGroup::Key("k".to_string()),
+            "1000/s,1".parse().unwrap(),
+        ));
+        let permit = limiter.acquire(Priority::Normal).await;
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut waiting = vec![];
+        for priority in [Priority::Low, Priority::Normal, Priority::Critical] {
+            let (limiter, order) = (limiter.clone(), order.clone());
+            waiting.push(tokio::spawn(async move {
+                let _permit = limiter.acquire(priority).await;
+                order.lock().unwrap().push(priority);
+            }));
+            // arrivals are ordered
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(limiter.queued(), 3);
+        drop(permit);
+        for waiter in waiting {
+            waiter.await.unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::Critical, Priority::Normal, Priority::Low]
+        );
+        assert_eq!(limiter.queued(), 0);
+    }
+}
@@ -1,2 +1,10 @@
-mod schedule_actor;
-mod ticker;
+pub(crate) mod assertion;
+pub(crate) mod chain;
+pub(crate) mod dispatcher;
+pub(crate) mod limiter;
+pub(crate) mod queue;
+pub(crate) mod schedule_actor;
+pub(crate) mod supervisor;
+mod template;
+pub(crate) mod ticker;
+pub(crate) mod workflow;
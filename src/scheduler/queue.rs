@@ -0,0 +1,252 @@
+use crate::api::dto::{DispatchQueueDto, DispatchQueueStatusDto};
+use crate::db::schema::Priority;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Workers reserved for a priority class, written as `<class>=<workers>`, e.g. `critical=16`.
+pub fn parse_reservation(s: &str) -> Result<(Priority, usize), String> {
+    let (class, workers) = s
+        .split_once('=')
+        .ok_or_else(|| format!("reservation should be <class>=<workers>, got {}", s))?;
+    let class = Priority::from_str(class.trim())?;
+    let workers = workers
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid number of workers in {}", s))?;
+    Ok((class, workers))
+}
+
+#[derive(Default)]
+struct Class {
+    /// Workers only this class and higher classes may use
+    reserved: usize,
+    in_flight: usize,
+    /// Waiting requests in arrival order, with the instant they started waiting
+    waiters: VecDeque<(Instant, oneshot::Sender<QueuePermit>)>,
+    dispatched: u64,
+    wait_total: Duration,
+    wait_max: Duration,
+}
+
+impl Class {
+    fn record_wait(&mut self, wait: Duration) {
+        self.dispatched += 1;
+        self.wait_total += wait;
+        self.wait_max = self.wait_max.max(wait);
+    }
+}
+
+/// Bounds the number of requests in flight across all schedules. When all workers are busy,
+/// due requests wait by priority class, higher classes first and in arrival order within a
+/// class. Workers reserved for a class are never taken by lower classes, so a flood of low
+/// priority requests can't starve higher ones.
+pub struct DispatchQueue {
+    workers: usize,
+    /// Indexed by position of the class in `Priority::ALL`
+    classes: Mutex<[Class; 4]>,
+}
+
+/// Held while the request is in flight, passes the worker on to the next waiting request on drop.
+pub struct QueuePermit {
+    queue: Option<Arc<DispatchQueue>>,
+    class: usize,
+}
+
+impl Drop for QueuePermit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            let mut classes = queue.classes.lock().unwrap();
+            classes[self.class].in_flight -= 1;
+            queue.wake(&mut classes);
+        }
+    }
+}
+
+/// Position of the class in `Priority::ALL`, highest class first.
+pub(crate) fn index(priority: Priority) -> usize {
+    Priority::ALL.iter().position(|p| *p == priority).unwrap()
+}
+
+impl DispatchQueue {
+    pub fn new(workers: usize, reservations: Vec<(Priority, usize)>) -> Arc<Self> {
+        let mut classes: [Class; 4] = Default::default();
+        for (priority, reserved) in reservations {
+            classes[index(priority)].reserved = reserved;
+        }
+        Arc::new(Self {
+            workers,
+            classes: Mutex::new(classes),
+        })
+    }
+
+    /// Whether a request of the class at `index` may take a worker now. Unused reservations of
+    /// higher classes are kept free.
+    fn available(&self, classes: &[Class; 4], index: usize) -> bool {
+        let in_flight: usize = classes.iter().map(|c| c.in_flight).sum();
+        let held: usize = classes[..index]
+            .iter()
+            .map(|c| c.reserved.saturating_sub(c.in_flight))
+            .sum();
+        in_flight + held < self.workers
+    }
+
+    /// Hands free workers to waiting requests, highest class first.
+    fn wake(self: &Arc<Self>, classes: &mut [Class; 4]) {
+        for index in 0..classes.len() {
+            while !classes[index].waiters.is_empty() && self.available(classes, index) {
+                let (since, waiter) = classes[index].waiters.pop_front().unwrap();
+                classes[index].in_flight += 1;
+                let permit = QueuePermit {
+                    queue: Some(self.clone()),
+                    class: index,
+                };
+                match waiter.send(permit) {
+                    Ok(()) => classes[index].record_wait(since.elapsed()),
+                    Err(mut permit) => {
+                        // waiter gave up, release without locking again
+                        permit.queue = None;
+                        classes[index].in_flight -= 1;
+                    }
+                }
+            }
+            if !classes[index].waiters.is_empty() {
+                // lower classes can't be available if this one is not
+                break;
+            }
+        }
+    }
+
+    /// Waits for a worker.
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> QueuePermit {
+        let index = index(priority);
+        let waiter = {
+            let mut classes = self.classes.lock().unwrap();
+            let ahead = classes[..=index].iter().any(|c| !c.waiters.is_empty());
+            if !ahead && self.available(&classes, index) {
+                classes[index].in_flight += 1;
+                classes[index].record_wait(Duration::ZERO);
+                return QueuePermit {
+                    queue: Some(self.clone()),
+                    class: index,
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            classes[index].waiters.push_back((Instant::now(), tx));
+            rx
+        };
+        waiter.await.expect("dispatch queue never drops waiters")
+    }
+
+    pub fn status(&self) -> DispatchQueueDto {
+        let classes = self.classes.lock().unwrap();
+        let classes = Priority::ALL
+            .iter()
+            .zip(classes.iter())
+            .map(|(priority, class)| DispatchQueueStatusDto {
+                priority: *priority,
+                reserved: class.reserved,
+                queued: class.waiters.len(),
+                in_flight: class.in_flight,
+                dispatched: class.dispatched,
+                wait_ms_avg: (class.wait_total.as_millis() as u64)
+                    .checked_div(class.dispatched)
+                    .unwrap_or(0),
+                wait_ms_max: class.wait_max.as_millis() as u64,
+                oldest_wait_ms: class
+                    .waiters
+                    .front()
+                    .map(|(since, _)| since.elapsed().as_millis() as u64)
+                    .unwrap_or(0),
+            })
+            .collect();
+        DispatchQueueDto {
+            workers: self.workers,
+            classes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn acquired(queue: &Arc<DispatchQueue>, priority: Priority) -> Option<QueuePermit> {
+        tokio::time::timeout(Duration::from_millis(20), queue.acquire(priority))
+            .await
+            .ok()
+    }
+
+    #[test]
+    fn parses_reservations() {
+        assert_eq!(
+            parse_reservation("critical=16"),
+            Ok((Priority::Critical, 16))
+        );
+        assert!(parse_reservation("critical").is_err());
+        assert!(parse_reservation("urgent=1").is_err());
+        assert!(parse_reservation("low=x").is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_reserved_workers_for_their_class() {
+        let queue = DispatchQueue::new(3, vec![(Priority::Critical, 1)]);
+        let _first = acquired(&queue, Priority::Low).await.unwrap();
+        let _second = acquired(&queue, Priority::Low).await.unwrap();
+        // the last worker is reserved
+        assert!(acquired(&queue, Priority::Low).await.is_none());
+        assert!(acquired(&queue, Priority::High).await.is_none());
+        let _critical = acquired(&queue, Priority::Critical).await.unwrap();
+        let status = queue.status();
+        assert_eq!(status.classes[0].in_flight, 1);
+        assert_eq!(status.classes[3].in_flight, 2);
+    }
+
+    #[tokio::test]
+    async fn hands_freed_workers_to_higher_classes_first() {
+        let queue = DispatchQueue::new(1, vec![]);
+        let permit = acquired(&queue, Priority::Normal).await.unwrap();
+        let low = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::Low).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let high = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.acquire(Priority::High).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.status().classes[3].queued, 1);
+
+        drop(permit);
+        let high = high.await.unwrap();
+        assert!(!low.is_finished());
+        drop(high);
+        drop(low.await.unwrap());
+        let status = queue.status();
+        assert!(status
+            .classes
+            .iter()
+            .all(|c| c.in_flight == 0 && c.queued == 0));
+        assert_eq!(status.classes[3].dispatched, 1);
+    }
+
+    #[tokio::test]
+    async fn serves_lower_classes_once_higher_ones_are_done() {
+        let queue = DispatchQueue::new(2, vec![(Priority::Critical, 1)]);
+        let critical = acquired(&queue, Priority::Critical).await.unwrap();
+        let critical_again = acquired(&queue, Priority::Critical).await.unwrap();
+        // requests of higher classes may use every worker
+        assert!(acquired(&queue, Priority::Low).await.is_none());
+        drop(critical);
+        // the reservation is still in use
+        let _low = acquired(&queue, Priority::Low).await.unwrap();
+        drop(critical_again);
+        // the reservation is unused again, so it's kept free
+        assert!(acquired(&queue, Priority::Low).await.is_none());
+        assert!(acquired(&queue, Priority::Critical).await.is_some());
+    }
+}
@@ -1,12 +1,27 @@
 use std::sync::Arc;
 
 use actix::{
-    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message, SpawnHandle,
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message, SpawnHandle,
 };
+use serde::{Deserialize, Serialize};
 
-use crate::db::schema::{ScheduleDocument, ScheduleId};
-use crate::db::ScheduleRepository;
-use crate::scheduler::ticker::Ticker;
+use crate::api::dto::{TriggerDto, TriggerStatus};
+use crate::cluster::{Cluster, ScheduleEventResponse};
+use crate::config;
+use crate::db::schema::{
+    ExecutionDocument, RequestBody, RequestHeaders, RunDocument, ScheduleDocument, ScheduleId,
+    ScheduleStatus, UpstreamDocument,
+};
+use crate::db::{trigger_id, ExecutionRepository, ScheduleRepository, TriggerRepository};
+use crate::metrics::metrics;
+use crate::scheduler::assertion::{self, Evaluation};
+use crate::scheduler::dispatcher::{CallbackPayload, Dispatcher};
+use crate::scheduler::supervisor::{FireFollowUps, ScheduleSupervisor};
+use crate::scheduler::template::TemplateContext;
+use crate::scheduler::ticker::{self, Ticker};
+
+/// Header carrying the trigger id, so receivers can drop requests of a run sent twice
+const IDEMPOTENCY_HEADER: &str = "Idempotency-Key";
 
 pub struct ScheduleActor {
     id: ScheduleId,
@@ -15,11 +30,27 @@ pub struct ScheduleActor {
     last_tick: Option<chrono::DateTime<chrono::Utc>>,
     next_tick: Option<chrono::DateTime<chrono::Utc>>,
     repo: Arc<ScheduleRepository>,
+    executions: Arc<ExecutionRepository>,
+    triggers: Arc<TriggerRepository>,
+    dispatcher: Arc<Dispatcher>,
+    supervisor: Addr<ScheduleSupervisor>,
     cancel_hnd: Option<SpawnHandle>,
+    /// Set for actors started only to run a manual trigger, they don't tick
+    manual: Option<TriggerNow>,
+    /// Cluster ticks are claimed through before they fire, on cluster members
+    cluster: Option<Arc<Cluster>>,
 }
 
 impl ScheduleActor {
-    pub fn new(id: ScheduleId, repo: Arc<ScheduleRepository>) -> Self {
+    pub fn new(
+        id: ScheduleId,
+        repo: Arc<ScheduleRepository>,
+        executions: Arc<ExecutionRepository>,
+        triggers: Arc<TriggerRepository>,
+        dispatcher: Arc<Dispatcher>,
+        supervisor: Addr<ScheduleSupervisor>,
+        cluster: Option<Arc<Cluster>>,
+    ) -> Self {
         Self {
             id,
             state: None,
@@ -27,7 +58,522 @@ impl ScheduleActor {
             last_tick: None,
             next_tick: None,
             repo,
+            executions,
+            triggers,
+            dispatcher,
+            supervisor,
             cancel_hnd: None,
+            manual: None,
+            cluster,
+        }
+    }
+
+    /// Makes the actor execute a manual trigger for a schedule without a running actor,
+    /// e.g. completed or paused one, and stop afterwards.
+    pub fn manual(self, trigger: TriggerNow) -> Self {
+        Self {
+            manual: Some(trigger),
+            ..self
+        }
+    }
+
+    /// Hands follow-ups matching the outcome of a finished run to the supervisor.
+    fn fire_follow_ups(&self, upstream: UpstreamDocument) {
+        let follow_ups = self.state.as_ref().and_then(|s| {
+            if upstream.succeeded {
+                s.on_success.clone()
+            } else {
+                s.on_failure.clone()
+            }
+        });
+        if let Some(follow_ups) = follow_ups.filter(|f| !f.is_empty()) {
+            let state = self.state.as_ref();
+            self.supervisor.do_send(FireFollowUps {
+                upstream,
+                tags: state.and_then(|s| s.tags.clone()),
+                dry_run: state.and_then(|s| s.dry_run),
+                follow_ups,
+            });
+        }
+    }
+
+    /// Schedules the next tick after `after`. Returns false if there is nothing left to run.
+    fn schedule_next(
+        &mut self,
+        after: &chrono::DateTime<chrono::Utc>,
+        ctx: &mut Context<Self>,
+    ) -> bool {
+        let next_tick = match self.ticker.as_ref().and_then(|t| t.next_after(after)) {
+            Some(next_tick) => next_tick,
+            None => {
+                self.next_tick = None;
+                return false;
+            }
+        };
+        self.next_tick = Some(next_tick);
+        let timeout = next_tick.signed_duration_since(chrono::Utc::now());
+        if timeout <= chrono::Duration::zero() {
+            log::debug!("Next tick for {} is in the past, running now", self.id);
+            ctx.notify(Tick(next_tick));
+        } else {
+            log::debug!("Next tick for {} in {}", self.id, timeout);
+            let cancel_hnd = ctx.notify_later(Tick(next_tick), timeout.to_std().unwrap());
+            self.cancel_hnd = Some(cancel_hnd);
+        }
+        true
+    }
+
+    /// Claims the run scheduled at `at` through the cluster, and executes it if this node got
+    /// it. Runs are retried until the claim is committed, so they are late rather than lost
+    /// while the cluster has no leader.
+    fn claim(
+        &mut self,
+        cluster: Arc<Cluster>,
+        at: chrono::DateTime<chrono::Utc>,
+        run: u64,
+        ctx: &mut Context<Self>,
+    ) {
+        let id = self.id.clone();
+        let f = async move { cluster.claim_tick(id, run, at).await };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(move |res, act, ctx| match res {
+            Ok(ScheduleEventResponse::TickClaimed(_)) => {
+                metrics().ticks.inc();
+                act.execute(at, run, 1, None, ctx);
+            }
+            Ok(ScheduleEventResponse::TickAlreadyClaimed(_)) => {
+                log::info!("Run {} of {} was claimed before, skipping", run, act.id);
+                if let Some(state) = act.state.as_mut() {
+                    state.runs = run;
+                    state.last_run = Some(at);
+                }
+                act.last_tick = Some(at);
+                if !act.schedule_next(&at, ctx) {
+                    ctx.stop();
+                }
+            }
+            Ok(ScheduleEventResponse::TickFenced(_)) => {
+                log::info!("Schedule {} is run by another member, stopping", act.id);
+                ctx.stop();
+            }
+            Ok(other) => {
+                log::info!(
+                    "Run {} of {} not claimed: {:?}, stopping",
+                    run,
+                    act.id,
+                    other
+                );
+                ctx.stop();
+            }
+            Err(e) => {
+                log::warn!(
+                    "Claiming run {} of {} failed: {}, retrying in 1s",
+                    run,
+                    act.id,
+                    e
+                );
+                let hnd = ctx.notify_later(Tick(at), std::time::Duration::from_secs(1));
+                act.cancel_hnd = Some(hnd);
+            }
+        });
+        ctx.wait(w);
+    }
+
+    /// Picks up the latest run of the ledger if the node stopped before storing its result.
+    /// A run in flight is sent again with the same trigger id, a completed one is stored, and a
+    /// run in flight that finished since, e.g. on another member, is completed in the ledger.
+    /// Returns true if a run was resumed, the next tick is scheduled once it finished.
+    fn recover(&mut self, trigger: Option<TriggerDto>, ctx: &mut Context<Self>) -> bool {
+        let (trigger, state) = match (trigger, self.state.as_mut()) {
+            (Some(trigger), Some(state)) => (trigger, state),
+            _ => return false,
+        };
+        // claimed runs are stored before they are sent, so a run in flight may be the last one
+        if trigger.run < state.runs
+            || (trigger.run == state.runs && trigger.status == TriggerStatus::Completed)
+        {
+            if trigger.status == TriggerStatus::InFlight {
+                // the run was resumed by another member, or its result stored
+                log::info!(
+                    "Run {} of {} at {} was interrupted and finished since",
+                    trigger.run,
+                    self.id,
+                    trigger.at
+                );
+                self.complete(trigger);
+            }
+            return false;
+        }
+        match trigger.status {
+            TriggerStatus::InFlight => {
+                log::warn!(
+                    "Run {} of {} at {} was interrupted, sending it again",
+                    trigger.run,
+                    self.id,
+                    trigger.at
+                );
+                self.execute(trigger.at, trigger.run, 1, None, ctx);
+                true
+            }
+            TriggerStatus::Completed => {
+                log::info!(
+                    "Run {} of {} at {} completed before the restart",
+                    trigger.run,
+                    self.id,
+                    trigger.at
+                );
+                state.runs = trigger.run;
+                state.last_run = Some(trigger.at);
+                let run = RunDocument {
+                    schedule_id: state.id.clone(),
+                    revision: state.revision,
+                    run: trigger.run,
+                    at: trigger.at,
+                    status: state.status,
+                    expires_at: state.expires_at,
+                };
+                self.record_run(run);
+                self.last_tick = Some(trigger.at);
+                if !self.schedule_next(&trigger.at, ctx) {
+                    ctx.stop();
+                }
+                true
+            }
+        }
+    }
+
+    /// Executes an attempt of the run scheduled at `at`, retrying it on failure while
+    /// retry delays last, and records the execution.
+    fn execute(
+        &mut self,
+        at: chrono::DateTime<chrono::Utc>,
+        run: u64,
+        attempt: u32,
+        manual: Option<TriggerNow>,
+        ctx: &mut Context<Self>,
+    ) {
+        let mut schedule = match self.state.clone() {
+            Some(schedule) => schedule,
+            None => return,
+        };
+        if let Some(trigger) = &manual {
+            if let Some(headers) = &trigger.headers {
+                schedule
+                    .request
+                    .headers
+                    .get_or_insert_with(Default::default)
+                    .extend(headers.clone());
+            }
+            if let Some(body) = &trigger.body {
+                schedule.request.body = Some(body.clone());
+            }
+        }
+        // follow-ups are triggered too, but recorded with the execution that caused them
+        let upstream = manual
+            .as_ref()
+            .and_then(|t| t.upstream.clone())
+            .or_else(|| schedule.upstream.clone());
+        let is_manual = manual
+            .as_ref()
+            .map(|t| t.upstream.is_none())
+            .unwrap_or(false);
+        // lag is measured on ticks only, triggered runs are due when they are received
+        let ticked = manual.is_none();
+        let trigger = TriggerDto {
+            id: trigger_id(&schedule.id, run, &at),
+            schedule_id: schedule.id.clone(),
+            run,
+            at,
+            node_id: self.cluster.as_ref().map(|c| c.id()),
+            manual: !ticked,
+            status: TriggerStatus::InFlight,
+        };
+        let headers = schedule
+            .request
+            .headers
+            .get_or_insert_with(Default::default);
+        if !headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case(IDEMPOTENCY_HEADER))
+        {
+            headers.insert(IDEMPOTENCY_HEADER.to_string(), trigger.id.clone());
+        }
+        let dispatcher = self.dispatcher.clone();
+        let executions = self.executions.clone();
+        let triggers = self.triggers.clone();
+        let recorded = trigger.clone();
+        let f = async move {
+            if attempt == 1 {
+                if let Err(e) = triggers.record(recorded).await {
+                    log::error!("error recording trigger of {}: {}", schedule.id, e);
+                }
+            }
+            let started_at = chrono::Utc::now();
+            if attempt == 1 && ticked {
+                let lag = (started_at - at).to_std().unwrap_or_default();
+                metrics().lag.observe(lag.as_secs_f64());
+                metrics().lag_last.set(lag.as_secs_f64());
+            }
+            let vars = TemplateContext {
+                id: schedule.id.clone(),
+                tags: schedule.tags.clone().unwrap_or_default(),
+                scheduled_at: at.to_rfc3339(),
+                fired_at: started_at.to_rfc3339(),
+                run,
+                attempt,
+                prev_run: schedule.last_run.map(|t| t.to_rfc3339()),
+                upstream: upstream.clone(),
+            };
+            let dry_run = schedule.dry_run.unwrap_or(false) || dispatcher.dry_run_forced();
+            let mut request = None;
+            let res = if dry_run {
+                dispatcher.dry_run(&schedule, &vars).map(|r| {
+                    request = Some(r);
+                    None
+                })
+            } else {
+                dispatcher.dispatch(&schedule, &vars).await.map(Some)
+            };
+            let evaluation = match &res {
+                Ok(Some(outcome)) => assertion::evaluate(schedule.success.as_ref(), outcome),
+                Ok(None) => Evaluation {
+                    succeeded: true,
+                    error: None,
+                    matches: None,
+                },
+                Err(e) => Evaluation {
+                    succeeded: false,
+                    error: Some(e.to_string()),
+                    matches: None,
+                },
+            };
+            let (response, body) = match &res {
+                Ok(Some(outcome)) => {
+                    let (response, body) = dispatcher.capture(schedule.capture.as_ref(), outcome);
+                    (Some(response), body)
+                }
+                _ => (None, None),
+            };
+            let outcome = res.ok().flatten();
+            let execution = ExecutionDocument {
+                id: executions.next_id().unwrap_or_default(),
+                schedule_id: schedule.id.clone(),
+                run,
+                attempt,
+                manual: is_manual,
+                scheduled_at: at,
+                started_at,
+                finished_at: chrono::Utc::now(),
+                status_code: outcome.as_ref().map(|o| o.status),
+                latency_ms: outcome.as_ref().map(|o| o.latency.as_millis() as u64),
+                succeeded: evaluation.succeeded,
+                error: evaluation.error.clone(),
+                matches: evaluation.matches,
+                response,
+                dry_run,
+                request,
+                upstream,
+            };
+            let summary = UpstreamDocument {
+                schedule_id: schedule.id.clone(),
+                execution_id: execution.id,
+                run,
+                succeeded: execution.succeeded,
+                status_code: execution.status_code,
+                error: execution.error.clone(),
+                finished_at: execution.finished_at,
+            };
+            let execution_id = execution.id;
+            if let Err(e) = executions.save(execution).await {
+                log::error!("error saving execution of {}: {}", schedule.id, e);
+            }
+            if let Some(body) = body {
+                if let Err(e) = executions
+                    .save_body(schedule.id.clone(), execution_id, body)
+                    .await
+                {
+                    log::error!("error saving response body of {}: {}", schedule.id, e);
+                }
+            }
+            (
+                summary,
+                evaluation.error,
+                dispatcher.retry_delays(&schedule.request),
+            )
+        };
+        let w =
+            actix::fut::wrap_future::<_, Self>(f).map(move |(summary, error, retry), act, ctx| {
+                if summary.succeeded {
+                    log::info!("Run {} of {} at {} succeeded", run, act.id, at);
+                    act.complete(trigger);
+                    act.callback(&summary, at, is_manual);
+                    act.fire_follow_ups(summary);
+                    act.finish_run(at, run, true, manual.is_some(), ctx);
+                    return;
+                }
+                let error = error.unwrap_or_default();
+                match retry.get(attempt as usize - 1) {
+                    Some(delay) => {
+                        metrics().retries.with_label_values(&["schedule"]).inc();
+                        log::warn!(
+                            "Attempt {} of run {} of {} failed: {}, retrying in {}s",
+                            attempt,
+                            run,
+                            act.id,
+                            error,
+                            delay
+                        );
+                        let retry = Retry {
+                            at,
+                            run,
+                            attempt: attempt + 1,
+                            manual,
+                        };
+                        ctx.notify_later(retry, std::time::Duration::from_secs(*delay as u64));
+                    }
+                    None => {
+                        log::error!("Run {} of {} at {} failed: {}", run, act.id, at, error);
+                        act.complete(trigger);
+                        act.callback(&summary, at, is_manual);
+                        act.fire_follow_ups(summary);
+                        act.finish_run(at, run, false, manual.is_some(), ctx);
+                    }
+                }
+            });
+        if is_manual {
+            // manual runs don't hold back ticks
+            ctx.spawn(w);
+        } else {
+            ctx.wait(w);
+        }
+    }
+
+    /// Marks the trigger of a finished run as completed in the ledger.
+    fn complete(&self, trigger: TriggerDto) {
+        let triggers = self.triggers.clone();
+        actix::spawn(async move {
+            if let Err(e) = triggers.complete(trigger.clone()).await {
+                log::error!("error completing trigger {}: {}", trigger.id, e);
+            }
+        });
+    }
+
+    /// Posts the outcome of a finished run to the schedule's callback url, if it has one.
+    /// Dry runs don't call back.
+    fn callback(
+        &self,
+        upstream: &UpstreamDocument,
+        at: chrono::DateTime<chrono::Utc>,
+        manual: bool,
+    ) {
+        let schedule = match &self.state {
+            Some(schedule) => schedule,
+            None => return,
+        };
+        let callback = match &schedule.callback {
+            Some(callback) => callback.clone(),
+            None => return,
+        };
+        if schedule.dry_run.unwrap_or(false) || self.dispatcher.dry_run_forced() {
+            return;
+        }
+        let payload = CallbackPayload {
+            id: upstream.schedule_id.clone(),
+            execution_id: upstream.execution_id,
+            run: upstream.run,
+            manual,
+            status: if upstream.succeeded {
+                "succeeded"
+            } else {
+                "failed"
+            },
+            status_code: upstream.status_code,
+            error: upstream.error.clone(),
+            scheduled_at: at,
+            executed_at: upstream.finished_at,
+        };
+        let dispatcher = self.dispatcher.clone();
+        actix::spawn(async move { dispatcher.callback(&callback, &payload).await });
+    }
+
+    /// Stores the outcome of a run on the schedule, through the cluster on cluster members.
+    /// Nothing is stored if the schedule was deleted or its definition replaced meanwhile.
+    fn record_run(&self, run: RunDocument) {
+        let repo = self.repo.clone();
+        let cluster = self.cluster.clone();
+        actix::spawn(async move {
+            let id = run.schedule_id.clone();
+            let recorded = match cluster {
+                Some(cluster) => cluster
+                    .record_run(run)
+                    .await
+                    .map(|r| matches!(r, ScheduleEventResponse::RunRecorded(_)))
+                    .map_err(|e| e.to_string()),
+                None => repo
+                    .record_run(run, chrono::Utc::now())
+                    .await
+                    .map_err(|e| e.to_string()),
+            };
+            match recorded {
+                Ok(true) => {}
+                Ok(false) => log::info!("Schedule {} changed while running, run not stored", id),
+                Err(e) => log::error!("error saving run of schedule {}: {}", id, e),
+            }
+        });
+    }
+
+    /// Stores the result of a run and schedules the next one. Manual runs
+    /// leave the schedule untouched.
+    fn finish_run(
+        &mut self,
+        at: chrono::DateTime<chrono::Utc>,
+        run: u64,
+        succeeded: bool,
+        manual: bool,
+        ctx: &mut Context<Self>,
+    ) {
+        if manual {
+            if self.manual.is_some() {
+                ctx.stop();
+            }
+            return;
+        }
+        self.last_tick = Some(at);
+        let has_next = self.schedule_next(&at, ctx);
+        if let Some(state) = self.state.as_mut() {
+            state.last_run = Some(at);
+            state.runs = run;
+            if !has_next {
+                state.status = if succeeded {
+                    ScheduleStatus::Completed
+                } else {
+                    ScheduleStatus::Failed
+                };
+                // failed schedules are kept for inspection
+                if succeeded {
+                    let retention = state
+                        .retention
+                        .as_deref()
+                        .and_then(|r| ticker::parse_duration(r).ok())
+                        .unwrap_or_else(config::db::retention);
+                    state.expires_at = Some(chrono::Utc::now() + retention);
+                }
+            }
+            // claims of cluster members already stored the run, only the last one changes more
+            if self.cluster.is_none() || !has_next {
+                let run = RunDocument {
+                    schedule_id: state.id.clone(),
+                    revision: state.revision,
+                    run,
+                    at,
+                    status: state.status,
+                    expires_at: state.expires_at,
+                };
+                self.record_run(run);
+            }
+        }
+        if !has_next {
+            log::debug!("No next tick for {}, stopping", self.id);
+            ctx.stop();
         }
     }
 }
@@ -36,44 +582,81 @@ impl ScheduleActor {
 #[rtype(result = "()")]
 pub struct Tick(chrono::DateTime<chrono::Utc>);
 
+/// Next attempt of a failed run.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Retry {
+    at: chrono::DateTime<chrono::Utc>,
+    run: u64,
+    attempt: u32,
+    manual: Option<TriggerNow>,
+}
+
+/// Runs the schedule immediately, out of band, without affecting its ticks.
+#[derive(Clone, Message, Serialize, Deserialize)]
+#[rtype(result = "()")]
+pub struct TriggerNow {
+    /// Headers added to, or replacing, the schedule's headers for this run
+    pub headers: Option<RequestHeaders>,
+    /// Body replacing the schedule's body for this run
+    pub body: Option<RequestBody>,
+    /// Execution that triggered this run, set for follow-ups
+    pub upstream: Option<UpstreamDocument>,
+}
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct StopIfNoSchedule;
 
+/// Stops the actor, e.g. when its schedule was replaced.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Stop;
+
 impl Actor for ScheduleActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
         log::info!("Starting {}", self.id);
+        metrics().actors.with_label_values(&["schedule"]).inc();
         let repo = self.repo.clone();
+        let triggers = self.triggers.clone();
         let id = self.id.clone();
-        let f = async move { repo.get::<ScheduleDocument>(id).await };
+        let f = async move {
+            let schedule = repo.get::<ScheduleDocument>(id.clone()).await?;
+            let trigger = triggers.latest(id).await?;
+            Ok::<_, std::io::Error>((schedule, trigger))
+        };
         let w = actix::fut::wrap_future::<_, Self>(f).map(|res, act, ctx| match res {
-            Ok(Some(ref schedule)) => {
+            Ok((Some(ref schedule), trigger)) => {
                 log::info!("Found schedule for {}", act.id);
+                if let Some(trigger) = act.manual.clone() {
+                    act.state = Some(schedule.clone());
+                    let run = schedule.runs;
+                    act.execute(chrono::Utc::now(), run, 1, Some(trigger), ctx);
+                    return;
+                }
+                if !schedule.status.is_active() {
+                    log::debug!("Schedule {} is not active, stopping", act.id);
+                    ctx.stop();
+                    return;
+                }
                 let t: Result<Ticker, String> = schedule.to_owned().try_into();
                 if let Ok(ticker) = t {
-                    let after = schedule.last_run.unwrap_or(chrono::Utc::now());
-                    let next_tick = ticker.next_after(&after);
-                    if next_tick.is_none() {
-                        log::debug!("No next tick for {}, stopping", act.id);
-                        ctx.stop();
-                        return;
-                    }
-                    let next_tick = next_tick.unwrap();
+                    // follow-ups are due right after their upstream, even if picked up late
+                    let after = schedule
+                        .last_run
+                        .or_else(|| schedule.upstream.as_ref().map(|u| u.finished_at))
+                        .unwrap_or(chrono::Utc::now());
                     act.state = Some(schedule.clone());
                     act.ticker = Some(ticker);
                     act.last_tick = schedule.last_run;
-                    act.next_tick = Some(next_tick);
-                    let timeout = next_tick.signed_duration_since(chrono::Utc::now());
-                    if timeout <= chrono::Duration::zero() {
-                        log::debug!("Next tick for {} is in the past, running now", act.id);
-                        ctx.notify(Tick(next_tick));
-                    } else {
-                        log::debug!("Next tick for {} in {}", act.id, timeout);
-                        let cancel_hnd =
-                            ctx.notify_later(Tick(next_tick), timeout.to_std().unwrap());
-                        act.cancel_hnd = Some(cancel_hnd);
+                    if act.recover(trigger, ctx) {
+                        return;
+                    }
+                    if !act.schedule_next(&after, ctx) {
+                        log::debug!("No next tick for {}, stopping", act.id);
+                        ctx.stop();
                     }
                 } else {
                     log::error!("Error while parsing schedule for {}. Stopping", act.id);
@@ -81,7 +664,11 @@ impl Actor for ScheduleActor {
                     ctx.stop();
                 }
             }
-            Ok(None) => {
+            Ok((None, _)) if act.manual.is_some() => {
+                log::error!("Schedule {} to trigger not found", act.id);
+                ctx.stop();
+            }
+            Ok((None, _)) => {
                 // Can't find schedule, we should wait for CreateSchedule message
                 log::info!("Creating {}", act.id);
                 ctx.notify_later(StopIfNoSchedule, std::time::Duration::from_secs(30));
@@ -96,6 +683,7 @@ impl Actor for ScheduleActor {
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         log::debug!("stopped for {}", self.id);
+        metrics().actors.with_label_values(&["schedule"]).dec();
     }
 }
 
@@ -103,7 +691,54 @@ impl Handler<Tick> for ScheduleActor {
     type Result = ();
 
     fn handle(&mut self, msg: Tick, ctx: &mut Self::Context) -> Self::Result {
-        todo!()
+        let run = match self.state.as_ref() {
+            Some(schedule) => schedule.runs + 1,
+            None => {
+                log::error!("Tick for {} without schedule, stopping", self.id);
+                ctx.stop();
+                return;
+            }
+        };
+        match self.cluster.clone() {
+            Some(cluster) => self.claim(cluster, msg.0, run, ctx),
+            None => {
+                metrics().ticks.inc();
+                self.execute(msg.0, run, 1, None, ctx);
+            }
+        }
+    }
+}
+
+impl Handler<Retry> for ScheduleActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Retry, ctx: &mut Self::Context) -> Self::Result {
+        self.execute(msg.at, msg.run, msg.attempt, msg.manual, ctx);
+    }
+}
+
+impl Handler<TriggerNow> for ScheduleActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: TriggerNow, ctx: &mut Self::Context) -> Self::Result {
+        let run = match self.state.as_ref() {
+            Some(schedule) => schedule.runs,
+            None => {
+                log::error!("Trigger for {} without schedule", self.id);
+                return;
+            }
+        };
+        log::info!("Manual trigger of {}", self.id);
+        self.execute(chrono::Utc::now(), run, 1, Some(msg), ctx);
+    }
+}
+
+impl Handler<Stop> for ScheduleActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Stop, ctx: &mut Self::Context) -> Self::Result {
+        log::info!("Stopping {}", self.id);
+        ctx.stop();
     }
 }
 
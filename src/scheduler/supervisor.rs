@@ -0,0 +1,609 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message, ResponseFuture};
+
+use crate::cluster::{Cluster, ClusterError, Ownership, ScheduleData, ScheduleEventResponse};
+use crate::config;
+use crate::db::schema::{
+    FollowUpDocument, ScheduleDocument, ScheduleId, ScheduleStatus, Tags, UpstreamDocument,
+    WorkflowId,
+};
+use crate::db::{ExecutionRepository, ScheduleRepository, TriggerRepository, WorkflowRepository};
+use crate::scheduler::dispatcher::Dispatcher;
+use crate::scheduler::schedule_actor::{ScheduleActor, Stop, TriggerNow};
+use crate::scheduler::workflow::{CancelRun, WorkflowActor};
+
+/// Maximum number of expired schedules removed per sweep
+const SWEEP_BATCH: usize = 10_000;
+/// Number of ids read at once when restoring schedules and workflows
+const RESTORE_PAGE: usize = 1_000;
+
+/// Owns one `ScheduleActor` per active schedule and one `WorkflowActor` per active workflow.
+pub struct ScheduleSupervisor {
+    repo: Arc<ScheduleRepository>,
+    executions: Arc<ExecutionRepository>,
+    workflows: Arc<WorkflowRepository>,
+    triggers: Arc<TriggerRepository>,
+    dispatcher: Arc<Dispatcher>,
+    actors: HashMap<ScheduleId, Addr<ScheduleActor>>,
+    workflow_actors: HashMap<WorkflowId, Addr<WorkflowActor>>,
+    /// Whether removal of expired schedules is in progress
+    sweeping: bool,
+    /// Whether actors of stored schedules and workflows were started
+    restored: Restored,
+    /// Shards whose schedules run on this node, on cluster members. Standalone nodes run all
+    ownership: Option<Ownership>,
+    /// Cluster ticks are claimed through, on cluster members
+    cluster: Option<Arc<Cluster>>,
+}
+
+#[derive(Default)]
+struct Restored {
+    schedules: bool,
+    workflows: bool,
+}
+
+impl ScheduleSupervisor {
+    pub fn new(
+        repo: Arc<ScheduleRepository>,
+        executions: Arc<ExecutionRepository>,
+        workflows: Arc<WorkflowRepository>,
+        triggers: Arc<TriggerRepository>,
+        dispatcher: Arc<Dispatcher>,
+    ) -> Self {
+        Self {
+            repo,
+            executions,
+            workflows,
+            triggers,
+            dispatcher,
+            actors: HashMap::new(),
+            workflow_actors: HashMap::new(),
+            sweeping: false,
+            restored: Restored::default(),
+            // cluster members run schedules once shards are assigned to them
+            ownership: config::cluster::node_id().map(|_| Ownership::default()),
+            cluster: None,
+        }
+    }
+
+    /// Starts actors of all active schedules, of owned shards on cluster members.
+    fn restore_schedules(&mut self, ctx: &mut Context<Self>) {
+        log::info!("Restoring schedules");
+        self.restore_schedules_after(None, 0, ctx);
+    }
+
+    /// Starts the actors of the owned active schedules of the page after the given id, then
+    /// of the next page. Actors read their schedule, so only ids are held here.
+    fn restore_schedules_after(
+        &mut self,
+        after: Option<ScheduleId>,
+        restored: usize,
+        ctx: &mut Context<Self>,
+    ) {
+        let repo = self.repo.clone();
+        let f = async move { repo.active_ids(after, RESTORE_PAGE).await };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(move |res, act, ctx| match res {
+            Ok(page) => {
+                let mut restored = restored;
+                for id in page.ids {
+                    if act.owns(&id) {
+                        act.start_actor(id, ctx);
+                        restored += 1;
+                    }
+                }
+                match page.next {
+                    Some(last) => act.restore_schedules_after(Some(last), restored, ctx),
+                    None => {
+                        log::info!("Restored {} schedules", restored);
+                        act.restored.schedules = true;
+                    }
+                }
+            }
+            Err(e) => log::error!("error restoring schedules: {}", e),
+        });
+        ctx.wait(w);
+    }
+
+    /// Removes expired schedules with their executions and forgets stopped actors. On cluster
+    /// members the leader removes them through the cluster, so every member removes the same.
+    fn sweep(&mut self, ctx: &mut Context<Self>) {
+        self.actors.retain(|_, addr| addr.connected());
+        self.workflow_actors.retain(|_, addr| addr.connected());
+        if self.sweeping {
+            return;
+        }
+        let cluster = self.cluster.clone();
+        if self.ownership.is_some() && !cluster.as_ref().is_some_and(|c| c.is_leader()) {
+            return;
+        }
+        self.sweeping = true;
+        let repo = self.repo.clone();
+        let executions = self.executions.clone();
+        let triggers = self.triggers.clone();
+        let f = async move {
+            let now = chrono::Utc::now();
+            if let Some(cluster) = cluster {
+                // listed here so every member removes the same entries
+                let entries = repo.expired(now, SWEEP_BATCH).await?;
+                if entries.is_empty() {
+                    return Ok(0);
+                }
+                return match cluster.write(ScheduleData::Expire(now, entries)).await {
+                    Ok(ScheduleEventResponse::Expired(expired)) => Ok(expired.len()),
+                    Ok(other) => Err(std::io::Error::other(format!("unexpected {:?}", other))),
+                    Err(e) => Err(std::io::Error::other(e)),
+                };
+            }
+            let deleted = repo.delete_expired(now, SWEEP_BATCH).await?;
+            for id in &deleted {
+                executions.delete(id.clone()).await?;
+                triggers.delete(id.clone()).await?;
+            }
+            Ok::<_, std::io::Error>(deleted.len())
+        };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(|res, act, _ctx| {
+            act.sweeping = false;
+            match res {
+                Ok(0) => {}
+                Ok(deleted) => log::info!("Removed {} expired schedules", deleted),
+                Err(e) => log::error!("error removing expired schedules: {}", e),
+            }
+        });
+        ctx.spawn(w);
+    }
+
+    /// Starts actors of all active workflows, of owned shards on cluster members.
+    fn restore_workflows(&mut self, ctx: &mut Context<Self>) {
+        self.restore_workflows_after(None, 0, ctx);
+    }
+
+    /// Starts the actors of the owned active workflows of the page after the given id, then
+    /// of the next page.
+    fn restore_workflows_after(
+        &mut self,
+        after: Option<WorkflowId>,
+        restored: usize,
+        ctx: &mut Context<Self>,
+    ) {
+        let workflows = self.workflows.clone();
+        let f = async move { workflows.active_ids(after, RESTORE_PAGE).await };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(move |res, act, ctx| match res {
+            Ok(page) => {
+                let mut restored = restored;
+                for id in page.ids {
+                    if act.owns(&id) {
+                        act.start_workflow(id);
+                        restored += 1;
+                    }
+                }
+                match page.next {
+                    Some(last) => act.restore_workflows_after(Some(last), restored, ctx),
+                    None => {
+                        log::info!("Restored {} workflows", restored);
+                        act.restored.workflows = true;
+                    }
+                }
+            }
+            Err(e) => log::error!("error restoring workflows: {}", e),
+        });
+        ctx.wait(w);
+    }
+
+    /// Starts the actor of a workflow, which claims its runs through the cluster on members.
+    fn start_workflow(&mut self, id: WorkflowId) {
+        if !self.owns(&id) {
+            log::debug!("Workflow {} is run by another member, not starting", id);
+            return;
+        }
+        if let Some(addr) = self.workflow_actors.get(&id) {
+            if addr.connected() {
+                log::debug!("Workflow {} is already running", id);
+                return;
+            }
+        }
+        let addr = WorkflowActor::new(
+            id.clone(),
+            self.workflows.clone(),
+            self.dispatcher.clone(),
+            self.cluster.clone(),
+        )
+        .start();
+        self.workflow_actors.insert(id, addr);
+    }
+
+    fn owns(&self, id: &str) -> bool {
+        self.ownership.as_ref().map(|o| o.owns(id)).unwrap_or(true)
+    }
+
+    /// Starts the actor of a schedule, which claims its ticks through the cluster on members.
+    fn start_actor(&mut self, id: ScheduleId, ctx: &mut Context<Self>) {
+        if !self.owns(&id) {
+            log::debug!("Schedule {} is run by another member, not starting", id);
+            return;
+        }
+        if let Some(addr) = self.actors.get(&id) {
+            if addr.connected() {
+                log::debug!("Schedule {} is already running", id);
+                return;
+            }
+        }
+        let addr = ScheduleActor::new(
+            id.clone(),
+            self.repo.clone(),
+            self.executions.clone(),
+            self.triggers.clone(),
+            self.dispatcher.clone(),
+            ctx.address(),
+            self.cluster.clone(),
+        )
+        .start();
+        self.actors.insert(id, addr);
+    }
+
+    /// Runs a schedule now, on the member owning its shard on cluster members. The returned
+    /// future completes once the owner accepted the trigger.
+    fn trigger(
+        &mut self,
+        id: ScheduleId,
+        trigger: TriggerNow,
+        ctx: &mut Context<Self>,
+    ) -> ResponseFuture<Result<(), ClusterError>> {
+        if !self.owns(&id) {
+            let cluster = self.cluster.clone();
+            return Box::pin(async move {
+                match cluster {
+                    Some(cluster) => cluster.trigger(id, trigger).await,
+                    None => Err(ClusterError::NoLeader),
+                }
+            });
+        }
+        self.run_trigger(id, trigger, ctx);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Runs a schedule now on this node.
+    fn run_trigger(&mut self, id: ScheduleId, trigger: TriggerNow, ctx: &mut Context<Self>) {
+        let trigger = match self.actors.get(&id) {
+            Some(addr) => match addr.try_send(trigger) {
+                Ok(()) => return,
+                Err(e) => e.into_inner(),
+            },
+            None => trigger,
+        };
+        // inactive schedules have no actor, run the trigger in a short lived one
+        ScheduleActor::new(
+            id,
+            self.repo.clone(),
+            self.executions.clone(),
+            self.triggers.clone(),
+            self.dispatcher.clone(),
+            ctx.address(),
+            self.cluster.clone(),
+        )
+        .manual(trigger)
+        .start();
+    }
+}
+
+/// Starts the actor for a schedule, if it is not running already.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartSchedule(pub ScheduleId);
+
+/// Stops the actor of a schedule, if it is running.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StopSchedule(pub ScheduleId);
+
+/// Restarts the actor of a schedule to pick up its new definition. The new actor starts once
+/// the old one stopped, after the run it is executing, so the two never run together.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RestartSchedule(pub ScheduleId);
+
+/// Runs a schedule immediately, through its actor if it is running. On cluster members, the
+/// trigger is handed to the member owning the shard of the schedule.
+#[derive(Message)]
+#[rtype(result = "Result<(), ClusterError>")]
+pub struct TriggerSchedule(pub ScheduleId, pub TriggerNow);
+
+/// Runs a schedule immediately on this node, handed over by another member. Returns false,
+/// rather than handing it over again, if this node doesn't own the shard of the schedule.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct RunForwardedTrigger(pub ScheduleId, pub TriggerNow);
+
+/// Starts the actor for a workflow, if it is not running already.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartWorkflow(pub WorkflowId);
+
+/// Cancels a run of a workflow. Returns false if the run is not in progress.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CancelWorkflowRun {
+    pub workflow_id: WorkflowId,
+    pub run_id: u64,
+}
+
+/// Makes schedule actors claim their ticks through the cluster this node is a member of.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinCluster(pub Arc<Cluster>);
+
+/// Runs the schedules of the given shards only, when shards were assigned to this node.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetOwnership(pub Ownership);
+
+/// Reports whether stored schedules were restored and how many actors are running.
+#[derive(Message)]
+#[rtype(result = "SupervisorStatus")]
+pub struct GetStatus;
+
+pub struct SupervisorStatus {
+    /// Actors of stored schedules and workflows were started
+    pub restored: bool,
+    pub schedule_actors: usize,
+    pub workflow_actors: usize,
+}
+
+/// Runs follow-ups of a finished run. Referenced schedules are triggered, inline
+/// requests are stored as one time schedules due immediately.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct FireFollowUps {
+    pub upstream: UpstreamDocument,
+    /// Tags of the upstream schedule, inherited by inline follow-ups
+    pub tags: Option<Tags>,
+    /// Dry run setting of the upstream schedule, inherited by inline follow-ups
+    pub dry_run: Option<bool>,
+    pub follow_ups: Vec<FollowUpDocument>,
+}
+
+impl Actor for ScheduleSupervisor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.ownership.is_none() {
+            self.restore_schedules(ctx);
+            self.restore_workflows(ctx);
+        }
+        ctx.run_interval(config::db::retention_sweep_interval(), |act, ctx| {
+            act.sweep(ctx)
+        });
+    }
+}
+
+impl Handler<StartSchedule> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: StartSchedule, ctx: &mut Self::Context) -> Self::Result {
+        // stopped actors are forgotten by `sweep`, not on every start
+        self.start_actor(msg.0, ctx);
+    }
+}
+
+impl Handler<StopSchedule> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: StopSchedule, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(addr) = self.actors.remove(&msg.0) {
+            addr.do_send(Stop);
+        }
+    }
+}
+
+impl Handler<RestartSchedule> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestartSchedule, ctx: &mut Self::Context) -> Self::Result {
+        let id = msg.0;
+        let old = match self.actors.get(&id) {
+            Some(addr) => addr.clone(),
+            None => return self.start_actor(id, ctx),
+        };
+        let stopping = old.clone();
+        // `Stop` is handled once the actor is done with the run it is executing
+        let f = async move {
+            let _ = stopping.send(Stop).await;
+        };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(move |_, act, ctx| {
+            // a stop or another restart took over meanwhile
+            if act.actors.get(&id) != Some(&old) {
+                return;
+            }
+            act.actors.remove(&id);
+            act.start_actor(id, ctx);
+        });
+        ctx.spawn(w);
+    }
+}
+
+impl Handler<TriggerSchedule> for ScheduleSupervisor {
+    type Result = ResponseFuture<Result<(), ClusterError>>;
+
+    fn handle(&mut self, msg: TriggerSchedule, ctx: &mut Self::Context) -> Self::Result {
+        self.trigger(msg.0, msg.1, ctx)
+    }
+}
+
+impl Handler<RunForwardedTrigger> for ScheduleSupervisor {
+    type Result = bool;
+
+    fn handle(&mut self, msg: RunForwardedTrigger, ctx: &mut Self::Context) -> Self::Result {
+        if !self.owns(&msg.0) {
+            return false;
+        }
+        self.run_trigger(msg.0, msg.1, ctx);
+        true
+    }
+}
+
+impl Handler<FireFollowUps> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: FireFollowUps, ctx: &mut Self::Context) -> Self::Result {
+        let upstream = msg.upstream;
+        let mut inline = Vec::new();
+        for (index, follow_up) in msg.follow_ups.into_iter().enumerate() {
+            match follow_up {
+                FollowUpDocument::Schedule(id) => {
+                    log::info!("Triggering {} after {}", id, upstream.schedule_id);
+                    let trigger = TriggerNow {
+                        headers: None,
+                        body: None,
+                        upstream: Some(upstream.clone()),
+                    };
+                    let triggered = self.trigger(id.clone(), trigger, ctx);
+                    let w = actix::fut::wrap_future::<_, Self>(triggered).map(move |res, _, _| {
+                        if let Err(e) = res {
+                            log::error!("error triggering follow-up {}: {}", id, e);
+                        }
+                    });
+                    ctx.spawn(w);
+                }
+                FollowUpDocument::Request { request } => {
+                    let now = chrono::Utc::now();
+                    inline.push(ScheduleDocument {
+                        id: format!(
+                            "{}-{}-{}",
+                            upstream.schedule_id, upstream.execution_id, index
+                        ),
+                        tags: msg.tags.clone(),
+                        request: *request,
+                        schedule: None,
+                        schedule_at: Some(now.to_rfc3339()),
+                        callback: None,
+                        success: None,
+                        capture: None,
+                        dry_run: msg.dry_run,
+                        on_success: None,
+                        on_failure: None,
+                        upstream: Some(upstream.clone()),
+                        retention: None,
+                        expires_at: None,
+                        dedup_key: None,
+                        dedup_window: None,
+                        priority: None,
+                        created_at: now,
+                        updated_at: now,
+                        last_run: None,
+                        runs: 0,
+                        status: ScheduleStatus::Scheduled,
+                        revision: 0,
+                    });
+                }
+            }
+        }
+        if inline.is_empty() {
+            return;
+        }
+        let repo = self.repo.clone();
+        let cluster = self.cluster.clone();
+        let f = async move {
+            let mut created = Vec::new();
+            for schedule in inline {
+                let id = schedule.id.clone();
+                let res = match &cluster {
+                    // members start it once the write is applied, like any created schedule
+                    Some(cluster) => cluster
+                        .write(ScheduleData::CreateFollowUp(Box::new(schedule)))
+                        .await
+                        .map(|_| false)
+                        .map_err(|e| e.to_string()),
+                    None => repo
+                        .create_follow_up(schedule)
+                        .await
+                        .map_err(|e| e.to_string()),
+                };
+                match res {
+                    Ok(true) => created.push(id),
+                    Ok(false) => {}
+                    Err(e) => log::error!("error saving follow-up {}: {}", id, e),
+                }
+            }
+            created
+        };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(|created, act, ctx| {
+            for id in created {
+                act.start_actor(id, ctx);
+            }
+        });
+        ctx.spawn(w);
+    }
+}
+
+impl Handler<StartWorkflow> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: StartWorkflow, _ctx: &mut Self::Context) -> Self::Result {
+        self.start_workflow(msg.0);
+    }
+}
+
+impl Handler<CancelWorkflowRun> for ScheduleSupervisor {
+    type Result = ResponseFuture<bool>;
+
+    fn handle(&mut self, msg: CancelWorkflowRun, _ctx: &mut Self::Context) -> Self::Result {
+        let addr = self.workflow_actors.get(&msg.workflow_id).cloned();
+        Box::pin(async move {
+            match addr {
+                Some(addr) => addr.send(CancelRun(msg.run_id)).await.unwrap_or(false),
+                None => false,
+            }
+        })
+    }
+}
+
+impl Handler<GetStatus> for ScheduleSupervisor {
+    type Result = actix::MessageResult<GetStatus>;
+
+    fn handle(&mut self, _msg: GetStatus, _ctx: &mut Self::Context) -> Self::Result {
+        actix::MessageResult(SupervisorStatus {
+            restored: self.restored.schedules && self.restored.workflows,
+            schedule_actors: self.actors.values().filter(|a| a.connected()).count(),
+            workflow_actors: self
+                .workflow_actors
+                .values()
+                .filter(|a| a.connected())
+                .count(),
+        })
+    }
+}
+
+impl Handler<JoinCluster> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: JoinCluster, _ctx: &mut Self::Context) -> Self::Result {
+        self.cluster = Some(msg.0);
+    }
+}
+
+impl Handler<SetOwnership> for ScheduleSupervisor {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetOwnership, ctx: &mut Self::Context) -> Self::Result {
+        let ownership = msg.0;
+        self.actors.retain(|id, addr| {
+            let owned = ownership.owns(id);
+            if !owned {
+                addr.do_send(Stop);
+            }
+            owned
+        });
+        self.workflow_actors.retain(|id, addr| {
+            let owned = ownership.owns(id);
+            if !owned {
+                addr.do_send(Stop);
+            }
+            owned
+        });
+        self.ownership = Some(ownership);
+        // also picks up schedules and workflows restored from a snapshot
+        self.restore_schedules(ctx);
+        self.restore_workflows(ctx);
+    }
+}
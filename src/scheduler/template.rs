@@ -0,0 +1,222 @@
+use crate::db::schema::{RequestBody, RequestDocument, RequestHeaders, Tags, UpstreamDocument};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+
+/// Variables available to request templates.
+#[derive(Clone, Debug, Serialize)]
+pub struct TemplateContext {
+    /// Schedule id
+    pub id: String,
+    /// Schedule tags
+    pub tags: Tags,
+    /// Instant the run was scheduled for, RFC 3339
+    pub scheduled_at: String,
+    /// Instant the request is actually sent, RFC 3339
+    pub fired_at: String,
+    /// Run number, starting from 1
+    pub run: u64,
+    /// Attempt number within the run, starting from 1
+    pub attempt: u32,
+    /// Instant of the previous run, RFC 3339, if any
+    pub prev_run: Option<String>,
+    /// Execution that triggered this run, for follow-ups
+    pub upstream: Option<UpstreamDocument>,
+}
+
+fn environment() -> Environment<'static> {
+    // the engine has no access to the filesystem or the environment,
+    // strict mode turns typos in variable names into render errors
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env
+}
+
+/// Checks template syntax of a request without rendering it.
+pub fn check(
+    url: &str,
+    headers: Option<&RequestHeaders>,
+    body: Option<&RequestBody>,
+) -> Result<(), String> {
+    let env = environment();
+    let compile = |s: &str| {
+        env.template_from_str(s)
+            .map(|_| ())
+            .map_err(|e| format!("invalid template: {}", e))
+    };
+    compile(url)?;
+    for value in headers.into_iter().flat_map(|h| h.values()) {
+        compile(value)?;
+    }
+    match body {
+        Some(RequestBody::Text(text)) => compile(text)?,
+        Some(RequestBody::Json { json }) => walk_json(json, &mut |s| compile(s))?,
+        Some(RequestBody::Form { form }) => {
+            for value in form.values() {
+                compile(value)?;
+            }
+        }
+        Some(RequestBody::Base64 { .. }) | None => {}
+    }
+    Ok(())
+}
+
+fn walk_json(
+    value: &serde_json::Value,
+    f: &mut impl FnMut(&str) -> Result<(), String>,
+) -> Result<(), String> {
+    match value {
+        serde_json::Value::String(s) => f(s),
+        serde_json::Value::Array(values) => values.iter().try_for_each(|v| walk_json(v, f)),
+        serde_json::Value::Object(map) => map.values().try_for_each(|v| walk_json(v, f)),
+        _ => Ok(()),
+    }
+}
+
+fn render_json(
+    env: &Environment,
+    value: &serde_json::Value,
+    ctx: &TemplateContext,
+) -> Result<serde_json::Value, String> {
+    Ok(match value {
+        serde_json::Value::String(s) => serde_json::Value::String(render_str(env, s, ctx)?),
+        serde_json::Value::Array(values) => serde_json::Value::Array(
+            values
+                .iter()
+                .map(|v| render_json(env, v, ctx))
+                .collect::<Result<_, _>>()?,
+        ),
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), render_json(env, v, ctx)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+fn render_str(env: &Environment, template: &str, ctx: &TemplateContext) -> Result<String, String> {
+    env.render_str(template, ctx)
+        .map_err(|e| format!("template render failed: {}", e))
+}
+
+/// Renders url, header values and body of the request. Rendered JSON bodies keep their
+/// structure, only string values are rendered, base64 bodies are left as is.
+pub fn render(request: &RequestDocument, ctx: &TemplateContext) -> Result<RequestDocument, String> {
+    let env = environment();
+    let mut rendered = request.clone();
+    rendered.url = render_str(&env, &request.url, ctx)?;
+    if let Some(headers) = rendered.headers.as_mut() {
+        for value in headers.values_mut() {
+            *value = render_str(&env, value, ctx)?;
+        }
+    }
+    rendered.body = match &request.body {
+        Some(RequestBody::Text(text)) => Some(RequestBody::Text(render_str(&env, text, ctx)?)),
+        Some(RequestBody::Json { json }) => Some(RequestBody::Json {
+            json: render_json(&env, json, ctx)?,
+        }),
+        Some(RequestBody::Form { form }) => Some(RequestBody::Form {
+            form: form
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), render_str(&env, v, ctx)?)))
+                .collect::<Result<_, String>>()?,
+        }),
+        other => other.clone(),
+    };
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        TemplateContext {
+            id: "report".to_string(),
+            tags: vec!["daily".to_string()],
+            scheduled_at: "2024-01-01T00:00:00+00:00".to_string(),
+            fired_at: "2024-01-01T00:00:01+00:00".to_string(),
+            run: 3,
+            attempt: 2,
+            prev_run: None,
+            upstream: None,
+        }
+    }
+
+    fn request(body: serde_json::Value) -> RequestDocument {
+        serde_json::from_value(serde_json::json!({
+            "method": "POST",
+            "url": "http://localhost/{{ id }}/{{ run }}",
+            "headers": {"x-attempt": "{{ attempt }}"},
+            "body": body,
+            "template": true,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_url_headers_and_text() {
+        let rendered = render(&request(serde_json::json!("{{ tags[0] }}")), &context()).unwrap();
+        assert_eq!(rendered.url, "http://localhost/report/3");
+        assert_eq!(rendered.headers.unwrap()["x-attempt"], "2");
+        assert!(matches!(rendered.body, Some(RequestBody::Text(text)) if text == "daily"));
+    }
+
+    #[test]
+    fn renders_strings_of_json_bodies() {
+        let body = serde_json::json!({"json": {
+            "at": "{{ scheduled_at }}",
+            "runs": [1, "{{ run }}"],
+            "count": 7,
+        }});
+        let rendered = render(&request(body), &context()).unwrap();
+        let json = match rendered.body {
+            Some(RequestBody::Json { json }) => json,
+            other => panic!("unexpected body {:?}", other),
+        };
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "at": "2024-01-01T00:00:00+00:00",
+                "runs": [1, "3"],
+                "count": 7,
+            })
+        );
+    }
+
+    #[test]
+    fn renders_upstream_of_follow_ups() {
+        let mut ctx = context();
+        ctx.upstream = Some(UpstreamDocument {
+            schedule_id: "extract".to_string(),
+            execution_id: 9,
+            run: 1,
+            succeeded: false,
+            status_code: Some(500),
+            error: Some("boom".to_string()),
+            finished_at: chrono::Utc::now(),
+        });
+        let body = serde_json::json!({"form": {"failed": "{{ upstream.schedule_id }}: {{ upstream.error }}"}});
+        let rendered = render(&request(body), &ctx).unwrap();
+        match rendered.body {
+            Some(RequestBody::Form { form }) => assert_eq!(form["failed"], "extract: boom"),
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_undefined_variables_and_invalid_syntax() {
+        let error = render(&request(serde_json::json!("{{ missing }}")), &context()).unwrap_err();
+        assert!(error.starts_with("template render failed"), "{}", error);
+        // prev_run is unset on the first run
+        assert!(render(&request(serde_json::json!("{{ prev_run.x }}")), &context()).is_err());
+
+        assert!(check("http://localhost/{{ id }}", None, None).is_ok());
+        let error = check("http://localhost/{{ id", None, None).unwrap_err();
+        assert!(error.starts_with("invalid template"), "{}", error);
+        let body = RequestBody::Json {
+            json: serde_json::json!({"a": ["{% if %}"]}),
+        };
+        assert!(check("http://localhost/", None, Some(&body)).is_err());
+    }
+}
@@ -4,11 +4,12 @@ use std::str::FromStr;
 /// Holds the information about when the next job should be run.
 pub enum Ticker {
     ScheduleAt(chrono::DateTime<chrono::Utc>),
-    Cron(cron::Schedule),
+    Cron(Box<cron::Schedule>),
 }
 
 impl Ticker {
     /// Returns the next time the job should be run. Or None if the job should not be run anymore.
+    #[allow(dead_code)]
     pub fn next(&self) -> Option<chrono::DateTime<chrono::Utc>> {
         match self {
             Ticker::ScheduleAt(dt) => Some(*dt),
@@ -35,21 +36,62 @@ impl Ticker {
     }
 }
 
-impl TryFrom<ScheduleDocument> for Ticker {
-    /// Creates a Ticker from a ScheduleDocument
-    type Error = String;
-
-    fn try_from(value: ScheduleDocument) -> Result<Self, Self::Error> {
-        if let Some(dt) = value.schedule_at {
+impl Ticker {
+    /// Creates a Ticker from `schedule_at`, or the cron `schedule` if it is not set.
+    pub fn parse(schedule: Option<&str>, schedule_at: Option<&str>) -> Result<Self, String> {
+        if let Some(dt) = schedule_at {
             Ok(Self::ScheduleAt(dt.parse().map_err(|_| {
                 format!("schedule_at format is not ISO8601: {}", dt)
             })?))
-        } else if let Some(c) = value.schedule {
-            Ok(Self::Cron(cron::Schedule::from_str(c.as_str()).map_err(
+        } else if let Some(c) = schedule {
+            Ok(Self::Cron(Box::new(cron::Schedule::from_str(c).map_err(
                 |_| format!("schedule format is not cron: {}", c),
-            )?))
+            )?)))
         } else {
-            Err(format!("ScheduleDocument has no schedule or schedule_at"))
+            Err("no schedule or schedule_at".to_string())
         }
     }
 }
+
+/// Parses a duration like `15m`, `2h30m` or `1d12h`. Units are `d`, `h`, `m`, `s` and `ms`.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let invalid = || format!("invalid duration {}", s);
+    let mut total = chrono::Duration::zero();
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let value: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let millis_per_unit = match &rest[..unit] {
+            "d" => 86_400_000,
+            "h" => 3_600_000,
+            "m" => 60_000,
+            "s" => 1_000,
+            "ms" => 1,
+            _ => return Err(invalid()),
+        };
+        total = value
+            .checked_mul(millis_per_unit)
+            .and_then(|millis| total.checked_add(&chrono::Duration::milliseconds(millis)))
+            .ok_or_else(invalid)?;
+        rest = &rest[unit..];
+    }
+    Ok(total)
+}
+
+impl TryFrom<ScheduleDocument> for Ticker {
+    /// Creates a Ticker from a ScheduleDocument
+    type Error = String;
+
+    fn try_from(value: ScheduleDocument) -> Result<Self, Self::Error> {
+        Self::parse(value.schedule.as_deref(), value.schedule_at.as_deref())
+    }
+}
@@ -0,0 +1,923 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, Context, Handler, Message, SpawnHandle,
+};
+
+use crate::api::dto::CreateWorkflowDto;
+use crate::cluster::{Cluster, ScheduleEventResponse, WorkflowRunFinished};
+use crate::db::schema::{
+    ScheduleDocument, ScheduleStatus, StepRunDocument, StepStatus, WorkflowDocument, WorkflowId,
+    WorkflowRunDocument, WorkflowRunStatus, WorkflowStepDocument,
+};
+use crate::db::WorkflowRepository;
+use crate::metrics::metrics;
+use crate::scheduler::assertion::{self, Evaluation};
+use crate::scheduler::dispatcher::Dispatcher;
+use crate::scheduler::schedule_actor::Stop;
+use crate::scheduler::template::TemplateContext;
+use crate::scheduler::ticker::Ticker;
+
+/// Validates a workflow before it is stored: its schedule, step requests and success
+/// criteria, and that `depends_on` forms a directed acyclic graph of existing steps.
+pub fn check(workflow: &CreateWorkflowDto, dispatcher: &Dispatcher) -> Result<(), String> {
+    Ticker::parse(
+        workflow.schedule.as_deref(),
+        workflow.schedule_at.as_deref(),
+    )?;
+    if workflow.steps.is_empty() {
+        return Err("workflow has no steps".to_string());
+    }
+    let mut names = HashSet::new();
+    for step in &workflow.steps {
+        if step.name.is_empty() {
+            return Err("step name is empty".to_string());
+        }
+        if !names.insert(step.name.as_str()) {
+            return Err(format!("step {} is defined more than once", step.name));
+        }
+        dispatcher
+            .check_request(&step.request)
+            .map_err(|e| format!("step {}: {}", step.name, e))?;
+        if let Some(success) = &step.success {
+            assertion::check(success).map_err(|e| format!("step {}: {}", step.name, e))?;
+        }
+    }
+    for step in &workflow.steps {
+        for parent in step.depends_on.iter().flatten() {
+            if !names.contains(parent.as_str()) {
+                return Err(format!(
+                    "step {} depends on unknown step {}",
+                    step.name, parent
+                ));
+            }
+        }
+    }
+
+    // Kahn's algorithm, steps left unresolved are part of a cycle
+    let mut resolved: HashSet<&str> = HashSet::new();
+    loop {
+        let ready: Vec<&str> = workflow
+            .steps
+            .iter()
+            .filter(|s| !resolved.contains(s.name.as_str()))
+            .filter(|s| {
+                s.depends_on
+                    .iter()
+                    .flatten()
+                    .all(|p| resolved.contains(p.as_str()))
+            })
+            .map(|s| s.name.as_str())
+            .collect();
+        if ready.is_empty() {
+            break;
+        }
+        resolved.extend(ready);
+    }
+    if resolved.len() < workflow.steps.len() {
+        let mut cycle: Vec<&str> = names.difference(&resolved).copied().collect();
+        cycle.sort_unstable();
+        return Err(format!("steps {} form a cycle", cycle.join(", ")));
+    }
+    Ok(())
+}
+
+/// Marks a running workflow run and its unfinished steps cancelled.
+pub fn cancel_run(run: &mut WorkflowRunDocument) {
+    let now = chrono::Utc::now();
+    for step in run.steps.iter_mut() {
+        if matches!(step.status, StepStatus::Pending | StepStatus::Running) {
+            step.status = StepStatus::Cancelled;
+            step.finished_at = Some(now);
+        }
+    }
+    run.status = WorkflowRunStatus::Cancelled;
+    run.finished_at = Some(now);
+}
+
+/// Run number `workflow.runs` of the workflow, scheduled at `at`, with all its steps pending.
+fn new_run(
+    workflow: &WorkflowDocument,
+    id: u64,
+    at: chrono::DateTime<chrono::Utc>,
+) -> WorkflowRunDocument {
+    WorkflowRunDocument {
+        id,
+        workflow_id: workflow.id.clone(),
+        run: workflow.runs,
+        scheduled_at: at,
+        started_at: chrono::Utc::now(),
+        finished_at: None,
+        status: WorkflowRunStatus::Running,
+        steps: workflow
+            .steps
+            .iter()
+            .map(|step| StepRunDocument {
+                name: step.name.clone(),
+                status: StepStatus::Pending,
+                attempts: 0,
+                started_at: None,
+                finished_at: None,
+                status_code: None,
+                latency_ms: None,
+                error: None,
+                matches: None,
+            })
+            .collect(),
+    }
+}
+
+/// Skips pending steps whose dependencies didn't succeed, marks running the pending steps
+/// whose dependencies all succeeded and returns their indexes.
+fn ready_steps(workflow: &WorkflowDocument, run: &mut WorkflowRunDocument) -> Vec<usize> {
+    let mut ready = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, step) in workflow.steps.iter().enumerate() {
+            if run.steps[index].status != StepStatus::Pending {
+                continue;
+            }
+            let parents: Vec<StepStatus> = step
+                .depends_on
+                .iter()
+                .flatten()
+                .filter_map(|p| run.steps.iter().find(|s| &s.name == p))
+                .map(|s| s.status)
+                .collect();
+            if parents.iter().any(|s| {
+                matches!(
+                    s,
+                    StepStatus::Failed | StepStatus::Skipped | StepStatus::Cancelled
+                )
+            }) {
+                run.steps[index].status = StepStatus::Skipped;
+                run.steps[index].finished_at = Some(chrono::Utc::now());
+                changed = true;
+            } else if parents.iter().all(|s| *s == StepStatus::Succeeded) {
+                run.steps[index].status = StepStatus::Running;
+                ready.push(index);
+            }
+        }
+    }
+    ready
+}
+
+/// Whether no step of the run is left to run.
+fn is_done(run: &WorkflowRunDocument) -> bool {
+    run.steps
+        .iter()
+        .all(|s| !matches!(s.status, StepStatus::Pending | StepStatus::Running))
+}
+
+/// Status of a finished run, succeeded if all its steps did.
+fn outcome(run: &WorkflowRunDocument) -> WorkflowRunStatus {
+    if run.steps.iter().all(|s| s.status == StepStatus::Succeeded) {
+        WorkflowRunStatus::Succeeded
+    } else {
+        WorkflowRunStatus::Failed
+    }
+}
+
+/// Records the result of an attempt of a step. Returns the delay in seconds before the next
+/// attempt if the attempt failed and retry delays last, otherwise finishes the step.
+fn record_attempt(step: &mut StepRunDocument, attempt: u32, result: StepAttempt) -> Option<u32> {
+    step.status_code = result.status_code;
+    step.latency_ms = result.latency_ms;
+    step.error = result.evaluation.error;
+    step.matches = result.evaluation.matches;
+    if !result.evaluation.succeeded {
+        if let Some(delay) = result.retry.get(attempt as usize - 1) {
+            return Some(*delay);
+        }
+    }
+    step.status = if result.evaluation.succeeded {
+        StepStatus::Succeeded
+    } else {
+        StepStatus::Failed
+    };
+    step.finished_at = Some(chrono::Utc::now());
+    None
+}
+
+/// Fails a step that didn't finish within its timeout.
+fn time_out(step: &mut StepRunDocument, timeout_ms: u64) {
+    step.status = StepStatus::Failed;
+    step.error = Some(format!("step timed out after {}ms", timeout_ms));
+    step.finished_at = Some(chrono::Utc::now());
+}
+
+/// Schedule sent through the dispatcher for a step, so that steps share templates,
+/// rate limits and success criteria with schedules.
+fn step_schedule(workflow: &WorkflowDocument, step: &WorkflowStepDocument) -> ScheduleDocument {
+    ScheduleDocument {
+        id: format!("{}/{}", workflow.id, step.name),
+        tags: workflow.tags.clone(),
+        request: step.request.clone(),
+        schedule: None,
+        schedule_at: None,
+        callback: None,
+        success: step.success.clone(),
+        capture: None,
+        dry_run: None,
+        on_success: None,
+        on_failure: None,
+        upstream: None,
+        retention: None,
+        expires_at: None,
+        dedup_key: None,
+        dedup_window: None,
+        priority: None,
+        created_at: workflow.created_at,
+        updated_at: workflow.updated_at,
+        last_run: workflow.last_run,
+        runs: workflow.runs,
+        status: ScheduleStatus::Scheduled,
+        revision: 0,
+    }
+}
+
+/// Result of one attempt of a step.
+struct StepAttempt {
+    evaluation: Evaluation,
+    status_code: Option<u16>,
+    latency_ms: Option<u64>,
+    retry: Vec<u32>,
+}
+
+/// Runs a workflow on its schedule. Steps start as soon as all their dependencies
+/// succeeded, steps depending on a failed step are skipped. Runs of a workflow
+/// don't overlap, the next tick is scheduled once a run finishes.
+pub struct WorkflowActor {
+    id: WorkflowId,
+    state: Option<WorkflowDocument>,
+    ticker: Option<Ticker>,
+    repo: Arc<WorkflowRepository>,
+    dispatcher: Arc<Dispatcher>,
+    run: Option<WorkflowRunDocument>,
+    /// In flight requests and pending retries of steps of the current run, by step index
+    attempts: HashMap<usize, SpawnHandle>,
+    /// Deadlines of steps of the current run, by step index
+    deadlines: HashMap<usize, SpawnHandle>,
+    /// Runs are claimed through before they start, on cluster members
+    cluster: Option<Arc<Cluster>>,
+}
+
+impl WorkflowActor {
+    pub fn new(
+        id: WorkflowId,
+        repo: Arc<WorkflowRepository>,
+        dispatcher: Arc<Dispatcher>,
+        cluster: Option<Arc<Cluster>>,
+    ) -> Self {
+        Self {
+            id,
+            state: None,
+            ticker: None,
+            repo,
+            dispatcher,
+            run: None,
+            attempts: HashMap::new(),
+            deadlines: HashMap::new(),
+            cluster,
+        }
+    }
+
+    /// Claims the run scheduled at `at` through the cluster, and starts it if this node got it.
+    /// Like schedule ticks, runs are retried until the claim is committed.
+    fn claim(
+        &mut self,
+        cluster: Arc<Cluster>,
+        at: chrono::DateTime<chrono::Utc>,
+        ctx: &mut Context<Self>,
+    ) {
+        let run = match self.state.as_ref() {
+            Some(workflow) => workflow.runs + 1,
+            None => return,
+        };
+        let id = self.id.clone();
+        let f = async move { cluster.claim_workflow_run(id, run, at).await };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(move |res, act, ctx| match res {
+            Ok(ScheduleEventResponse::TickClaimed(_)) => act.start_run(at, ctx),
+            Ok(ScheduleEventResponse::TickAlreadyClaimed(_)) => {
+                log::info!(
+                    "Run {} of workflow {} was claimed before, skipping",
+                    run,
+                    act.id
+                );
+                if let Some(workflow) = act.state.as_mut() {
+                    workflow.runs = run;
+                    workflow.last_run = Some(at);
+                }
+                if !act.schedule_next(&at, ctx) {
+                    ctx.stop();
+                }
+            }
+            Ok(other) => {
+                log::info!(
+                    "Run {} of workflow {} not claimed: {:?}, stopping",
+                    run,
+                    act.id,
+                    other
+                );
+                ctx.stop();
+            }
+            Err(e) => {
+                log::warn!(
+                    "Claiming run {} of workflow {} failed: {}, retrying in 1s",
+                    run,
+                    act.id,
+                    e
+                );
+                ctx.notify_later(WorkflowTick(at), std::time::Duration::from_secs(1));
+            }
+        });
+        ctx.wait(w);
+    }
+
+    /// Schedules the next tick after `after`. Returns false if there is nothing left to run.
+    fn schedule_next(
+        &mut self,
+        after: &chrono::DateTime<chrono::Utc>,
+        ctx: &mut Context<Self>,
+    ) -> bool {
+        let next_tick = match self.ticker.as_ref().and_then(|t| t.next_after(after)) {
+            Some(next_tick) => next_tick,
+            None => return false,
+        };
+        let timeout = next_tick.signed_duration_since(chrono::Utc::now());
+        if timeout <= chrono::Duration::zero() {
+            ctx.notify(WorkflowTick(next_tick));
+        } else {
+            log::debug!("Next run of workflow {} in {}", self.id, timeout);
+            ctx.notify_later(WorkflowTick(next_tick), timeout.to_std().unwrap());
+        }
+        true
+    }
+
+    fn save_run(&self) {
+        if let Some(run) = self.run.clone() {
+            let repo = self.repo.clone();
+            actix::spawn(async move {
+                if let Err(e) = repo.save_run(run).await {
+                    log::error!("error saving workflow run: {}", e);
+                }
+            });
+        }
+    }
+
+    fn start_run(&mut self, at: chrono::DateTime<chrono::Utc>, ctx: &mut Context<Self>) {
+        let workflow = match self.state.as_mut() {
+            Some(workflow) => workflow,
+            None => return,
+        };
+        workflow.runs += 1;
+        let run = new_run(workflow, self.repo.next_run_id().unwrap_or_default(), at);
+        log::info!("Run {} of workflow {} started", run.run, self.id);
+        self.run = Some(run);
+        self.advance(ctx);
+    }
+
+    /// Skips steps whose dependencies did not succeed, starts steps whose dependencies
+    /// all succeeded and finishes the run once no step is left.
+    fn advance(&mut self, ctx: &mut Context<Self>) {
+        let (workflow, run) = match (self.state.as_ref(), self.run.as_mut()) {
+            (Some(workflow), Some(run)) => (workflow, run),
+            _ => return,
+        };
+        let ready = ready_steps(workflow, run);
+        if is_done(run) {
+            self.finish_run(ctx);
+            return;
+        }
+        self.save_run();
+        for index in ready {
+            self.start_step(index, 1, ctx);
+        }
+    }
+
+    fn start_step(&mut self, index: usize, attempt: u32, ctx: &mut Context<Self>) {
+        let (workflow, run) = match (self.state.as_ref(), self.run.as_mut()) {
+            (Some(workflow), Some(run)) => (workflow, run),
+            _ => return,
+        };
+        let step = &workflow.steps[index];
+        let step_run = &mut run.steps[index];
+        step_run.attempts = attempt;
+        if step_run.started_at.is_none() {
+            step_run.started_at = Some(chrono::Utc::now());
+            if let Some(timeout_ms) = step.timeout_ms {
+                let run_id = run.id;
+                let hnd = ctx.run_later(
+                    std::time::Duration::from_millis(timeout_ms),
+                    move |act, ctx| act.time_out_step(run_id, index, timeout_ms, ctx),
+                );
+                self.deadlines.insert(index, hnd);
+            }
+        }
+
+        let schedule = step_schedule(workflow, step);
+        let run_id = run.id;
+        let vars = TemplateContext {
+            id: workflow.id.clone(),
+            tags: workflow.tags.clone().unwrap_or_default(),
+            scheduled_at: run.scheduled_at.to_rfc3339(),
+            fired_at: chrono::Utc::now().to_rfc3339(),
+            run: run.run,
+            attempt,
+            prev_run: workflow.last_run.map(|t| t.to_rfc3339()),
+            upstream: None,
+        };
+        let dispatcher = self.dispatcher.clone();
+        let f = async move {
+            let (evaluation, outcome) = if dispatcher.dry_run_forced() {
+                let evaluation = match dispatcher.dry_run(&schedule, &vars) {
+                    Ok(_) => Evaluation {
+                        succeeded: true,
+                        error: None,
+                        matches: None,
+                    },
+                    Err(e) => Evaluation {
+                        succeeded: false,
+                        error: Some(e.to_string()),
+                        matches: None,
+                    },
+                };
+                (evaluation, None)
+            } else {
+                match dispatcher.dispatch(&schedule, &vars).await {
+                    Ok(outcome) => (
+                        assertion::evaluate(schedule.success.as_ref(), &outcome),
+                        Some(outcome),
+                    ),
+                    Err(e) => (
+                        Evaluation {
+                            succeeded: false,
+                            error: Some(e.to_string()),
+                            matches: None,
+                        },
+                        None,
+                    ),
+                }
+            };
+            StepAttempt {
+                evaluation,
+                status_code: outcome.as_ref().map(|o| o.status),
+                latency_ms: outcome.as_ref().map(|o| o.latency.as_millis() as u64),
+                retry: dispatcher.retry_delays(&schedule.request),
+            }
+        };
+        let w = actix::fut::wrap_future::<_, Self>(f)
+            .map(move |result, act, ctx| act.step_finished(run_id, index, attempt, result, ctx));
+        let hnd = ctx.spawn(w);
+        self.attempts.insert(index, hnd);
+    }
+
+    /// Whether `index` is a running step of the current run `run_id`.
+    fn is_running(&self, run_id: u64, index: usize) -> bool {
+        self.run
+            .as_ref()
+            .map(|run| run.id == run_id && run.steps[index].status == StepStatus::Running)
+            .unwrap_or(false)
+    }
+
+    fn step_finished(
+        &mut self,
+        run_id: u64,
+        index: usize,
+        attempt: u32,
+        result: StepAttempt,
+        ctx: &mut Context<Self>,
+    ) {
+        if !self.is_running(run_id, index) {
+            return;
+        }
+        self.attempts.remove(&index);
+        let run = self.run.as_mut().unwrap();
+        let step = &mut run.steps[index];
+        if let Some(delay) = record_attempt(step, attempt, result) {
+            metrics()
+                .retries
+                .with_label_values(&["workflow_step"])
+                .inc();
+            log::warn!(
+                "Attempt {} of step {} of {} failed: {}, retrying in {}s",
+                attempt,
+                step.name,
+                self.id,
+                step.error.as_deref().unwrap_or_default(),
+                delay
+            );
+            let hnd = ctx.run_later(
+                std::time::Duration::from_secs(delay as u64),
+                move |act, ctx| {
+                    if act.is_running(run_id, index) {
+                        act.start_step(index, attempt + 1, ctx);
+                    }
+                },
+            );
+            self.attempts.insert(index, hnd);
+            self.save_run();
+            return;
+        }
+        if let Some(hnd) = self.deadlines.remove(&index) {
+            ctx.cancel_future(hnd);
+        }
+        self.advance(ctx);
+    }
+
+    fn time_out_step(
+        &mut self,
+        run_id: u64,
+        index: usize,
+        timeout_ms: u64,
+        ctx: &mut Context<Self>,
+    ) {
+        if !self.is_running(run_id, index) {
+            return;
+        }
+        self.deadlines.remove(&index);
+        if let Some(hnd) = self.attempts.remove(&index) {
+            ctx.cancel_future(hnd);
+        }
+        let step = &mut self.run.as_mut().unwrap().steps[index];
+        log::warn!("Step {} of {} timed out", step.name, self.id);
+        time_out(step, timeout_ms);
+        self.advance(ctx);
+    }
+
+    /// Stores the finished run and schedules the next one.
+    fn finish_run(&mut self, ctx: &mut Context<Self>) {
+        let mut run = match self.run.take() {
+            Some(run) => run,
+            None => return,
+        };
+        for (_, hnd) in self.attempts.drain().chain(self.deadlines.drain()) {
+            ctx.cancel_future(hnd);
+        }
+        if run.status == WorkflowRunStatus::Running {
+            run.status = outcome(&run);
+            run.finished_at = Some(chrono::Utc::now());
+        }
+        log::info!(
+            "Run {} of workflow {} finished: {:?}",
+            run.run,
+            self.id,
+            run.status
+        );
+        let at = run.scheduled_at;
+        let succeeded = run.status == WorkflowRunStatus::Succeeded;
+        let has_next = self.schedule_next(&at, ctx);
+        let repo = self.repo.clone();
+        let cluster = self.cluster.clone();
+        let workflow = self.state.as_mut().map(|workflow| {
+            workflow.last_run = Some(at);
+            workflow.updated_at = chrono::Utc::now();
+            if !has_next {
+                workflow.status = if succeeded {
+                    ScheduleStatus::Completed
+                } else {
+                    ScheduleStatus::Failed
+                };
+            }
+            workflow.clone()
+        });
+        actix::spawn(async move {
+            let finished = WorkflowRunFinished {
+                workflow_id: run.workflow_id.clone(),
+                run: run.run,
+                at,
+                status: workflow.as_ref().map(|w| w.status).unwrap_or_default(),
+            };
+            // runs are history of the node that ran them, the workflow is replicated
+            if let Err(e) = repo.save_run(run).await {
+                log::error!("error saving workflow run: {}", e);
+            }
+            let saved = match (cluster, workflow) {
+                (Some(cluster), Some(_)) => cluster
+                    .finish_workflow_run(finished)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                (None, Some(workflow)) => repo.save(workflow).await.map_err(|e| e.to_string()),
+                (_, None) => Ok(()),
+            };
+            if let Err(e) = saved {
+                log::error!("error saving workflow: {}", e);
+            }
+        });
+        if !has_next {
+            log::debug!("No next run for workflow {}, stopping", self.id);
+            ctx.stop();
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WorkflowTick(chrono::DateTime<chrono::Utc>);
+
+/// Cancels the run if it is the one in progress. Returns false otherwise.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CancelRun(pub u64);
+
+impl Actor for WorkflowActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("Starting workflow {}", self.id);
+        metrics().actors.with_label_values(&["workflow"]).inc();
+        let repo = self.repo.clone();
+        let id = self.id.clone();
+        let f = async move { repo.get::<WorkflowDocument>(id).await };
+        let w = actix::fut::wrap_future::<_, Self>(f).map(|res, act, ctx| match res {
+            Ok(Some(workflow)) => {
+                if !workflow.status.is_active() {
+                    log::debug!("Workflow {} is not active, stopping", act.id);
+                    ctx.stop();
+                    return;
+                }
+                match Ticker::parse(
+                    workflow.schedule.as_deref(),
+                    workflow.schedule_at.as_deref(),
+                ) {
+                    Ok(ticker) => {
+                        let after = workflow.last_run.unwrap_or(chrono::Utc::now());
+                        act.state = Some(workflow);
+                        act.ticker = Some(ticker);
+                        if !act.schedule_next(&after, ctx) {
+                            log::debug!("No next run for workflow {}, stopping", act.id);
+                            ctx.stop();
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Error while parsing workflow {}: {}", act.id, e);
+                        ctx.stop();
+                    }
+                }
+            }
+            Ok(None) => {
+                log::error!("Workflow {} not found", act.id);
+                ctx.stop();
+            }
+            Err(e) => {
+                log::error!("error getting workflow {}: {}", act.id, e);
+                ctx.stop();
+            }
+        });
+        ctx.wait(w);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        metrics().actors.with_label_values(&["workflow"]).dec();
+    }
+}
+
+impl Handler<WorkflowTick> for WorkflowActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: WorkflowTick, ctx: &mut Self::Context) -> Self::Result {
+        match self.cluster.clone() {
+            Some(cluster) => self.claim(cluster, msg.0, ctx),
+            None => self.start_run(msg.0, ctx),
+        }
+    }
+}
+
+impl Handler<Stop> for WorkflowActor {
+    type Result = ();
+
+    /// Cancels the run in progress, e.g. when the shard of the workflow moved to another member.
+    fn handle(&mut self, _msg: Stop, ctx: &mut Self::Context) -> Self::Result {
+        log::info!("Stopping workflow {}", self.id);
+        if let Some(run) = self.run.as_mut() {
+            cancel_run(run);
+            self.save_run();
+        }
+        ctx.stop();
+    }
+}
+
+impl Handler<CancelRun> for WorkflowActor {
+    type Result = bool;
+
+    fn handle(&mut self, msg: CancelRun, ctx: &mut Self::Context) -> Self::Result {
+        match self.run.as_mut() {
+            Some(run) if run.id == msg.0 => {
+                log::info!("Cancelling run {} of workflow {}", run.run, self.id);
+                cancel_run(run);
+                self.finish_run(ctx);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Workflow with steps given as `(name, dependencies)`.
+    fn workflow(steps: &[(&str, &[&str])]) -> WorkflowDocument {
+        let steps: Vec<serde_json::Value> = steps
+            .iter()
+            .map(|(name, depends_on)| {
+                serde_json::json!({
+                    "name": name,
+                    "request": {"url": format!("http://localhost/{}", name), "method": "GET"},
+                    "depends_on": depends_on,
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "id": "w",
+            "schedule_at": "2030-01-01T00:00:00Z",
+            "steps": steps,
+            "created_at": "2030-01-01T00:00:00Z",
+            "updated_at": "2030-01-01T00:00:00Z",
+            "runs": 1,
+        }))
+        .unwrap()
+    }
+
+    fn attempt(succeeded: bool, retry: &[u32]) -> StepAttempt {
+        StepAttempt {
+            evaluation: Evaluation {
+                succeeded,
+                error: (!succeeded).then(|| "status 500".to_string()),
+                matches: None,
+            },
+            status_code: Some(if succeeded { 200 } else { 500 }),
+            latency_ms: Some(3),
+            retry: retry.to_vec(),
+        }
+    }
+
+    fn statuses(run: &WorkflowRunDocument) -> Vec<StepStatus> {
+        run.steps.iter().map(|s| s.status).collect()
+    }
+
+    /// Finishes a running step with a single attempt.
+    fn finish(run: &mut WorkflowRunDocument, index: usize, succeeded: bool) {
+        assert_eq!(run.steps[index].status, StepStatus::Running);
+        assert_eq!(
+            record_attempt(&mut run.steps[index], 1, attempt(succeeded, &[])),
+            None
+        );
+    }
+
+    #[test]
+    fn fans_out_and_in_along_dependencies() {
+        let workflow = workflow(&[
+            ("fetch", &[]),
+            ("left", &["fetch"]),
+            ("right", &["fetch"]),
+            ("join", &["left", "right"]),
+        ]);
+        let mut run = new_run(&workflow, 7, chrono::Utc::now());
+        assert_eq!(run.run, 1);
+        assert_eq!(ready_steps(&workflow, &mut run), [0]);
+
+        finish(&mut run, 0, true);
+        assert_eq!(ready_steps(&workflow, &mut run), [1, 2]);
+        // the join waits for both branches
+        finish(&mut run, 1, true);
+        assert!(ready_steps(&workflow, &mut run).is_empty());
+        assert_eq!(run.steps[3].status, StepStatus::Pending);
+        finish(&mut run, 2, true);
+        assert_eq!(ready_steps(&workflow, &mut run), [3]);
+        assert!(!is_done(&run));
+
+        finish(&mut run, 3, true);
+        assert!(ready_steps(&workflow, &mut run).is_empty());
+        assert!(is_done(&run));
+        assert_eq!(outcome(&run), WorkflowRunStatus::Succeeded);
+    }
+
+    #[test]
+    fn skips_the_steps_after_a_failed_one() {
+        let workflow = workflow(&[
+            ("fetch", &[]),
+            ("other", &[]),
+            ("parse", &["fetch"]),
+            ("store", &["parse", "other"]),
+        ]);
+        let mut run = new_run(&workflow, 1, chrono::Utc::now());
+        assert_eq!(ready_steps(&workflow, &mut run), [0, 1]);
+
+        finish(&mut run, 0, false);
+        assert!(ready_steps(&workflow, &mut run).is_empty());
+        use StepStatus::*;
+        assert_eq!(statuses(&run), [Failed, Running, Skipped, Skipped]);
+        assert!(run.steps[3].finished_at.is_some());
+        assert!(!is_done(&run));
+
+        finish(&mut run, 1, true);
+        assert!(is_done(&run));
+        assert_eq!(outcome(&run), WorkflowRunStatus::Failed);
+    }
+
+    #[test]
+    fn retries_failed_attempts_while_delays_last() {
+        let workflow = workflow(&[("fetch", &[])]);
+        let mut run = new_run(&workflow, 1, chrono::Utc::now());
+        ready_steps(&workflow, &mut run);
+        let step = &mut run.steps[0];
+
+        assert_eq!(record_attempt(step, 1, attempt(false, &[1, 5])), Some(1));
+        assert_eq!(step.status, StepStatus::Running);
+        assert_eq!(step.error.as_deref(), Some("status 500"));
+        assert_eq!(record_attempt(step, 2, attempt(false, &[1, 5])), Some(5));
+        assert_eq!(record_attempt(step, 3, attempt(false, &[1, 5])), None);
+        assert_eq!(step.status, StepStatus::Failed);
+        assert_eq!(step.status_code, Some(500));
+        assert!(step.finished_at.is_some());
+
+        // a later success ends the retries
+        let mut run = new_run(&workflow, 2, chrono::Utc::now());
+        ready_steps(&workflow, &mut run);
+        let step = &mut run.steps[0];
+        assert_eq!(record_attempt(step, 1, attempt(false, &[1])), Some(1));
+        assert_eq!(record_attempt(step, 2, attempt(true, &[1])), None);
+        assert_eq!(step.status, StepStatus::Succeeded);
+        assert_eq!(step.error, None);
+    }
+
+    #[test]
+    fn fails_steps_timing_out() {
+        let workflow = workflow(&[("fetch", &[]), ("parse", &["fetch"])]);
+        let mut run = new_run(&workflow, 1, chrono::Utc::now());
+        ready_steps(&workflow, &mut run);
+
+        time_out(&mut run.steps[0], 250);
+        assert_eq!(run.steps[0].status, StepStatus::Failed);
+        assert_eq!(
+            run.steps[0].error.as_deref(),
+            Some("step timed out after 250ms")
+        );
+        ready_steps(&workflow, &mut run);
+        assert_eq!(run.steps[1].status, StepStatus::Skipped);
+        assert_eq!(outcome(&run), WorkflowRunStatus::Failed);
+    }
+
+    #[test]
+    fn cancels_unfinished_steps() {
+        let workflow = workflow(&[("fetch", &[]), ("slow", &[]), ("parse", &["fetch"])]);
+        let mut run = new_run(&workflow, 1, chrono::Utc::now());
+        ready_steps(&workflow, &mut run);
+        finish(&mut run, 0, true);
+
+        cancel_run(&mut run);
+        use StepStatus::*;
+        assert_eq!(statuses(&run), [Succeeded, Cancelled, Cancelled]);
+        assert_eq!(run.status, WorkflowRunStatus::Cancelled);
+        assert!(run.finished_at.is_some());
+        assert!(is_done(&run));
+        // nothing starts after a cancellation
+        assert!(ready_steps(&workflow, &mut run).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_graphs() {
+        let dispatcher = Dispatcher::from_env();
+        let create = |steps: serde_json::Value| -> CreateWorkflowDto {
+            serde_json::from_value(serde_json::json!({
+                "id": "w",
+                "schedule": "0 * * * * *",
+                "steps": steps,
+            }))
+            .unwrap()
+        };
+        let request = serde_json::json!({"url": "http://localhost/", "method": "GET"});
+        let valid = create(serde_json::json!([
+            {"name": "a", "request": request},
+            {"name": "b", "request": request, "depends_on": ["a"]},
+        ]));
+        assert_eq!(check(&valid, &dispatcher), Ok(()));
+
+        let empty = create(serde_json::json!([]));
+        assert_eq!(
+            check(&empty, &dispatcher).unwrap_err(),
+            "workflow has no steps"
+        );
+        let unknown = create(serde_json::json!([
+            {"name": "a", "request": request, "depends_on": ["z"]},
+        ]));
+        assert_eq!(
+            check(&unknown, &dispatcher).unwrap_err(),
+            "step a depends on unknown step z"
+        );
+        let cycle = create(serde_json::json!([
+            {"name": "a", "request": request},
+            {"name": "b", "request": request, "depends_on": ["a", "c"]},
+            {"name": "c", "request": request, "depends_on": ["b"]},
+        ]));
+        assert_eq!(
+            check(&cycle, &dispatcher).unwrap_err(),
+            "steps b, c form a cycle"
+        );
+    }
+}